@@ -0,0 +1,475 @@
+use crate::config::{self, BasicAuthRule};
+use crate::{log, web};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use ring::digest;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, Response};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Verified credentials remembered per user file, so pages with many
+/// assets don't pay for a slow hash on every request
+const VERIFIED_CACHE_SIZE: usize = 1024;
+
+/// Parsed user files, re-read when their modification time or size changes
+static USER_FILES: Mutex<Option<HashMap<PathBuf, Arc<UserFile>>>> = Mutex::new(None);
+
+/// A user's password hash and roles
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub password: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// Users read from an htpasswd or TOML file
+#[derive(Debug, Default)]
+pub struct UserStore {
+    users: HashMap<String, User>,
+}
+
+/// Layout of a TOML user file
+#[derive(Debug, Deserialize)]
+struct UserFileToml {
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+impl UserStore {
+    /// Parse htpasswd `user:hash` lines, skipping blank lines and `#`
+    /// comments. An optional third field lists the user's roles, separated by
+    /// commas. Returns a warning for each entry that can't be used.
+    pub fn parse_htpasswd(contents: &str) -> (Self, Vec<String>) {
+        let mut users = Vec::new();
+        let mut warnings = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ':');
+            match (fields.next(), fields.next()) {
+                (Some(name), Some(password)) => users.push(User {
+                    name: name.to_string(),
+                    password: password.to_string(),
+                    roles: fields
+                        .next()
+                        .map(|roles| {
+                            roles
+                                .split(',')
+                                .map(str::trim)
+                                .filter(|role| !role.is_empty())
+                                .map(str::to_string)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                }),
+                _ => warnings.push(format!("line {}: expected user:hash", number + 1)),
+            }
+        }
+        Self::from_users(users, warnings)
+    }
+
+    /// Parse `[[users]]` tables with `name`, `password` (a hash) and `roles`
+    pub fn parse_toml(contents: &str) -> Result<(Self, Vec<String>), String> {
+        let file: UserFileToml = toml::from_str(contents).map_err(|e| e.to_string())?;
+        Ok(Self::from_users(file.users, Vec::new()))
+    }
+
+    fn from_users(entries: Vec<User>, mut warnings: Vec<String>) -> (Self, Vec<String>) {
+        let mut users = HashMap::new();
+        for user in entries {
+            if HashKind::of(&user.password).is_none() {
+                warnings.push(format!(
+                    "unsupported hash for user '{}' (use bcrypt, sha256-crypt, sha512-crypt or argon2)",
+                    user.name
+                ));
+                continue;
+            }
+            users.insert(user.name.clone(), user);
+        }
+        (UserStore { users }, warnings)
+    }
+
+    /// Check a user's password against their stored hash
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        self.users
+            .get(user)
+            .is_some_and(|user| verify_hash(&user.password, password))
+    }
+
+    /// A known user's roles
+    pub fn roles(&self, user: &str) -> Option<&[String]> {
+        self.users.get(user).map(|user| user.roles.as_slice())
+    }
+}
+
+/// Password hash formats accepted in user files
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HashKind {
+    Bcrypt,
+    Sha256Crypt,
+    Sha512Crypt,
+    Argon2,
+}
+
+impl HashKind {
+    fn of(hash: &str) -> Option<Self> {
+        if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            Some(HashKind::Bcrypt)
+        } else if hash.starts_with("$5$") {
+            Some(HashKind::Sha256Crypt)
+        } else if hash.starts_with("$6$") {
+            Some(HashKind::Sha512Crypt)
+        } else if hash.starts_with("$argon2") {
+            Some(HashKind::Argon2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Check a password against a bcrypt, sha256-crypt, sha512-crypt or argon2 hash
+pub fn verify_hash(hash: &str, password: &str) -> bool {
+    match HashKind::of(hash) {
+        Some(HashKind::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(HashKind::Sha256Crypt) => sha_crypt::sha256_check(password, hash).is_ok(),
+        Some(HashKind::Sha512Crypt) => sha_crypt::sha512_check(password, hash).is_ok(),
+        Some(HashKind::Argon2) => PasswordHash::new(hash).is_ok_and(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        }),
+        None => false,
+    }
+}
+
+/// A user file as last read from disk
+pub struct UserFile {
+    modified: Option<SystemTime>,
+    len: u64,
+    store: UserStore,
+    /// Digests of credentials that have already been verified
+    verified: Mutex<HashSet<Vec<u8>>>,
+}
+
+impl UserFile {
+    /// Check a user's password, remembering credentials that were correct
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let key = digest::digest(&digest::SHA256, format!("{}:{}", user, password).as_bytes())
+            .as_ref()
+            .to_vec();
+        if self.verified.lock().unwrap().contains(&key) {
+            return true;
+        }
+        if !self.store.verify(user, password) {
+            return false;
+        }
+
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= VERIFIED_CACHE_SIZE {
+            verified.clear();
+        }
+        verified.insert(key);
+        true
+    }
+
+    /// Check a password on a blocking thread, since hashes are slow on purpose
+    pub async fn verify_blocking(self: Arc<Self>, user: String, password: String) -> bool {
+        rocket::tokio::task::spawn_blocking(move || self.verify(&user, &password))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// A known user's roles
+    pub fn roles(&self, user: &str) -> Option<&[String]> {
+        self.store.roles(user)
+    }
+}
+
+/// The user file at `path`, re-read if it changed since last use. Files
+/// ending in `.toml` hold `[[users]]` tables; anything else is htpasswd.
+pub fn load_users(path: &Path) -> Result<Arc<UserFile>, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let modified = metadata.modified().ok();
+
+    let mut files = USER_FILES.lock().unwrap();
+    let files = files.get_or_insert_with(HashMap::new);
+    if let Some(file) = files.get(path)
+        && file.modified == modified
+        && file.len == metadata.len()
+    {
+        return Ok(Arc::clone(file));
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (store, warnings) = if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+    {
+        UserStore::parse_toml(&contents).map_err(|e| format!("{}: {}", path.display(), e))?
+    } else {
+        UserStore::parse_htpasswd(&contents)
+    };
+    for warning in warnings {
+        log::log_warning(&format!("User file {} {}", path.display(), warning));
+    }
+    let verb = if files.contains_key(path) {
+        "Reloaded"
+    } else {
+        "Loaded"
+    };
+    log::log_barleywine(&format!(
+        "{} user file {} with {} users",
+        verb,
+        path.display(),
+        store.users.len()
+    ));
+
+    let file = Arc::new(UserFile {
+        modified,
+        len: metadata.len(),
+        store,
+        verified: Mutex::new(HashSet::new()),
+    });
+    files.insert(path.to_path_buf(), Arc::clone(&file));
+    Ok(file)
+}
+
+/// How a refused request should ask for credentials, set by the guards
+/// for the fairing
+#[derive(Debug, Clone, Default)]
+pub enum Challenge {
+    #[default]
+    None,
+    /// Answer with a Basic authentication challenge for the realm
+    Basic(String),
+    /// Send the browser to the login page at this location
+    Login(String),
+    /// Permanently redirect to this location, such as the HTTPS page
+    Redirect(String),
+}
+
+/// Adds the `WWW-Authenticate` challenge to refused requests and sends
+/// visitors without a session to the login page
+pub struct AuthChallenge;
+
+#[rocket::async_trait]
+impl Fairing for AuthChallenge {
+    fn info(&self) -> Info {
+        Info {
+            name: "Authentication Challenge",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let challenge = request.local_cache(Challenge::default);
+        match challenge {
+            Challenge::None => {}
+            Challenge::Basic(realm) => {
+                response.set_header(Header::new(
+                    "WWW-Authenticate",
+                    format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm),
+                ));
+            }
+            Challenge::Login(location) | Challenge::Redirect(location) => {
+                let status = match challenge {
+                    Challenge::Login(_) => Status::SeeOther,
+                    _ => Status::PermanentRedirect,
+                };
+                response.set_status(status);
+                response.set_header(Header::new("Location", location.clone()));
+                response.remove_header("Content-Type");
+                response.set_sized_body(0, std::io::Cursor::new(""));
+            }
+        }
+    }
+}
+
+/// The user authenticated with HTTP Basic credentials, when the path
+/// requires them. Requests to unprotected paths always succeed with `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicAuth(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasicAuth {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        let path = request.uri().path();
+        let Some(rule) = find_rule(&config.auth.basic, path.as_str()) else {
+            return Outcome::Success(BasicAuth(None));
+        };
+
+        let result = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(parse_credentials)
+        {
+            None => Err("no credentials presented".to_string()),
+            Some((user, password)) => match load_users(&rule.htpasswd) {
+                Err(e) => {
+                    log::log_error(&format!("Could not read user file {}", e));
+                    Err(format!("user file unavailable for user '{}'", user))
+                }
+                Ok(file) => {
+                    if file.verify_blocking(user.clone(), password).await {
+                        Ok(user)
+                    } else {
+                        Err(format!("invalid credentials for user '{}'", user))
+                    }
+                }
+            },
+        };
+
+        match result {
+            Ok(user) => Outcome::Success(BasicAuth(Some(user))),
+            Err(reason) => {
+                // A browser's first request never carries credentials, so
+                // only failed attempts are worth a warning
+                if request.headers().contains("Authorization") {
+                    log::log_warning(&format!(
+                        "Basic authentication failed for {} from {}: {}",
+                        path,
+                        request
+                            .remote()
+                            .map(|addr| addr.to_string())
+                            .unwrap_or_else(|| "unknown".to_string()),
+                        reason
+                    ));
+                }
+                request.local_cache(|| Challenge::Basic(rule.realm.clone()));
+                web::log_denied(request, Status::Unauthorized, None);
+                Outcome::Error((Status::Unauthorized, reason))
+            }
+        }
+    }
+}
+
+/// The rule for the longest configured prefix covering a request path, which
+/// is normalized first so encoded spellings of a prefix are covered too
+pub fn find_rule<'a>(rules: &'a [BasicAuthRule], path: &str) -> Option<&'a BasicAuthRule> {
+    let path = config::normalize_path(path);
+    rules
+        .iter()
+        .filter(|rule| config::path_has_prefix(&path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.trim_end_matches('/').len())
+}
+
+/// Decode an `Authorization: Basic ...` header into a user and password
+pub fn parse_credentials(header: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::PasswordHasher;
+    use argon2::password_hash::SaltString;
+
+    #[test]
+    fn test_parse_credentials() {
+        // "alice:open:sesame"; only the first colon separates the password
+        assert_eq!(
+            parse_credentials("Basic YWxpY2U6b3BlbjpzZXNhbWU="),
+            Some(("alice".to_string(), "open:sesame".to_string()))
+        );
+        assert_eq!(parse_credentials("Bearer YWxpY2U6b3Blbg=="), None);
+        assert_eq!(parse_credentials("Basic not-base64!"), None);
+    }
+
+    #[test]
+    fn test_find_rule_normalizes_path() {
+        let rules = vec![BasicAuthRule {
+            prefix: "/internal".to_string(),
+            realm: "Internal".to_string(),
+            htpasswd: PathBuf::from("users.htpasswd"),
+        }];
+        assert!(find_rule(&rules, "/internal/secret.txt").is_some());
+        assert!(find_rule(&rules, "/%69nternal/secret.txt").is_some());
+        assert!(find_rule(&rules, "//internal/secret.txt").is_some());
+        assert!(find_rule(&rules, "/public/../internal/secret.txt").is_some());
+        assert!(find_rule(&rules, "/internals.txt").is_none());
+    }
+
+    #[test]
+    fn test_verify_hash_formats() {
+        let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+        assert!(verify_hash(&bcrypt_hash, "secret"));
+        assert!(!verify_hash(&bcrypt_hash, "wrong"));
+
+        // The format `htpasswd -2` writes
+        let sha256_hash =
+            sha_crypt::sha256_simple("secret", &sha_crypt::Sha256Params::new(5000).unwrap())
+                .unwrap();
+        assert!(verify_hash(&sha256_hash, "secret"));
+        assert!(!verify_hash(&sha256_hash, "wrong"));
+
+        let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap();
+        let argon2_hash = Argon2::default()
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string();
+        assert!(verify_hash(&argon2_hash, "secret"));
+        assert!(!verify_hash(&argon2_hash, "wrong"));
+
+        // Apache MD5 hashes aren't supported
+        assert!(!verify_hash("$apr1$salt$hash", "secret"));
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+        let contents = format!(
+            "# editors\nalice:{}:editor, admin\n\nbob:$apr1$xyz$abc\nbroken line\n",
+            bcrypt_hash
+        );
+        let (store, warnings) = UserStore::parse_htpasswd(&contents);
+
+        assert!(store.verify("alice", "secret"));
+        assert_eq!(
+            store.roles("alice"),
+            Some(&["editor".to_string(), "admin".to_string()][..])
+        );
+        assert!(!store.verify("bob", "secret"));
+        assert!(!store.verify("carol", "secret"));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("line 5"));
+        assert!(warnings[1].contains("bob"));
+    }
+
+    #[test]
+    fn test_parse_toml_users() {
+        let contents = format!(
+            "[[users]]\nname = \"alice\"\npassword = \"{}\"\nroles = [\"editor\"]\n",
+            bcrypt::hash("secret", 4).unwrap()
+        );
+        let (store, warnings) = UserStore::parse_toml(&contents).unwrap();
+
+        assert!(warnings.is_empty());
+        assert!(store.verify("alice", "secret"));
+        assert_eq!(store.roles("alice"), Some(&["editor".to_string()][..]));
+        assert!(UserStore::parse_toml("[[users]]\nname = 1").is_err());
+    }
+}
@@ -25,6 +25,26 @@ pub struct Cli {
     /// Specify a log directory
     #[structopt(long, parse(from_os_str))]
     pub log: Option<PathBuf>,
+
+    /// Serve HTTPS with a generated development certificate and local CA
+    #[structopt(long)]
+    pub dev_tls: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Print a signed, expiring URL for a file under a protected prefix
+    Sign {
+        /// URL path of the file, e.g. /private/report.pdf
+        path: String,
+
+        /// How long the link stays valid, e.g. 90m, 24h or 7d
+        #[structopt(long, default_value = "24h")]
+        ttl: String,
+    },
 }
 
 impl Cli {
@@ -45,13 +65,13 @@ impl Cli {
         }
 
         // Validate config file exists if specified
-        if let Some(ref config_path) = self.config {
-            if !config_path.exists() {
-                return Err(format!(
-                    "Configuration file '{}' does not exist",
-                    config_path.display()
-                ));
-            }
+        if let Some(ref config_path) = self.config
+            && !config_path.exists()
+        {
+            return Err(format!(
+                "Configuration file '{}' does not exist",
+                config_path.display()
+            ));
         }
 
         // Validate log directory exists if specified
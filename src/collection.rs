@@ -0,0 +1,425 @@
+use crate::config::Config;
+use crate::frontmatter;
+use crate::render::{self, escape_html};
+use crate::{symlinks, web};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Entries per listing page when neither the configuration nor the index
+/// says otherwise
+pub const DEFAULT_PER_PAGE: usize = 10;
+
+/// Reading speed behind the reading time estimate
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Longest summary taken from a page's first paragraph, in characters
+const SUMMARY_LENGTH: usize = 200;
+
+/// A directory whose index lists its pages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collection {
+    /// Directory on disk
+    pub dir: PathBuf,
+    /// URL path of the directory, ending in `/`
+    pub url: String,
+    /// Entries per listing page
+    pub per_page: usize,
+}
+
+impl Collection {
+    /// URL of a listing page; the first is the directory itself
+    pub fn page_url(&self, page: usize) -> String {
+        if page <= 1 {
+            self.url.clone()
+        } else {
+            format!("{}page/{}/", self.url, page)
+        }
+    }
+}
+
+/// A page listed in a collection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    /// URL path of the page
+    pub url: String,
+    /// Markdown file on disk
+    pub path: PathBuf,
+    pub title: String,
+    pub date: Option<DateTime<Utc>>,
+    pub summary: String,
+    pub author: Option<String>,
+    /// Estimated reading time in minutes
+    pub reading_minutes: usize,
+}
+
+/// URL path of a directory under the webroot, ending in `/`
+fn dir_url(config: &Config, dir: &Path) -> Option<String> {
+    let relative = dir.strip_prefix(&config.content.webroot).ok()?;
+    let mut url = String::from("/");
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            return None;
+        };
+        url.push_str(&name.to_string_lossy());
+        url.push('/');
+    }
+    Some(url)
+}
+
+/// The collection a directory under the webroot is, if it is one: declared
+/// in `[[content.collections]]`, or by `collection: true` in the front
+/// matter of its markdown index
+pub fn find(config: &Config, dir: &Path) -> Option<Collection> {
+    let url = dir_url(config, dir)?;
+    let declared = config
+        .content
+        .collections
+        .iter()
+        .find(|collection| collection.path.trim_end_matches('/') == url.trim_end_matches('/'));
+    if let Some(declared) = declared {
+        return Some(Collection {
+            dir: dir.to_path_buf(),
+            url,
+            per_page: declared.per_page,
+        });
+    }
+
+    let index = config
+        .content
+        .index_files
+        .iter()
+        .map(|index| dir.join(index))
+        .find(|index| index.is_file())?;
+    if index.extension().is_none_or(|extension| extension != "md") {
+        return None;
+    }
+    let source = web::read_to_string(config, &index).ok()?;
+    let (front_matter, _) = frontmatter::split(&source);
+    front_matter.collection.then(|| Collection {
+        dir: dir.to_path_buf(),
+        url,
+        per_page: front_matter.per_page.unwrap_or(DEFAULT_PER_PAGE).max(1),
+    })
+}
+
+/// The collection and listing page for a request such as `blog/page/2`
+pub fn page_request(config: &Config, file: &Path) -> Option<(Collection, usize)> {
+    let mut components: Vec<&str> = file
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<_>>()?;
+    let page = components.pop()?.parse::<usize>().ok()?;
+    if components.pop()? != "page" || page == 0 {
+        return None;
+    }
+    let dir = components
+        .iter()
+        .fold(config.content.webroot.clone(), |dir, name| dir.join(name));
+    if !dir.is_dir() {
+        return None;
+    }
+    find(config, &dir).map(|collection| (collection, page))
+}
+
+/// The published pages of a collection, newest first. Markdown files and
+/// subdirectories with a markdown index are listed; drafts, index files and
+/// anything the extension or symlink rules refuse are not.
+pub fn entries(config: &Config, collection: &Collection) -> Vec<Entry> {
+    let Ok(children) = fs::read_dir(&collection.dir) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<Entry> = children
+        .flatten()
+        .filter_map(|child| {
+            let name = child.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                return None;
+            }
+            let path = child.path();
+            let (path, url) = if path.is_dir() {
+                let index = config
+                    .content
+                    .index_files
+                    .iter()
+                    .filter(|index| index.ends_with(".md"))
+                    .map(|index| path.join(index))
+                    .find(|index| index.is_file())?;
+                (index, format!("{}{}/", collection.url, name))
+            } else if name.ends_with(".md") && !config.content.index_files.contains(&name) {
+                let url = format!("{}{}", collection.url, name);
+                (path, url)
+            } else {
+                return None;
+            };
+            entry(config, path, url)
+        })
+        .collect();
+
+    // Newest first, undated pages last, ties by title
+    entries.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.title.cmp(&b.title)));
+    entries
+}
+
+fn entry(config: &Config, path: PathBuf, url: String) -> Option<Entry> {
+    if web::blocked_component(config, &path).is_some()
+        || symlinks::check(&config.content.webroot, &path, config.security.symlinks).is_err()
+    {
+        return None;
+    }
+    let source = web::read_to_string(config, &path).ok()?;
+    let (front_matter, body) = frontmatter::split(&source);
+    if front_matter.draft {
+        return None;
+    }
+
+    let title = front_matter
+        .title
+        .clone()
+        .or_else(|| render::title(body))
+        .unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+    let summary = front_matter
+        .summary
+        .clone()
+        .unwrap_or_else(|| first_paragraph(body));
+    Some(Entry {
+        url,
+        date: front_matter.date_time(),
+        author: front_matter.author,
+        reading_minutes: body
+            .split_whitespace()
+            .count()
+            .div_ceil(WORDS_PER_MINUTE)
+            .max(1),
+        path,
+        title,
+        summary,
+    })
+}
+
+/// The plain text of a page's first paragraph of prose, shortened to about
+/// `SUMMARY_LENGTH` characters
+pub fn first_paragraph(body: &str) -> String {
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in body.lines() {
+        let line = line.trim();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                break;
+            }
+            continue;
+        }
+        // Headings, bylines, lists, quotes, tables and raw HTML aren't prose
+        let emphasis_only = (line.starts_with('*') && line.ends_with('*'))
+            || (line.starts_with('_') && line.ends_with('_'));
+        let skipped = emphasis_only
+            || line.starts_with(['#', '<', '>', '|', '-', '+', '='])
+            || line.starts_with("* ")
+            || line.split_once(". ").is_some_and(|(number, _)| {
+                !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+            });
+        if paragraph.is_empty() && skipped {
+            continue;
+        }
+        paragraph.push(line);
+    }
+
+    let text = plain_text(&markdown::to_html(&paragraph.join("\n")));
+    if text.chars().count() <= SUMMARY_LENGTH {
+        return text;
+    }
+    let cut: String = text.chars().take(SUMMARY_LENGTH).collect();
+    let cut = cut
+        .rsplit_once(' ')
+        .map_or(cut.as_str(), |(start, _)| start);
+    format!("{}…", cut.trim_end_matches([',', ';', ':', '.', ' ']))
+}
+
+/// Rendered HTML reduced to its text
+fn plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The HTML listing for one page of a collection, or `None` past its last
+/// page
+pub fn listing(collection: &Collection, entries: &[Entry], page: usize) -> Option<String> {
+    let pages = entries.len().div_ceil(collection.per_page).max(1);
+    if page == 0 || page > pages {
+        return None;
+    }
+
+    let mut html = String::from("<section class=\"collection\">\n");
+    let start = (page - 1) * collection.per_page;
+    for entry in entries.iter().skip(start).take(collection.per_page) {
+        html.push_str(&format!(
+            "<article class=\"collection-entry\">\n<h2><a href=\"{}\">{}</a></h2>\n<p class=\"collection-meta\">",
+            escape_html(&entry.url),
+            escape_html(&entry.title)
+        ));
+        if let Some(date) = entry.date {
+            html.push_str(&format!(
+                "<time datetime=\"{}\">{}</time> · ",
+                date.format("%Y-%m-%d"),
+                date.format("%B %-d, %Y")
+            ));
+        }
+        html.push_str(&format!("{} min read</p>\n", entry.reading_minutes));
+        if !entry.summary.is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", escape_html(&entry.summary)));
+        }
+        html.push_str("</article>\n");
+    }
+    if entries.is_empty() {
+        html.push_str("<p>Nothing has been published here yet.</p>\n");
+    }
+
+    if pages > 1 {
+        html.push_str("<nav class=\"pagination\">\n");
+        if page > 1 {
+            html.push_str(&format!(
+                "<a href=\"{}\" rel=\"prev\">← Newer</a>\n",
+                escape_html(&collection.page_url(page - 1))
+            ));
+        }
+        html.push_str(&format!("<span>Page {} of {}</span>\n", page, pages));
+        if page < pages {
+            html.push_str(&format!(
+                "<a href=\"{}\" rel=\"next\">Older →</a>\n",
+                escape_html(&collection.page_url(page + 1))
+            ));
+        }
+        html.push_str("</nav>\n");
+    }
+    html.push_str("</section>\n");
+    Some(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CollectionConfig;
+    use std::fs;
+
+    fn webroot(name: &str) -> PathBuf {
+        let webroot = std::env::temp_dir().join(format!(
+            "barleywine_collection_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&webroot);
+        fs::create_dir_all(webroot.join("blog/series")).unwrap();
+        webroot
+    }
+
+    #[test]
+    fn test_entries_and_pagination() {
+        let root = webroot("entries");
+        let blog = root.join("blog");
+        fs::write(
+            blog.join("index.md"),
+            "---\ncollection: true\nper_page: 2\n---\n# Blog\n",
+        )
+        .unwrap();
+        fs::write(
+            blog.join("old.md"),
+            "---\ndate: 2023-05-01\n---\n# Old news\n\n*A byline*\n\nFirst **real** paragraph.\n\nSecond.\n",
+        )
+        .unwrap();
+        fs::write(
+            blog.join("new.md"),
+            "---\ntitle: Fresh\ndate: 2024-02-01\nsummary: Hand written\n---\nBody\n",
+        )
+        .unwrap();
+        fs::write(blog.join("draft.md"), "---\ndraft: true\n---\n# Secret\n").unwrap();
+        fs::write(blog.join("series/index.md"), "# A series\n\nParts.\n").unwrap();
+        fs::write(blog.join("notes.txt"), "not a page").unwrap();
+
+        let mut config = Config::default();
+        config.content.webroot = root.clone();
+        let collection = find(&config, &blog).unwrap();
+        assert_eq!(collection.url, "/blog/");
+        assert_eq!(collection.per_page, 2);
+
+        let entries = entries(&config, &collection);
+        let titles: Vec<&str> = entries.iter().map(|entry| entry.title.as_str()).collect();
+        assert_eq!(titles, vec!["Fresh", "Old news", "A series"]);
+        assert_eq!(entries[0].summary, "Hand written");
+        assert_eq!(entries[1].summary, "First real paragraph.");
+        assert_eq!(entries[2].url, "/blog/series/");
+
+        let first = listing(&collection, &entries, 1).unwrap();
+        assert!(first.contains("<a href=\"/blog/new.md\">Fresh</a>"));
+        assert!(first.contains("<time datetime=\"2024-02-01\">February 1, 2024</time>"));
+        assert!(first.contains("<a href=\"/blog/page/2/\" rel=\"next\">"));
+        let second = listing(&collection, &entries, 2).unwrap();
+        assert!(second.contains("A series"));
+        assert!(second.contains("<a href=\"/blog/\" rel=\"prev\">"));
+        assert!(listing(&collection, &entries, 3).is_none());
+
+        let (paged, page) = page_request(&config, Path::new("blog/page/2")).unwrap();
+        assert_eq!((paged.url.as_str(), page), ("/blog/", 2));
+        assert!(page_request(&config, Path::new("blog/page/0")).is_none());
+        assert!(page_request(&config, Path::new("blog/series/page/2")).is_none());
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_collection_from_config() {
+        let root = webroot("config");
+        let mut config = Config::default();
+        config.content.webroot = root.clone();
+        assert!(find(&config, &root.join("blog")).is_none());
+
+        config.content.collections.push(CollectionConfig {
+            path: "/blog".to_string(),
+            per_page: 5,
+        });
+        let collection = find(&config, &root.join("blog")).unwrap();
+        assert_eq!(collection.per_page, 5);
+        assert!(entries(&config, &collection).is_empty());
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_first_paragraph() {
+        assert_eq!(
+            first_paragraph("# Title\n\n- a list\n\nSee [the docs](/docs) & `code`.\nMore.\n"),
+            "See the docs & code. More."
+        );
+        let long = "word ".repeat(100);
+        let summary = first_paragraph(&long);
+        assert!(summary.ends_with("word…"));
+        assert!(summary.chars().count() <= SUMMARY_LENGTH + 1);
+    }
+}
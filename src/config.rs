@@ -1,10 +1,13 @@
-use crate::log;
+use crate::units::TimeSpan;
+use crate::{collection, cors, ipfilter, log, render, signed, tls};
+use arc_swap::ArcSwap;
+use rocket::data::ByteUnit;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
 
 /// Main configuration structure for Barleywine
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +23,14 @@ pub struct Config {
     pub template: TemplateConfig,
     pub routes: RoutesConfig,
     pub middleware: MiddlewareConfig,
+    #[serde(default)]
+    pub internal: InternalConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
 }
 
 /// Server configuration
@@ -31,10 +42,72 @@ pub struct ServerConfig {
     pub port: u16,
     /// Number of worker threads
     pub workers: u32,
-    /// Request timeout in seconds
-    pub timeout: u64,
-    /// Maximum request size in bytes
-    pub max_request_size: u64,
+    /// Time a connection may go without reading or writing anything
+    pub timeout: TimeSpan,
+    /// Maximum request size, the ceiling for every `[limits]` entry
+    pub max_request_size: ByteUnit,
+    /// Time a client has to send a request's headers
+    #[serde(default = "default_header_timeout")]
+    pub header_timeout: TimeSpan,
+    /// Relay connections through a listener enforcing `timeout` and `header_timeout`
+    #[serde(default = "default_true")]
+    pub connection_guard: bool,
+    /// Time an idle keep-alive connection is kept open
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: TimeSpan,
+    /// TLS termination (HTTPS is served when this section is present)
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Reverse proxies trusted to report the client address
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+}
+
+/// Reverse proxy configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// Proxy addresses or CIDR networks whose forwarding headers are believed
+    pub trusted: Vec<String>,
+    /// Expect a PROXY protocol (v1 or v2) header on every connection
+    pub proxy_protocol: bool,
+    /// Redirect requests a trusted proxy received over plain HTTP to HTTPS
+    pub https_redirect: bool,
+}
+
+/// TLS configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file containing the certificate chain
+    pub certs: PathBuf,
+    /// PEM file containing the private key
+    pub key: PathBuf,
+    /// Minimum TLS version: "1.2" or "1.3"
+    #[serde(default = "default_tls_min_version")]
+    pub min_version: String,
+    /// Cipher suites in order of preference (empty means Rocket's defaults)
+    #[serde(default)]
+    pub ciphers: Vec<String>,
+    /// Prefer the server's cipher order over the client's
+    #[serde(default)]
+    pub prefer_server_cipher_order: bool,
+    /// CA certificates (PEM) that client certificates are verified against
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// Restart the listener with the new certificate when the files change
+    #[serde(default = "default_true")]
+    pub reload: bool,
+    /// Port for a plain HTTP listener that redirects to HTTPS
+    #[serde(default)]
+    pub redirect_port: Option<u16>,
+}
+
+fn default_tls_min_version() -> String {
+    "1.2".to_string()
+}
+
+fn default_true() -> bool {
+    true
 }
 
 /// Logging configuration
@@ -61,6 +134,60 @@ pub struct ContentConfig {
     pub markdown_enabled: bool,
     /// Markdown extensions to enable
     pub markdown_extensions: Vec<String>,
+    /// Whether raw HTML in markdown is kept, after sanitizing, instead of
+    /// being escaped
+    #[serde(default)]
+    pub allow_html: bool,
+    /// What the sanitizer keeps when `allow_html` is on
+    #[serde(default)]
+    pub html: HtmlConfig,
+    /// Directories whose index lists their pages
+    #[serde(default)]
+    pub collections: Vec<CollectionConfig>,
+    /// Whether `$...$` and `$$...$$` in markdown are rendered as math
+    #[serde(default = "default_math")]
+    pub math: bool,
+}
+
+fn default_math() -> bool {
+    true
+}
+
+/// A directory declared a collection in the configuration rather than in
+/// its index's front matter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollectionConfig {
+    /// URL path of the directory, such as `/blog`
+    pub path: String,
+    /// Entries per page of the listing
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+fn default_per_page() -> usize {
+    collection::DEFAULT_PER_PAGE
+}
+
+/// The HTML allowlist for markdown pages when `allow_html` is on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HtmlConfig {
+    /// Tags the sanitizer keeps
+    pub tags: Vec<String>,
+    /// Attributes kept on each tag, with `*` for those kept on any tag
+    pub attributes: BTreeMap<String, Vec<String>>,
+    /// Schemes allowed in link and image URLs
+    pub url_schemes: Vec<String>,
+}
+
+impl Default for HtmlConfig {
+    fn default() -> Self {
+        HtmlConfig {
+            tags: render::default_tags(),
+            attributes: render::default_attributes(),
+            url_schemes: ["http", "https", "mailto"].map(String::from).to_vec(),
+        }
+    }
 }
 
 /// Cache configuration
@@ -87,6 +214,273 @@ pub struct SecurityConfig {
     pub cors_enabled: bool,
     /// CORS allowed origins
     pub cors_origins: Vec<String>,
+    /// Path prefixes that require a verified client certificate
+    #[serde(default)]
+    pub client_certs: Vec<ClientCertRule>,
+    /// Values of the security headers (an empty value omits that header)
+    #[serde(default)]
+    pub headers: SecurityHeadersConfig,
+    /// CORS policy details and per-path overrides
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Per-client rate limits and the concurrent request cap
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Client IP allow and deny rules, evaluated in order
+    #[serde(default)]
+    pub ip_rules: Vec<IpRule>,
+    /// Whether symbolic links under the webroot are followed
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    /// Path prefixes only reachable through signed, expiring URLs
+    #[serde(default)]
+    pub signed_urls: SignedUrlConfig,
+    /// Files other sites may not embed, checked in order
+    #[serde(default)]
+    pub hotlink: Vec<HotlinkRule>,
+}
+
+/// Files only our own pages and the listed domains may embed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotlinkRule {
+    /// URL path prefix the rule covers (any path when unset)
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// File extensions the rule covers, e.g. ".jpg" (any when empty)
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Domains whose pages may embed the files; "*.example.com" covers subdomains
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// Allow requests without a Referer or Origin, such as direct visits
+    #[serde(default = "default_true")]
+    pub allow_empty: bool,
+    /// Image under the webroot sent instead of the file, e.g. "/images/hotlink.png"
+    #[serde(default)]
+    pub replacement: Option<String>,
+}
+
+impl HotlinkRule {
+    /// Check if a request from the page at `referer` may fetch the file.
+    /// Pages on `host`, the site itself, always may.
+    pub fn allows(&self, referer: Option<&str>, host: Option<&str>) -> bool {
+        let referer = referer.map(str::trim).filter(|referer| !referer.is_empty());
+        let Some(referer) = referer else {
+            return self.allow_empty;
+        };
+        let Some(domain) = referer_domain(referer) else {
+            return false;
+        };
+        host.is_some_and(|host| host.eq_ignore_ascii_case(domain))
+            || self
+                .allowed_domains
+                .iter()
+                .any(|pattern| matches_pattern(pattern, domain))
+    }
+}
+
+/// Signed URLs granting access to files under protected prefixes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SignedUrlConfig {
+    /// Secret the signatures are made with
+    pub key: Option<String>,
+    /// URL path prefixes that require a valid signature
+    pub prefixes: Vec<String>,
+}
+
+/// How symbolic links met while resolving a request are treated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Refuse any path that passes through a symbolic link
+    Deny,
+    /// Follow links as long as the target stays inside the webroot
+    #[default]
+    WithinWebroot,
+    /// Follow links wherever they lead
+    Follow,
+}
+
+/// Whether an IP rule lets matching clients in or keeps them out
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpAction {
+    Allow,
+    Deny,
+}
+
+/// Allow or deny clients in a set of networks under a path prefix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpRule {
+    /// URL path prefix the rule applies to (the whole site by default)
+    #[serde(default = "default_ip_rule_prefix")]
+    pub prefix: String,
+    pub action: IpAction,
+    /// Addresses or CIDR networks, IPv4 or IPv6, or `all`
+    pub cidrs: Vec<String>,
+}
+
+fn default_ip_rule_prefix() -> String {
+    "/".to_string()
+}
+
+/// Token-bucket rate limiting per client, plus a global concurrency cap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Enable per-client rate limiting
+    pub enabled: bool,
+    /// Requests per second each client's budget refills by
+    pub rate: f64,
+    /// Requests a client may make in a burst
+    pub burst: u32,
+    /// Header identifying the client, read only from trusted proxies (the client IP when unset or absent)
+    pub key_header: Option<String>,
+    /// Concurrent requests served before shedding load with 503 (0 means no cap)
+    pub max_concurrent: usize,
+    /// Separate budgets for path prefixes
+    pub paths: Vec<RateLimitPathRule>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            rate: 10.0,
+            burst: 20,
+            key_header: None,
+            max_concurrent: 0,
+            paths: vec![],
+        }
+    }
+}
+
+/// Rate limit budget for a path prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitPathRule {
+    /// URL path prefix the budget applies to, e.g. `/downloads`
+    pub prefix: String,
+    /// Requests per second
+    pub rate: f64,
+    /// Requests a client may make in a burst
+    pub burst: u32,
+}
+
+/// CORS policy applied with `cors_origins` when `cors_enabled` and
+/// `middleware.cors` are both on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Methods allowed in cross-origin requests
+    pub methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests
+    pub headers: Vec<String>,
+    /// Response headers exposed to cross-origin scripts
+    pub expose_headers: Vec<String>,
+    /// Whether cross-origin requests may carry cookies and credentials
+    pub credentials: bool,
+    /// Seconds a browser may cache a preflight response
+    pub max_age: u64,
+    /// Policies for path prefixes; unset fields fall back to the values above
+    pub paths: Vec<CorsPathRule>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "OPTIONS".to_string()],
+            headers: vec![],
+            expose_headers: vec![],
+            credentials: false,
+            max_age: 86400,
+            paths: vec![],
+        }
+    }
+}
+
+/// CORS policy for a path prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsPathRule {
+    /// URL path prefix the policy applies to, e.g. `/api`
+    pub prefix: String,
+    /// Set to false to send no CORS headers under this prefix
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub origins: Option<Vec<String>>,
+    pub methods: Option<Vec<String>>,
+    pub headers: Option<Vec<String>>,
+    pub expose_headers: Option<Vec<String>>,
+    pub credentials: Option<bool>,
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsPathRule {
+    fn default() -> Self {
+        CorsPathRule {
+            prefix: "/".to_string(),
+            enabled: true,
+            origins: None,
+            methods: None,
+            headers: None,
+            expose_headers: None,
+            credentials: None,
+            max_age: None,
+        }
+    }
+}
+
+/// Security header values, sent when `security_headers` and
+/// `middleware.security` are both enabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    /// Content-Security-Policy; rendered pages add a per-response nonce
+    pub content_security_policy: String,
+    /// Strict-Transport-Security, only sent when TLS is enabled
+    pub strict_transport_security: String,
+    /// X-Content-Type-Options
+    pub content_type_options: String,
+    /// X-Frame-Options (keep in step with the CSP `frame-ancestors`)
+    pub frame_options: String,
+    /// Referrer-Policy
+    pub referrer_policy: String,
+    /// Permissions-Policy
+    pub permissions_policy: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            content_security_policy: "default-src 'self'; style-src 'self'; img-src 'self' data:; object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'".to_string(),
+            strict_transport_security: "max-age=31536000; includeSubDomains".to_string(),
+            content_type_options: "nosniff".to_string(),
+            frame_options: "DENY".to_string(),
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            permissions_policy: "camera=(), microphone=(), geolocation=(), payment=(), usb=()"
+                .to_string(),
+        }
+    }
+}
+
+/// Client certificate requirement for a path prefix. Patterns may use `*`
+/// as a wildcard and match case-insensitively.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientCertRule {
+    /// URL path prefix the rule protects, e.g. `/internal`
+    pub prefix: String,
+    /// Subjects allowed in, e.g. `CN=*.build.example.com, O=Example`
+    #[serde(default)]
+    pub allow_subjects: Vec<String>,
+    /// Subject alternative names (DNS names, emails, URIs, IPs) allowed in
+    #[serde(default)]
+    pub allow_sans: Vec<String>,
+    /// Subjects refused even when allowed above
+    #[serde(default)]
+    pub deny_subjects: Vec<String>,
+    /// Subject alternative names refused even when allowed above
+    #[serde(default)]
+    pub deny_sans: Vec<String>,
 }
 
 /// Performance configuration
@@ -113,19 +507,51 @@ pub struct DevelopmentConfig {
     pub debug_routes: bool,
     /// Pretty print JSON responses
     pub pretty_json: bool,
+    /// Address the debug routes listener binds to
+    #[serde(default = "default_debug_host")]
+    pub debug_host: String,
+    /// Port the debug routes listener binds to
+    #[serde(default = "default_debug_port")]
+    pub debug_port: u16,
+    /// Allow the debug routes listener to bind to a non-loopback address
+    #[serde(default)]
+    pub debug_allow_remote: bool,
+    /// Directory for generated state such as `--dev-tls` certificates
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
+}
+
+fn default_debug_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_debug_port() -> u16 {
+    8001
+}
+
+fn default_state_dir() -> PathBuf {
+    PathBuf::from(".barleywine")
+}
+
+fn default_header_timeout() -> TimeSpan {
+    TimeSpan::from_secs(10)
+}
+
+fn default_keep_alive() -> TimeSpan {
+    TimeSpan::from_secs(5)
 }
 
 /// Request limits configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LimitsConfig {
     /// Form data limit
-    pub forms: String,
+    pub forms: ByteUnit,
     /// JSON payload limit
-    pub json: String,
+    pub json: ByteUnit,
     /// File upload limit
-    pub file: String,
+    pub file: ByteUnit,
     /// Data limit
-    pub data: String,
+    pub data: ByteUnit,
 }
 
 /// Template configuration
@@ -141,6 +567,10 @@ pub struct TemplateConfig {
     pub site_title: String,
     /// Site description
     pub site_description: String,
+    /// Public address of the site, such as `https://example.com`, for the
+    /// absolute links in feeds
+    #[serde(default)]
+    pub site_url: Option<String>,
 }
 
 /// Routes configuration
@@ -169,6 +599,132 @@ pub struct MiddlewareConfig {
     pub custom: Vec<String>,
 }
 
+/// Authentication configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Path prefixes protected by HTTP Basic authentication
+    pub basic: Vec<BasicAuthRule>,
+    /// Login sessions used by `rules`
+    pub session: SessionConfig,
+    /// Path prefixes that require a logged-in user with one of the given roles
+    pub rules: Vec<AuthRule>,
+}
+
+/// Login form and session cookie settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// User file: `[[users]]` tables when it ends in `.toml`, otherwise htpasswd
+    pub users: Option<PathBuf>,
+    /// Seconds a login lasts
+    pub ttl: u64,
+    /// Name of the session cookie
+    pub cookie_name: String,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            users: None,
+            ttl: 28800,
+            cookie_name: "barleywine_session".to_string(),
+        }
+    }
+}
+
+/// Roles allowed under a path prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthRule {
+    /// URL path prefix the rule protects, e.g. `/drafts`
+    pub prefix: String,
+    /// Roles granted access (any logged-in user when empty)
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// HTTP Basic authentication for a path prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicAuthRule {
+    /// URL path prefix the rule protects, e.g. `/internal`
+    pub prefix: String,
+    /// Realm shown by the browser's login prompt
+    pub realm: String,
+    /// htpasswd file with bcrypt, sha256-crypt, sha512-crypt or argon2 hashes
+    pub htpasswd: PathBuf,
+}
+
+/// Full-text search over the markdown pages of the webroot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Whether to index the markdown pages and serve search
+    pub enabled: bool,
+    /// URL path of the search endpoint and results page
+    pub path: String,
+    /// Most results returned for a query
+    pub max_results: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: false,
+            path: "/_search".to_string(),
+            max_results: 20,
+        }
+    }
+}
+
+/// Internal endpoints configuration (health checks, build info)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InternalConfig {
+    /// Reserved URL prefix under which Barleywine's own endpoints are mounted
+    pub prefix: String,
+    /// Whether requests to internal endpoints are written to the access log
+    pub access_log: bool,
+}
+
+impl Default for InternalConfig {
+    fn default() -> Self {
+        InternalConfig {
+            prefix: "/_barleywine".to_string(),
+            access_log: false,
+        }
+    }
+}
+
+/// Graceful shutdown configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Shut down gracefully on Ctrl-C (SIGINT)
+    pub ctrlc: bool,
+    /// Signals that trigger a graceful shutdown (unix only)
+    pub signals: Vec<String>,
+    /// Seconds to let in-flight requests finish before closing connections
+    pub grace: u32,
+    /// Seconds to let connections close after the grace period
+    pub mercy: u32,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            ctrlc: true,
+            signals: vec!["term".to_string()],
+            grace: 5,
+            mercy: 5,
+        }
+    }
+}
+
+/// Signals that may trigger a shutdown (`hup` is reserved for configuration reload)
+const SHUTDOWN_SIGNALS: &[&str] = &[
+    "alrm", "chld", "int", "io", "pipe", "quit", "term", "usr1", "usr2",
+];
+
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -176,8 +732,13 @@ impl Default for Config {
                 host: "127.0.0.1".to_string(),
                 port: 8000,
                 workers: 4,
-                timeout: 30,
-                max_request_size: 10 * 1024 * 1024, // 10MB
+                timeout: TimeSpan::from_secs(30),
+                max_request_size: ByteUnit::Mebibyte(10),
+                header_timeout: default_header_timeout(),
+                connection_guard: true,
+                keep_alive: default_keep_alive(),
+                tls: None,
+                proxy: ProxyConfig::default(),
             },
             logging: LoggingConfig {
                 level: "info".to_string(),
@@ -195,6 +756,10 @@ impl Default for Config {
                     "task_lists".to_string(),
                     "autolinks".to_string(),
                 ],
+                allow_html: false,
+                html: HtmlConfig::default(),
+                collections: Vec::new(),
+                math: true,
             },
             cache: CacheConfig {
                 enabled: true,
@@ -212,6 +777,14 @@ impl Default for Config {
                 ],
                 cors_enabled: false,
                 cors_origins: vec!["*".to_string()],
+                client_certs: vec![],
+                headers: SecurityHeadersConfig::default(),
+                cors: CorsConfig::default(),
+                rate_limit: RateLimitConfig::default(),
+                ip_rules: vec![],
+                symlinks: SymlinkPolicy::default(),
+                signed_urls: SignedUrlConfig::default(),
+                hotlink: vec![],
             },
             performance: PerformanceConfig {
                 compression: true,
@@ -224,12 +797,16 @@ impl Default for Config {
                 hot_reload: false,
                 debug_routes: false,
                 pretty_json: true,
+                debug_host: default_debug_host(),
+                debug_port: default_debug_port(),
+                debug_allow_remote: false,
+                state_dir: default_state_dir(),
             },
             limits: LimitsConfig {
-                forms: "1MiB".to_string(),
-                json: "1MiB".to_string(),
-                file: "10MiB".to_string(),
-                data: "10MiB".to_string(),
+                forms: ByteUnit::Mebibyte(1),
+                json: ByteUnit::Mebibyte(1),
+                file: ByteUnit::Mebibyte(10),
+                data: ByteUnit::Mebibyte(10),
             },
             template: TemplateConfig {
                 custom_template: PathBuf::new(),
@@ -237,6 +814,7 @@ impl Default for Config {
                 custom_js: PathBuf::new(),
                 site_title: "Barleywine Server".to_string(),
                 site_description: "A fast static file server with markdown support".to_string(),
+                site_url: None,
             },
             routes: RoutesConfig {
                 mappings: HashMap::new(),
@@ -249,10 +827,17 @@ impl Default for Config {
                 security: true,
                 custom: vec![],
             },
+            internal: InternalConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            auth: AuthConfig::default(),
+            search: SearchConfig::default(),
         }
     }
 }
 
+/// Configuration files looked for when none is given on the command line
+const DEFAULT_CONFIG_FILES: [&str; 3] = ["barleywine.toml", "config.toml", "barleywine.conf"];
+
 impl Config {
     /// Load configuration from a file
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
@@ -281,9 +866,7 @@ impl Config {
             }
             None => {
                 // Try default locations
-                let default_files = ["barleywine.toml", "config.toml", "barleywine.conf"];
-
-                for file in &default_files {
+                for file in &DEFAULT_CONFIG_FILES {
                     if Path::new(file).exists() {
                         log::log_barleywine(&format!("Found default configuration file: {}", file));
                         return Self::from_file(file);
@@ -326,10 +909,11 @@ impl Config {
         }
 
         // Validate log file directory
-        if let Some(parent) = self.logging.file.parent() {
-            if parent.exists() && !parent.is_dir() {
-                return Err(ConfigError::LogDirectoryNotDirectory(parent.to_path_buf()));
-            }
+        if let Some(parent) = self.logging.file.parent()
+            && parent.exists()
+            && !parent.is_dir()
+        {
+            return Err(ConfigError::LogDirectoryNotDirectory(parent.to_path_buf()));
         }
 
         // Validate custom template file
@@ -362,6 +946,339 @@ impl Config {
             ));
         }
 
+        // Validate debug routes listener address
+        if self.development.debug_routes {
+            let debug_ip: IpAddr = self
+                .development
+                .debug_host
+                .parse()
+                .map_err(|_| ConfigError::InvalidHost(self.development.debug_host.clone()))?;
+            if !debug_ip.is_loopback() && !self.development.debug_allow_remote {
+                return Err(ConfigError::DebugRoutesNotLoopback(
+                    self.development.debug_host.clone(),
+                ));
+            }
+            if self.development.debug_port == 0 || self.development.debug_port == self.server.port {
+                return Err(ConfigError::InvalidPort(self.development.debug_port));
+            }
+        }
+
+        // Validate internal endpoint prefix
+        if !is_valid_prefix(&self.internal.prefix) {
+            return Err(ConfigError::InvalidInternalPrefix(
+                self.internal.prefix.clone(),
+            ));
+        }
+
+        // Validate TLS settings
+        if let Some(ref tls_config) = self.server.tls {
+            if !tls_config.certs.exists() {
+                return Err(ConfigError::TlsFileNotFound(tls_config.certs.clone()));
+            }
+            if !tls_config.key.exists() {
+                return Err(ConfigError::TlsFileNotFound(tls_config.key.clone()));
+            }
+            tls::effective_ciphers(tls_config).map_err(ConfigError::InvalidTls)?;
+            if let Some(ref client_ca) = tls_config.client_ca
+                && !client_ca.exists()
+            {
+                return Err(ConfigError::TlsFileNotFound(client_ca.clone()));
+            }
+            if let Some(redirect_port) = tls_config.redirect_port
+                && (redirect_port == 0 || redirect_port == self.server.port)
+            {
+                return Err(ConfigError::InvalidPort(redirect_port));
+            }
+        }
+
+        // Validate CORS policies
+        for rule in &self.security.cors.paths {
+            if !rule.prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(rule.prefix.clone()));
+            }
+        }
+        cors::validate(self).map_err(ConfigError::InvalidCors)?;
+
+        // Validate rate limits
+        let rate_limit = &self.security.rate_limit;
+        let budgets = std::iter::once(("", rate_limit.rate, rate_limit.burst)).chain(
+            rate_limit
+                .paths
+                .iter()
+                .map(|rule| (rule.prefix.as_str(), rule.rate, rule.burst)),
+        );
+        for (prefix, rate, burst) in budgets {
+            if !prefix.is_empty() && !prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(prefix.to_string()));
+            }
+            if !rate.is_finite() || rate <= 0.0 || burst == 0 {
+                return Err(ConfigError::InvalidRateLimit(format!(
+                    "rate must be positive and burst at least 1 (got rate {} and burst {})",
+                    rate, burst
+                )));
+            }
+        }
+
+        // Validate request limits
+        let limits = [
+            ("server.max_request_size", self.server.max_request_size),
+            ("limits.forms", self.limits.forms),
+            ("limits.json", self.limits.json),
+            ("limits.file", self.limits.file),
+            ("limits.data", self.limits.data),
+        ];
+        for (name, limit) in limits {
+            if limit == ByteUnit::Byte(0) {
+                return Err(ConfigError::InvalidLimit(format!(
+                    "{} must be larger than zero",
+                    name
+                )));
+            }
+            if limit > self.server.max_request_size {
+                return Err(ConfigError::InvalidLimit(format!(
+                    "{} ({}) exceeds server.max_request_size ({})",
+                    name, limit, self.server.max_request_size
+                )));
+            }
+        }
+        if self.server.keep_alive.as_secs() > u64::from(u32::MAX) {
+            return Err(ConfigError::InvalidLimit(format!(
+                "server.keep_alive ({}) is too long",
+                self.server.keep_alive
+            )));
+        }
+
+        // Validate the HTML allowlist
+        for tag in &self.content.html.tags {
+            if !is_html_name(tag) {
+                return Err(ConfigError::InvalidHtmlAllowlist(format!(
+                    "'{}' is not a tag name",
+                    tag
+                )));
+            }
+            if render::REMOVED_WITH_CONTENT.contains(&tag.to_ascii_lowercase().as_str()) {
+                return Err(ConfigError::InvalidHtmlAllowlist(format!(
+                    "<{}> can't be allowed",
+                    tag
+                )));
+            }
+        }
+        for (tag, attributes) in &self.content.html.attributes {
+            if tag != "*" && !is_html_name(tag) {
+                return Err(ConfigError::InvalidHtmlAllowlist(format!(
+                    "'{}' is not a tag name",
+                    tag
+                )));
+            }
+            for attribute in attributes {
+                let attribute = attribute.to_ascii_lowercase();
+                if !is_html_name(&attribute) || attribute.starts_with("on") {
+                    return Err(ConfigError::InvalidHtmlAllowlist(format!(
+                        "'{}' can't be allowed on {}",
+                        attribute, tag
+                    )));
+                }
+                // Links always get rel="noopener noreferrer"
+                if attribute == "rel" && (tag == "*" || tag.eq_ignore_ascii_case("a")) {
+                    return Err(ConfigError::InvalidHtmlAllowlist(
+                        "rel can't be allowed on a".to_string(),
+                    ));
+                }
+            }
+        }
+        for scheme in &self.content.html.url_schemes {
+            let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+            if !valid || scheme.eq_ignore_ascii_case("javascript") {
+                return Err(ConfigError::InvalidHtmlAllowlist(format!(
+                    "'{}' is not an allowed URL scheme",
+                    scheme
+                )));
+            }
+        }
+
+        // Validate the site URL
+        if let Some(ref site_url) = self.template.site_url {
+            let host = site_url
+                .strip_prefix("https://")
+                .or_else(|| site_url.strip_prefix("http://"))
+                .map(|rest| rest.split('/').next().unwrap_or_default());
+            if host.is_none_or(str::is_empty) || site_url.contains(['?', '#', ' ']) {
+                return Err(ConfigError::InvalidSiteUrl(site_url.clone()));
+            }
+        }
+
+        // Validate search
+        if !is_valid_prefix(&self.search.path) {
+            return Err(ConfigError::InvalidSearch(format!(
+                "path '{}' must start with '/', not end with '/', and not be '/'",
+                self.search.path
+            )));
+        }
+        if self.search.max_results == 0 {
+            return Err(ConfigError::InvalidSearch(
+                "max_results must be at least 1".to_string(),
+            ));
+        }
+
+        // Validate collections
+        for collection in &self.content.collections {
+            if collection.path != "/" && !is_valid_prefix(&collection.path) {
+                return Err(ConfigError::InvalidCollection(format!(
+                    "path '{}' must start with '/' and not end with one",
+                    collection.path
+                )));
+            }
+            if collection.per_page == 0 {
+                return Err(ConfigError::InvalidCollection(format!(
+                    "per_page for {} must be at least 1",
+                    collection.path
+                )));
+            }
+        }
+
+        // Validate signed URLs
+        let signed_urls = &self.security.signed_urls;
+        for prefix in &signed_urls.prefixes {
+            if !prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(prefix.clone()));
+            }
+        }
+        match signed_urls.key {
+            Some(ref key) if key.len() < signed::MIN_KEY_LENGTH => {
+                return Err(ConfigError::InvalidSignedUrl(format!(
+                    "key must be at least {} characters",
+                    signed::MIN_KEY_LENGTH
+                )));
+            }
+            None if !signed_urls.prefixes.is_empty() => {
+                return Err(ConfigError::InvalidSignedUrl(
+                    "protected prefixes need a key".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        // Validate hotlink rules
+        for rule in &self.security.hotlink {
+            if rule.prefix.is_none() && rule.extensions.is_empty() {
+                return Err(ConfigError::InvalidHotlink(
+                    "each rule needs a prefix or extensions, or it would cover every page"
+                        .to_string(),
+                ));
+            }
+            for path in rule.prefix.iter().chain(&rule.replacement) {
+                if !path.starts_with('/') {
+                    return Err(ConfigError::InvalidPathPrefix(path.clone()));
+                }
+            }
+            if let Some(extension) = rule.extensions.iter().find(|ext| !ext.starts_with('.')) {
+                return Err(ConfigError::InvalidHotlink(format!(
+                    "extension '{}' must start with '.'",
+                    extension
+                )));
+            }
+        }
+
+        // Validate trusted proxies
+        let proxy = &self.server.proxy;
+        for entry in &proxy.trusted {
+            ipfilter::parse_networks(entry).map_err(ConfigError::InvalidProxy)?;
+        }
+        if (proxy.proxy_protocol || proxy.https_redirect) && proxy.trusted.is_empty() {
+            return Err(ConfigError::InvalidProxy(
+                "proxy_protocol and https_redirect require trusted proxies".to_string(),
+            ));
+        }
+
+        // Validate IP rules
+        for rule in &self.security.ip_rules {
+            if !rule.prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(rule.prefix.clone()));
+            }
+            for cidr in &rule.cidrs {
+                ipfilter::parse_networks(cidr).map_err(ConfigError::InvalidIpRule)?;
+            }
+        }
+
+        // Validate client certificate rules
+        for rule in &self.security.client_certs {
+            if !rule.prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(rule.prefix.clone()));
+            }
+        }
+        let verifies_clients = self
+            .server
+            .tls
+            .as_ref()
+            .is_some_and(|tls_config| tls_config.client_ca.is_some());
+        if !self.security.client_certs.is_empty() && !verifies_clients {
+            return Err(ConfigError::InvalidTls(
+                "security.client_certs rules require server.tls.client_ca".to_string(),
+            ));
+        }
+
+        // Validate basic authentication rules
+        for rule in &self.auth.basic {
+            if !rule.prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(rule.prefix.clone()));
+            }
+            if rule.realm.is_empty() || rule.realm.contains(['"', '\\']) {
+                return Err(ConfigError::InvalidAuth(format!(
+                    "realm for {} must be non-empty and cannot contain quotes or backslashes",
+                    rule.prefix
+                )));
+            }
+            if !rule.htpasswd.is_file() {
+                return Err(ConfigError::AuthFileNotFound(rule.htpasswd.clone()));
+            }
+        }
+
+        // Validate login sessions
+        for rule in &self.auth.rules {
+            if !rule.prefix.starts_with('/') {
+                return Err(ConfigError::InvalidPathPrefix(rule.prefix.clone()));
+            }
+        }
+        let session = &self.auth.session;
+        if let Some(ref users) = session.users {
+            if !users.is_file() {
+                return Err(ConfigError::AuthFileNotFound(users.clone()));
+            }
+        } else if !self.auth.rules.is_empty() {
+            return Err(ConfigError::InvalidAuth(
+                "auth.rules require auth.session.users".to_string(),
+            ));
+        }
+        if session.ttl == 0 {
+            return Err(ConfigError::InvalidAuth(
+                "auth.session.ttl must be at least 1 second".to_string(),
+            ));
+        }
+        if session.cookie_name.is_empty()
+            || !session
+                .cookie_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(ConfigError::InvalidAuth(format!(
+                "invalid session cookie name '{}'",
+                session.cookie_name
+            )));
+        }
+
+        // Validate shutdown signals
+        if let Some(signal) = self
+            .shutdown
+            .signals
+            .iter()
+            .find(|signal| !SHUTDOWN_SIGNALS.contains(&signal.as_str()))
+        {
+            return Err(ConfigError::InvalidShutdownSignal(signal.clone()));
+        }
+
         Ok(())
     }
 
@@ -370,7 +1287,7 @@ impl Config {
         let path = path.as_ref();
         let default_config = Self::default();
         let toml_content =
-            toml::to_string_pretty(&default_config).map_err(|e| ConfigError::SerializeError(e))?;
+            toml::to_string_pretty(&default_config).map_err(ConfigError::SerializeError)?;
 
         fs::write(path, toml_content)
             .map_err(|e| ConfigError::WriteError(path.to_path_buf(), e))?;
@@ -425,6 +1342,27 @@ impl Config {
             .any(|ext| ext == extension)
     }
 
+    /// The first hotlink rule covering a request path, once normalized
+    pub fn hotlink_rule(&self, path: &str) -> Option<&HotlinkRule> {
+        let path = normalize_path(path);
+        let extension = Path::new(&path)
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()));
+        self.security.hotlink.iter().find(|rule| {
+            let prefix_matches = rule
+                .prefix
+                .as_ref()
+                .is_none_or(|prefix| path_has_prefix(&path, prefix));
+            let extension_matches = rule.extensions.is_empty()
+                || extension.as_ref().is_some_and(|extension| {
+                    rule.extensions
+                        .iter()
+                        .any(|ext| ext.eq_ignore_ascii_case(extension))
+                });
+            prefix_matches && extension_matches
+        })
+    }
+
     /// Print configuration verification details
     pub fn print_config(
         &self,
@@ -456,7 +1394,20 @@ impl Config {
         println!("   Server:");
         println!("     Address: {}:{}", self.server.host, self.server.port);
         println!("     Workers: {}", self.server.workers);
-        println!("     Timeout: {}s", self.server.timeout);
+        println!("     Timeout: {}", self.server.timeout);
+        match self.server.tls {
+            Some(ref tls_config) => {
+                println!(
+                    "     TLS: enabled (minimum version {})",
+                    tls_config.min_version
+                );
+                println!("     Certificates: {}", tls_config.certs.display());
+                if let Some(redirect_port) = tls_config.redirect_port {
+                    println!("     HTTP Redirect Port: {}", redirect_port);
+                }
+            }
+            None => println!("     TLS: disabled"),
+        }
 
         println!("   Content:");
         println!("     Webroot: {}", self.content.webroot.display());
@@ -488,7 +1439,113 @@ impl Config {
         println!("   Development:");
         println!("     Dev Mode: {}", self.development.dev_mode);
         println!("     Hot Reload: {}", self.development.hot_reload);
+        println!("     Debug Routes: {}", self.development.debug_routes);
+        if self.development.debug_routes {
+            println!(
+                "     Debug Listener: {}:{}",
+                self.development.debug_host, self.development.debug_port
+            );
+        }
+
+        println!("   Shutdown:");
+        println!("     Ctrl-C: {}", self.shutdown.ctrlc);
+        println!("     Signals: {:?}", self.shutdown.signals);
+        println!(
+            "     Grace: {}s, Mercy: {}s",
+            self.shutdown.grace, self.shutdown.mercy
+        );
+
+        println!("   Internal Endpoints:");
+        println!("     Prefix: {}", self.internal.prefix);
+        println!("     Access Log: {}", self.internal.access_log);
+    }
+}
+
+/// Whether a URL path falls under a configured path prefix. `/docs` covers
+/// `/docs` and everything below it, but not `/docs-old`. Request paths must
+/// go through [`normalize_path`] first so they compare like the file served.
+pub fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    let base = prefix.trim_end_matches('/');
+    match path.strip_prefix(base) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The path a raw request path serves, decoded and normalized the way the
+/// file route resolves it: `%xx` escapes are decoded, empty and `.` segments
+/// are dropped and `..` removes the segment before it. `/%64ocs//a/../b`
+/// becomes `/docs/b`.
+pub fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<String> = Vec::new();
+    for raw in path.split('/').filter(|raw| !raw.is_empty()) {
+        let decoded = rocket::http::RawStr::new(raw).percent_decode_lossy();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment.to_string()),
+            }
+        }
     }
+    format!("/{}", segments.join("/"))
+}
+
+/// Match a value against a pattern where `*` stands for any run of
+/// characters, ignoring ASCII case
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let value = value.to_ascii_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: the whole value must match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// The host of a Referer or Origin URL, without port or credentials
+fn referer_domain(referer: &str) -> Option<&str> {
+    let (_, rest) = referer.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then_some(host)
+}
+
+/// Check that a URL prefix is absolute, not the root, and has no trailing slash
+fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.len() > 1
+        && prefix.starts_with('/')
+        && !prefix.ends_with('/')
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '.'))
+}
+
+/// Whether a name could be an HTML tag or attribute
+fn is_html_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':'))
 }
 
 /// Configuration error types
@@ -509,6 +1566,26 @@ pub enum ConfigError {
     InvalidPort(u16),
     InvalidHost(String),
     InvalidCompressionLevel(u32),
+    InvalidInternalPrefix(String),
+    DebugRoutesNotLoopback(String),
+    NoConfigFile,
+    InvalidShutdownSignal(String),
+    TlsFileNotFound(PathBuf),
+    InvalidPathPrefix(String),
+    InvalidCors(String),
+    InvalidRateLimit(String),
+    InvalidTls(String),
+    InvalidAuth(String),
+    InvalidIpRule(String),
+    InvalidProxy(String),
+    InvalidSignedUrl(String),
+    InvalidHotlink(String),
+    InvalidLimit(String),
+    InvalidHtmlAllowlist(String),
+    InvalidCollection(String),
+    InvalidSiteUrl(String),
+    InvalidSearch(String),
+    AuthFileNotFound(PathBuf),
     AlreadyInitialized,
 }
 
@@ -584,6 +1661,87 @@ impl std::fmt::Display for ConfigError {
             ConfigError::InvalidCompressionLevel(level) => {
                 write!(f, "Invalid compression level: {} (must be 1-9)", level)
             }
+            ConfigError::InvalidInternalPrefix(prefix) => {
+                write!(
+                    f,
+                    "Invalid internal endpoint prefix '{}' (must start with '/', not end with '/', and not be '/')",
+                    prefix
+                )
+            }
+            ConfigError::DebugRoutesNotLoopback(host) => {
+                write!(
+                    f,
+                    "Debug routes would bind to non-loopback address {} (set development.debug_allow_remote = true to allow this)",
+                    host
+                )
+            }
+            ConfigError::NoConfigFile => {
+                write!(f, "No configuration file in use to reload")
+            }
+            ConfigError::InvalidShutdownSignal(signal) => {
+                write!(
+                    f,
+                    "Invalid shutdown signal '{}'. Valid signals are: {}",
+                    signal,
+                    SHUTDOWN_SIGNALS.join(", ")
+                )
+            }
+            ConfigError::TlsFileNotFound(path) => {
+                write!(
+                    f,
+                    "TLS certificate or key file not found: {}",
+                    path.display()
+                )
+            }
+            ConfigError::InvalidPathPrefix(prefix) => {
+                write!(f, "Invalid path prefix '{}': must start with '/'", prefix)
+            }
+            ConfigError::InvalidCors(reason) => {
+                write!(f, "Invalid CORS configuration: {}", reason)
+            }
+            ConfigError::InvalidRateLimit(reason) => {
+                write!(f, "Invalid rate limit: {}", reason)
+            }
+            ConfigError::InvalidTls(reason) => {
+                write!(f, "Invalid TLS configuration: {}", reason)
+            }
+            ConfigError::InvalidAuth(reason) => {
+                write!(f, "Invalid authentication configuration: {}", reason)
+            }
+            ConfigError::InvalidIpRule(reason) => {
+                write!(f, "Invalid IP rule: {}", reason)
+            }
+            ConfigError::InvalidProxy(reason) => {
+                write!(f, "Invalid proxy configuration: {}", reason)
+            }
+            ConfigError::InvalidSignedUrl(reason) => {
+                write!(f, "Invalid signed URL configuration: {}", reason)
+            }
+            ConfigError::InvalidHotlink(reason) => {
+                write!(f, "Invalid hotlink rule: {}", reason)
+            }
+            ConfigError::InvalidLimit(reason) => {
+                write!(f, "Invalid limit: {}", reason)
+            }
+            ConfigError::InvalidHtmlAllowlist(reason) => {
+                write!(f, "Invalid HTML allowlist: {}", reason)
+            }
+            ConfigError::InvalidCollection(reason) => {
+                write!(f, "Invalid collection: {}", reason)
+            }
+            ConfigError::InvalidSiteUrl(url) => {
+                write!(
+                    f,
+                    "Invalid site URL '{}': expected http:// or https:// and a host, without a query",
+                    url
+                )
+            }
+            ConfigError::InvalidSearch(reason) => {
+                write!(f, "Invalid search settings: {}", reason)
+            }
+            ConfigError::AuthFileNotFound(path) => {
+                write!(f, "User file not found: {}", path.display())
+            }
             ConfigError::AlreadyInitialized => {
                 write!(f, "Configuration has already been initialized")
             }
@@ -593,8 +1751,11 @@ impl std::fmt::Display for ConfigError {
 
 impl std::error::Error for ConfigError {}
 
-/// Global configuration instance
-static GLOBAL_CONFIG: OnceLock<Config> = OnceLock::new();
+/// Global configuration instance, swapped atomically on reload
+static GLOBAL_CONFIG: OnceLock<ArcSwap<Config>> = OnceLock::new();
+
+/// Configuration file the global configuration was loaded from
+static CONFIG_FILE: OnceLock<Option<PathBuf>> = OnceLock::new();
 
 /// Initialize the global configuration
 pub fn init_config(config_file: Option<&Path>) -> Result<(), ConfigError> {
@@ -605,17 +1766,163 @@ pub fn init_config(config_file: Option<&Path>) -> Result<(), ConfigError> {
     });
 
     GLOBAL_CONFIG
-        .set(config)
+        .set(ArcSwap::from_pointee(config))
         .map_err(|_| ConfigError::AlreadyInitialized)?;
+    let _ = CONFIG_FILE.set(find_config_file(config_file));
 
     Ok(())
 }
 
-/// Get the global configuration
-pub fn get_config() -> &'static Config {
+/// TLS settings that replace `[server.tls]` for the life of the process
+static TLS_OVERRIDE: OnceLock<TlsConfig> = OnceLock::new();
+
+/// Serve with the given TLS settings instead of the configured ones, including
+/// after a reload (used by `--dev-tls`)
+pub fn override_tls(tls_config: TlsConfig) {
+    let global = GLOBAL_CONFIG
+        .get()
+        .expect("Configuration not initialized. Call init_config() first.");
+    let mut config = Config::clone(&global.load());
+    config.server.tls = Some(tls_config.clone());
+    global.store(Arc::new(config));
+    let _ = TLS_OVERRIDE.set(tls_config);
+}
+
+/// Find the configuration file in use: the CLI-specified file or the first default that exists
+pub fn find_config_file(config_file: Option<&Path>) -> Option<PathBuf> {
+    match config_file {
+        Some(path) => Some(path.to_path_buf()),
+        None => DEFAULT_CONFIG_FILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists()),
+    }
+}
+
+/// Get the configuration file the global configuration was loaded from
+pub fn config_file() -> Option<&'static Path> {
+    CONFIG_FILE.get().and_then(|path| path.as_deref())
+}
+
+/// Reload the configuration file and atomically swap it in.
+///
+/// On failure the current configuration is kept. Settings that only take
+/// effect at startup keep their running values and are returned so the
+/// caller can report them.
+pub fn reload_config() -> Result<Vec<String>, ConfigError> {
+    let result = config_file()
+        .ok_or(ConfigError::NoConfigFile)
+        .and_then(Config::from_file)
+        .map(|mut next| {
+            if let Some(tls_config) = TLS_OVERRIDE.get() {
+                next.server.tls = Some(tls_config.clone());
+            }
+            let current = get_config();
+            let pinned = pin_restart_only(&current, &mut next);
+            GLOBAL_CONFIG
+                .get()
+                .expect("Configuration not initialized. Call init_config() first.")
+                .store(Arc::new(next));
+            pinned
+        });
+
+    match &result {
+        Ok(pinned) => {
+            set_reload_error(None);
+            log::log_barleywine("Configuration reloaded");
+            for setting in pinned {
+                log::log_warning(&format!(
+                    "Configuration change to {} requires a restart to take effect",
+                    setting
+                ));
+            }
+        }
+        Err(e) => {
+            set_reload_error(Some(e.to_string()));
+            log::log_error(&format!(
+                "Configuration reload failed, keeping current configuration: {}",
+                e
+            ));
+        }
+    }
+
+    result
+}
+
+/// Keep the running values of settings that can't change live, returning
+/// a description of each one that the new configuration tried to change
+fn pin_restart_only(current: &Config, next: &mut Config) -> Vec<String> {
+    let mut pinned = Vec::new();
+
+    macro_rules! pin {
+        ($name:literal, $($field:ident).+) => {
+            if next.$($field).+ != current.$($field).+ {
+                pinned.push(format!(
+                    "{} ({:?} -> {:?})",
+                    $name,
+                    current.$($field).+,
+                    next.$($field).+
+                ));
+                next.$($field).+ = current.$($field).+.clone();
+            }
+        };
+    }
+
+    pin!("server.host", server.host);
+    pin!("server.port", server.port);
+    pin!("server.workers", server.workers);
+    pin!("server.tls", server.tls);
+    pin!("server.proxy.proxy_protocol", server.proxy.proxy_protocol);
+    pin!("server.connection_guard", server.connection_guard);
+    pin!("server.timeout", server.timeout);
+    pin!("server.header_timeout", server.header_timeout);
+    pin!("server.keep_alive", server.keep_alive);
+    pin!("server.max_request_size", server.max_request_size);
+    pin!("limits", limits);
+    pin!("logging.file", logging.file);
+    pin!("content.webroot", content.webroot);
+    pin!("development.dev_mode", development.dev_mode);
+    pin!("development.hot_reload", development.hot_reload);
+    pin!("development.debug_routes", development.debug_routes);
+    pin!("development.debug_host", development.debug_host);
+    pin!("development.debug_port", development.debug_port);
+    pin!(
+        "development.debug_allow_remote",
+        development.debug_allow_remote
+    );
+    pin!("development.state_dir", development.state_dir);
+    pin!("internal.prefix", internal.prefix);
+    pin!("search.enabled", search.enabled);
+    pin!("search.path", search.path);
+    pin!("shutdown.ctrlc", shutdown.ctrlc);
+    pin!("shutdown.signals", shutdown.signals);
+    pin!("shutdown.grace", shutdown.grace);
+    pin!("shutdown.mercy", shutdown.mercy);
+
+    pinned
+}
+
+/// Error from the most recent configuration reload, if it failed
+static RELOAD_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Record the outcome of a configuration reload (`None` means it succeeded)
+pub fn set_reload_error(error: Option<String>) {
+    if let Ok(mut last) = RELOAD_ERROR.lock() {
+        *last = error;
+    }
+}
+
+/// Get the error from the most recent configuration reload, if it failed
+pub fn last_reload_error() -> Option<String> {
+    RELOAD_ERROR.lock().ok().and_then(|last| last.clone())
+}
+
+/// Get the current global configuration
+pub fn get_config() -> Arc<Config> {
     GLOBAL_CONFIG
         .get()
         .expect("Configuration not initialized. Call init_config() first.")
+        .load_full()
 }
 
 /// Check if configuration has been initialized
@@ -633,6 +1940,7 @@ mod tests {
     fn test_default_config() {
         let config = Config::default();
         assert_eq!(config.server.port, 8000);
+        assert!(config.server.connection_guard);
         assert_eq!(config.logging.level, "info");
         assert_eq!(config.content.webroot, PathBuf::from("webroot"));
         assert!(config.content.markdown_enabled);
@@ -658,6 +1966,185 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_request_limits() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+
+        config.limits.file = ByteUnit::Mebibyte(20);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidLimit(_))
+        ));
+        config.limits.file = ByteUnit::Byte(0);
+        assert!(config.validate().is_err());
+
+        // Sizes and durations are parsed when the file is read
+        let text = toml::to_string(&Config::default()).unwrap();
+        let parsed: Config = toml::from_str(
+            &text
+                .replace(
+                    "max_request_size = 10485760",
+                    "max_request_size = \"10 MiB\"",
+                )
+                .replace("timeout = \"30s\"", "timeout = 45"),
+        )
+        .unwrap();
+        assert_eq!(parsed.server.max_request_size, ByteUnit::Mebibyte(10));
+        assert_eq!(parsed.server.timeout, TimeSpan::from_secs(45));
+        assert_eq!(parsed.limits, Config::default().limits);
+
+        let garbage = text.replace(
+            "max_request_size = 10485760",
+            "max_request_size = \"ten megs\"",
+        );
+        assert!(toml::from_str::<Config>(&garbage).is_err());
+        let garbage = text.replace("timeout = \"30s\"", "timeout = \"a while\"");
+        assert!(toml::from_str::<Config>(&garbage).is_err());
+    }
+
+    #[test]
+    fn test_html_allowlist_validation() {
+        let mut config = Config::default();
+        config.content.allow_html = true;
+        config.content.html.tags.push("custom-note".to_string());
+        assert!(config.validate().is_ok());
+
+        let invalid = |change: fn(&mut HtmlConfig)| {
+            let mut config = Config::default();
+            change(&mut config.content.html);
+            matches!(config.validate(), Err(ConfigError::InvalidHtmlAllowlist(_)))
+        };
+        assert!(invalid(|html| html.tags.push("SCRIPT".to_string())));
+        assert!(invalid(|html| html.tags.push("<b>".to_string())));
+        assert!(invalid(|html| {
+            html.attributes
+                .insert("*".to_string(), vec!["onclick".to_string()]);
+        }));
+        assert!(invalid(|html| {
+            html.attributes
+                .insert("a".to_string(), vec!["rel".to_string()]);
+        }));
+        assert!(invalid(|html| html
+            .url_schemes
+            .push("javascript".to_string())));
+    }
+
+    #[test]
+    fn test_internal_prefix_validation() {
+        assert!(is_valid_prefix("/_barleywine"));
+        assert!(is_valid_prefix("/ops/internal"));
+        assert!(!is_valid_prefix("/"));
+        assert!(!is_valid_prefix(""));
+        assert!(!is_valid_prefix("_barleywine"));
+        assert!(!is_valid_prefix("/_barleywine/"));
+        assert!(!is_valid_prefix("/with space"));
+    }
+
+    #[test]
+    fn test_debug_routes_require_loopback() {
+        let mut config = Config::default();
+        config.development.debug_routes = true;
+        assert!(config.validate().is_ok());
+
+        config.development.debug_host = "0.0.0.0".to_string();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::DebugRoutesNotLoopback(_))
+        ));
+
+        config.development.debug_allow_remote = true;
+        assert!(config.validate().is_ok());
+
+        config.development.debug_port = config.server.port;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_reload_pins_restart_only_settings() {
+        let current = Config::default();
+        let mut next = Config::default();
+        next.server.port = 9000;
+        next.server.header_timeout = TimeSpan::from_secs(60);
+        next.internal.prefix = "/_internal".to_string();
+        next.content.index_files = vec!["README.md".to_string()];
+        next.security.blocked_extensions.push(".bak".to_string());
+
+        let pinned = pin_restart_only(&current, &mut next);
+
+        assert_eq!(pinned.len(), 3);
+        assert!(pinned[0].starts_with("server.port"));
+        assert!(pinned[1].starts_with("server.header_timeout"));
+        assert!(pinned[2].starts_with("internal.prefix"));
+        assert_eq!(next.server.port, 8000);
+        assert_eq!(next.server.header_timeout, TimeSpan::from_secs(10));
+        assert_eq!(next.internal.prefix, "/_barleywine");
+        // Live settings are applied
+        assert_eq!(next.content.index_files, vec!["README.md".to_string()]);
+        assert!(
+            next.security
+                .blocked_extensions
+                .contains(&".bak".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shutdown_signal_validation() {
+        let mut config = Config::default();
+        config.shutdown.signals = vec!["term".to_string(), "int".to_string()];
+        assert!(config.validate().is_ok());
+
+        // SIGHUP is reserved for configuration reload
+        config.shutdown.signals = vec!["hup".to_string()];
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidShutdownSignal(_))
+        ));
+    }
+
+    #[test]
+    fn test_path_has_prefix() {
+        assert!(path_has_prefix("/docs", "/docs"));
+        assert!(path_has_prefix("/docs/api/index.md", "/docs"));
+        assert!(path_has_prefix("/docs/api", "/docs/"));
+        assert!(!path_has_prefix("/docs-old/index.md", "/docs"));
+        assert!(!path_has_prefix("/", "/docs"));
+        assert!(path_has_prefix("/anything", "/"));
+
+        // Encoded and doubled-slash spellings reach the same files
+        assert!(path_has_prefix(&normalize_path("/%64ocs/api"), "/docs"));
+        assert!(path_has_prefix(&normalize_path("/%64%6F%63%73"), "/docs"));
+        assert!(path_has_prefix(&normalize_path("//docs/api"), "/docs"));
+        assert!(path_has_prefix(&normalize_path("/./docs//api/"), "/docs"));
+        assert!(path_has_prefix(&normalize_path("/misc/../docs/a"), "/docs"));
+        assert!(!path_has_prefix(&normalize_path("/docs/../misc"), "/docs"));
+        assert!(!path_has_prefix(&normalize_path("/docs%2Dold/a"), "/docs"));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("/docs/"), "/docs");
+        assert_eq!(normalize_path("//a///b"), "/a/b");
+        assert_eq!(normalize_path("/a%2Fb/c"), "/a/b/c");
+        assert_eq!(normalize_path("/a%20b"), "/a b");
+        assert_eq!(normalize_path("/../../etc"), "/etc");
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*.example.com", "build.example.com"));
+        assert!(matches_pattern("*.Example.com", "a.b.example.COM"));
+        assert!(!matches_pattern("*.example.com", "example.com"));
+        assert!(matches_pattern("CN=*, O=Example", "CN=ci-1, O=Example"));
+        assert!(matches_pattern("ops@*", "ops@example.com"));
+        assert!(matches_pattern("*", "anything"));
+        assert!(matches_pattern("exact", "EXACT"));
+        assert!(!matches_pattern("exact", "exactly"));
+        assert!(!matches_pattern("a*b*c", "a-c-b"));
+    }
+
     #[test]
     fn test_extension_filtering() {
         let mut config = Config::default();
@@ -675,6 +2162,62 @@ mod tests {
         assert!(!config.is_extension_allowed(".env")); // Still blocked
     }
 
+    #[test]
+    fn test_hotlink_rules() {
+        let mut config = Config::default();
+        config.security.hotlink = vec![
+            HotlinkRule {
+                prefix: Some("/media".to_string()),
+                extensions: vec![],
+                allowed_domains: vec![],
+                allow_empty: false,
+                replacement: None,
+            },
+            HotlinkRule {
+                prefix: None,
+                extensions: vec![".jpg".to_string(), ".mp4".to_string()],
+                allowed_domains: vec!["partner.example".to_string(), "*.cdn.example".to_string()],
+                allow_empty: true,
+                replacement: Some("/hotlink.png".to_string()),
+            },
+        ];
+        assert!(config.validate().is_ok());
+
+        assert!(config.hotlink_rule("/index.html").is_none());
+        assert!(
+            config
+                .hotlink_rule("/media/clip.webm")
+                .unwrap()
+                .prefix
+                .is_some()
+        );
+        let rule = config.hotlink_rule("/photos/cat.JPG").unwrap();
+        assert!(rule.replacement.is_some());
+
+        let host = Some("ourdomain.example");
+        assert!(rule.allows(None, host));
+        assert!(rule.allows(Some(""), host));
+        assert!(rule.allows(Some("https://ourdomain.example:8443/gallery"), host));
+        assert!(rule.allows(Some("https://partner.example/page"), host));
+        assert!(rule.allows(Some("https://img.cdn.example"), host));
+        assert!(!rule.allows(Some("https://cdn.example/"), host));
+        assert!(!rule.allows(Some("https://partner.example.evil.test/"), host));
+        assert!(!rule.allows(Some("https://ourdomain.example@evil.test/"), host));
+        assert!(!rule.allows(Some("null"), host));
+
+        let media = config.hotlink_rule("/media/clip.webm").unwrap();
+        assert!(!media.allows(None, host));
+        assert!(config.hotlink_rule("/%6Dedia/clip.webm").is_some());
+        assert!(config.hotlink_rule("//media/clip.webm").is_some());
+        assert!(config.hotlink_rule("/photos/cat%2Ejpg").is_some());
+
+        config.security.hotlink[0].prefix = None;
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidHotlink(_))
+        ));
+    }
+
     #[test]
     fn test_config_file_loading() {
         let temp_dir = std::env::temp_dir();
@@ -0,0 +1,378 @@
+use crate::config::{self, Config, CorsPathRule};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method};
+use rocket::{Request, Responder, Response, Route, options, routes};
+
+/// Methods the file server answers, advertised to plain OPTIONS requests
+const ALLOW: &str = "GET, HEAD, OPTIONS";
+
+/// The CORS policy in effect for a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: u64,
+}
+
+impl Policy {
+    /// Whether any origin is allowed without credentials, so responses
+    /// don't depend on the requesting origin
+    fn is_public(&self) -> bool {
+        !self.credentials && self.origins.iter().any(|origin| origin == "*")
+    }
+}
+
+/// Adds CORS headers to responses and answers preflights
+pub struct Cors;
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let config = config::get_config();
+        let Some(policy) = policy_for(&config, request.uri().path().as_str()) else {
+            return;
+        };
+
+        // Caches must keep responses for different origins apart
+        if !policy.is_public() {
+            response.adjoin_header(Header::new("Vary", "Origin"));
+        }
+
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+        if !policy
+            .origins
+            .iter()
+            .any(|pattern| origin_matches(pattern, origin))
+        {
+            return;
+        }
+
+        let requested_method = request.headers().get_one("Access-Control-Request-Method");
+        match (request.method(), requested_method) {
+            (Method::Options, Some(method)) => {
+                if !policy
+                    .methods
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(method))
+                {
+                    return;
+                }
+                response.set_header(Header::new(
+                    "Access-Control-Allow-Methods",
+                    policy.methods.join(", "),
+                ));
+                if !policy.headers.is_empty() {
+                    response.set_header(Header::new(
+                        "Access-Control-Allow-Headers",
+                        policy.headers.join(", "),
+                    ));
+                }
+                response.set_header(Header::new(
+                    "Access-Control-Max-Age",
+                    policy.max_age.to_string(),
+                ));
+            }
+            _ => {
+                if !policy.expose_headers.is_empty() {
+                    response.set_header(Header::new(
+                        "Access-Control-Expose-Headers",
+                        policy.expose_headers.join(", "),
+                    ));
+                }
+            }
+        }
+
+        let allow_origin = if policy.is_public() { "*" } else { origin };
+        response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            allow_origin.to_string(),
+        ));
+        if policy.credentials {
+            response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+    }
+}
+
+/// Empty answer to an OPTIONS request; the fairing adds any CORS headers
+#[derive(Responder)]
+#[response(status = 204)]
+pub struct Preflight {
+    inner: (),
+    allow: Header<'static>,
+}
+
+/// Answer OPTIONS for every path so browser preflights succeed
+#[options("/<_..>")]
+fn preflight() -> Preflight {
+    Preflight {
+        inner: (),
+        allow: Header::new("Allow", ALLOW),
+    }
+}
+
+/// Routes to mount alongside the file server
+pub fn routes() -> Vec<Route> {
+    routes![preflight]
+}
+
+/// Whether CORS is turned on for this configuration
+pub fn is_enabled(config: &Config) -> bool {
+    config.security.cors_enabled && config.middleware.cors
+}
+
+/// The policy for a path: the longest matching `[[security.cors.paths]]` rule
+/// layered over the global settings, or `None` when CORS is off there. The
+/// path is normalized first so encoded spellings of a prefix match its rule.
+pub fn policy_for(config: &Config, path: &str) -> Option<Policy> {
+    if !is_enabled(config) {
+        return None;
+    }
+
+    let path = config::normalize_path(path);
+    let cors = &config.security.cors;
+    let rule = cors
+        .paths
+        .iter()
+        .filter(|rule| config::path_has_prefix(&path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.trim_end_matches('/').len());
+    match rule {
+        Some(rule) if !rule.enabled => None,
+        Some(rule) => Some(merge(config, rule)),
+        None => Some(merge(config, &CorsPathRule::default())),
+    }
+}
+
+fn merge(config: &Config, rule: &CorsPathRule) -> Policy {
+    let cors = &config.security.cors;
+    Policy {
+        origins: rule
+            .origins
+            .clone()
+            .unwrap_or_else(|| config.security.cors_origins.clone()),
+        methods: rule.methods.clone().unwrap_or_else(|| cors.methods.clone()),
+        headers: rule.headers.clone().unwrap_or_else(|| cors.headers.clone()),
+        expose_headers: rule
+            .expose_headers
+            .clone()
+            .unwrap_or_else(|| cors.expose_headers.clone()),
+        credentials: rule.credentials.unwrap_or(cors.credentials),
+        max_age: rule.max_age.unwrap_or(cors.max_age),
+    }
+}
+
+/// Match an `Origin` header against an allowed origin: `*`, an exact origin
+/// such as `https://example.com`, or a wildcard subdomain such as
+/// `https://*.example.com` (which does not match `https://example.com`)
+pub fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" || pattern.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+
+    let (Some((scheme, host)), Some((origin_scheme, origin_host))) =
+        (pattern.split_once("://"), origin.split_once("://"))
+    else {
+        return false;
+    };
+    let Some(suffix) = host.strip_prefix('*') else {
+        return false;
+    };
+
+    let origin_host = origin_host.to_ascii_lowercase();
+    let suffix = suffix.to_ascii_lowercase();
+    scheme.eq_ignore_ascii_case(origin_scheme)
+        && origin_host.len() > suffix.len()
+        && origin_host.ends_with(&suffix)
+        && !origin_host[..origin_host.len() - suffix.len()].contains(':')
+}
+
+/// Check every CORS policy the configuration can produce
+pub fn validate(config: &Config) -> Result<(), String> {
+    let policies = std::iter::once(merge(config, &CorsPathRule::default())).chain(
+        config
+            .security
+            .cors
+            .paths
+            .iter()
+            .map(|rule| merge(config, rule)),
+    );
+
+    // Credentials for any origin only matter once a policy is sent
+    let enabled = is_enabled(config);
+    for policy in policies {
+        for origin in &policy.origins {
+            if !is_valid_origin_pattern(origin) {
+                return Err(format!(
+                    "origin '{}' must be \"*\", an origin like \"https://example.com\" or a wildcard like \"https://*.example.com\"",
+                    origin
+                ));
+            }
+        }
+        if enabled && policy.credentials && policy.origins.iter().any(|origin| origin == "*") {
+            return Err(
+                "credentials cannot be allowed for any origin (\"*\"); list the origins instead"
+                    .to_string(),
+            );
+        }
+        if let Some(method) = policy
+            .methods
+            .iter()
+            .find(|method| method.is_empty() || !method.chars().all(|c| c.is_ascii_alphabetic()))
+        {
+            return Err(format!("invalid method '{}'", method));
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_origin_pattern(pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let Some((scheme, host)) = pattern.split_once("://") else {
+        return false;
+    };
+    let host = host.strip_prefix("*.").unwrap_or(host);
+    !scheme.is_empty()
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        && !host.is_empty()
+        && !host.contains(['/', '*', '?', '#'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    fn cors_config() -> Config {
+        let mut config = Config::default();
+        config.security.cors_enabled = true;
+        config.middleware.cors = true;
+        config.security.cors_origins = vec!["https://*.example.com".to_string()];
+        config.security.cors.paths = vec![
+            CorsPathRule {
+                prefix: "/api".to_string(),
+                origins: Some(vec!["*".to_string()]),
+                max_age: Some(60),
+                ..CorsPathRule::default()
+            },
+            CorsPathRule {
+                prefix: "/private".to_string(),
+                enabled: false,
+                ..CorsPathRule::default()
+            },
+        ];
+        config
+    }
+
+    #[test]
+    fn test_origin_matches() {
+        assert!(origin_matches("*", "https://anything.test"));
+        assert!(origin_matches("https://example.com", "https://EXAMPLE.com"));
+        assert!(!origin_matches("https://example.com", "http://example.com"));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://app.example.com"
+        ));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://a.b.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://evilexample.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "http://app.example.com"
+        ));
+        assert!(origin_matches(
+            "https://*.example.com:8443",
+            "https://app.example.com:8443"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://app.example.com:8443"
+        ));
+    }
+
+    #[test]
+    fn test_policy_for_paths() {
+        let config = cors_config();
+        let site = policy_for(&config, "/index.html").unwrap();
+        assert_eq!(site.origins, vec!["https://*.example.com"]);
+        assert_eq!(site.max_age, 86400);
+
+        let api = policy_for(&config, "/api/data.json").unwrap();
+        assert_eq!(api.origins, vec!["*"]);
+        assert_eq!(api.max_age, 60);
+        assert_eq!(api.methods, site.methods);
+
+        assert!(policy_for(&config, "/private/notes.md").is_none());
+        assert!(policy_for(&config, "/%70rivate/notes.md").is_none());
+        assert!(policy_for(&config, "//private/notes.md").is_none());
+        assert_eq!(policy_for(&config, "/%61pi/data.json").unwrap().max_age, 60);
+        assert!(policy_for(&Config::default(), "/index.html").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = cors_config();
+        assert!(validate(&config).is_ok());
+
+        config.security.cors.credentials = true;
+        assert!(validate(&config).unwrap_err().contains("credentials"));
+        // Not checked while CORS is off
+        config.security.cors_enabled = false;
+        assert!(validate(&config).is_ok());
+        config.security.cors_enabled = true;
+
+        config.security.cors.credentials = false;
+        config.security.cors_origins = vec!["example.com".to_string()];
+        assert!(validate(&config).is_err());
+
+        config.security.cors_origins = vec!["https://example.com".to_string()];
+        config.security.cors.methods = vec!["GET POST".to_string()];
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn test_options_responder() {
+        let _ = config::init_config(None);
+        let rocket = rocket::build().attach(Cors).mount("/", routes());
+        let client = Client::tracked(rocket).unwrap();
+        let response = client
+            .options("/data/report.json")
+            .header(Header::new("Origin", "https://app.example.com"))
+            .header(Header::new("Access-Control-Request-Method", "GET"))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(response.headers().get_one("Allow"), Some(ALLOW));
+        // CORS is off by default, so no cross-origin access is granted
+        assert!(
+            response
+                .headers()
+                .get_one("Access-Control-Allow-Origin")
+                .is_none()
+        );
+    }
+}
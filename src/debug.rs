@@ -0,0 +1,257 @@
+use crate::web::{self, JsonResponse, Resolution};
+use crate::{config, log, proxy};
+use rocket::http::Status;
+use rocket::http::uri::Origin;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Build, Request, Rocket, Route, State, get, routes};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// Placeholder for values removed from the configuration dump
+const REDACTED: &str = "[redacted]";
+
+/// Config key segments whose values are treated as secrets
+const SECRET_KEY_SEGMENTS: &[&str] = &["secret", "password", "passwd", "token", "key"];
+
+/// A route as mounted on the main server
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteInfo {
+    pub method: String,
+    pub uri: String,
+    pub rank: isize,
+    pub name: Option<String>,
+}
+
+/// Routes mounted on the main server, captured before launch
+pub struct RouteTable(pub Vec<RouteInfo>);
+
+/// Resolved route table response
+#[derive(Debug, Serialize)]
+pub struct RoutesReport<'a> {
+    pub routes: &'a [RouteInfo],
+    pub mappings: HashMap<String, String>,
+    pub redirects: HashMap<String, String>,
+    pub index_files: Vec<String>,
+}
+
+/// Explanation of how a URL would be served
+#[derive(Debug, Serialize)]
+pub struct ResolveReport {
+    pub url: String,
+    pub route: &'static str,
+    #[serde(flatten)]
+    pub resolution: Resolution,
+}
+
+/// A request header as received
+#[derive(Debug, Serialize)]
+pub struct EchoHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// The request as Barleywine sees it
+#[derive(Debug, Serialize)]
+pub struct Echo {
+    pub method: String,
+    pub uri: String,
+    pub remote_addr: Option<SocketAddr>,
+    pub client_ip: Option<IpAddr>,
+    pub headers: Vec<EchoHeader>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Echo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(Echo {
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            remote_addr: request.remote(),
+            client_ip: request.client_ip(),
+            headers: request
+                .headers()
+                .iter()
+                .map(|header| EchoHeader {
+                    name: header.name().to_string(),
+                    value: header.value().to_string(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Effective configuration with secrets redacted
+#[get("/config")]
+fn effective_config() -> Result<JsonResponse<Value>, Status> {
+    let mut value = serde_json::to_value(config::get_config().as_ref())
+        .map_err(|_| Status::InternalServerError)?;
+    redact_secrets(&mut value);
+    Ok(JsonResponse(value))
+}
+
+/// Routes mounted on the main server plus configured mappings and redirects
+#[get("/routes")]
+fn route_table(table: &State<RouteTable>) -> JsonResponse<RoutesReport<'_>> {
+    let config = config::get_config();
+    JsonResponse(RoutesReport {
+        routes: &table.0,
+        mappings: config.routes.mappings.clone(),
+        redirects: config.routes.redirects.clone(),
+        index_files: config.content.index_files.clone(),
+    })
+}
+
+/// Which file a URL would be served from, and why
+#[get("/resolve?<url>")]
+fn resolve(url: &str) -> Result<JsonResponse<ResolveReport>, (Status, String)> {
+    let config = config::get_config();
+    let origin = Origin::parse(url)
+        .map_err(|e| (Status::BadRequest, format!("Invalid URL '{}': {}", url, e)))?;
+    let path = origin.path();
+
+    // Internal endpoints never reach the file server
+    let prefix = config.internal.prefix.as_str();
+    if path == prefix || path.as_str().starts_with(&format!("{}/", prefix)) {
+        let resolution = Resolution {
+            path: None,
+            handling: None,
+            error: None,
+            page: None,
+            steps: vec![format!("Handled by internal endpoints under {}", prefix)],
+        };
+        return Ok(JsonResponse(ResolveReport {
+            url: url.to_string(),
+            route: "internal",
+            resolution,
+        }));
+    }
+
+    // Mirror the sanitisation Rocket applies to the `files` route
+    let file = path.segments().to_path_buf(false).map_err(|e| {
+        (
+            Status::BadRequest,
+            format!("Rejected path '{}': {:?}", url, e),
+        )
+    })?;
+    let mut resolution = web::resolve_path(&config, &file);
+
+    if let Some(target) = config.routes.redirects.get(path.as_str()) {
+        resolution
+            .steps
+            .insert(0, format!("Configured redirect to {}", target));
+    }
+    if let Some(target) = config.routes.mappings.get(path.as_str()) {
+        resolution
+            .steps
+            .insert(0, format!("Configured mapping to {}", target));
+    }
+
+    Ok(JsonResponse(ResolveReport {
+        url: url.to_string(),
+        route: if file.components().count() == 0 {
+            "index"
+        } else {
+            "files"
+        },
+        resolution,
+    }))
+}
+
+/// Request headers and client address as Barleywine sees them
+#[get("/echo")]
+fn echo(echo: Echo) -> JsonResponse<Echo> {
+    JsonResponse(echo)
+}
+
+/// Replace the values of secret-looking keys throughout a JSON tree
+pub fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if is_secret_key(key) && !child.is_null() {
+                    *child = Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(child);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    key.to_lowercase()
+        .split(['_', '-'])
+        .any(|segment| SECRET_KEY_SEGMENTS.contains(&segment))
+}
+
+/// Routes served by the debug listener
+pub fn routes() -> Vec<Route> {
+    routes![effective_config, route_table, resolve, echo]
+}
+
+/// Build the debug listener for a main server instance
+pub fn build_debug_rocket(main: &Rocket<Build>) -> Rocket<Build> {
+    let config = config::get_config();
+    let table = RouteTable(
+        main.routes()
+            .map(|route| RouteInfo {
+                method: route.method.to_string(),
+                uri: route.uri.to_string(),
+                rank: route.rank,
+                name: route.name.as_ref().map(|name| name.to_string()),
+            })
+            .collect(),
+    );
+
+    let figment = rocket::Config::figment()
+        .merge(("address", config.development.debug_host.as_str()))
+        .merge(("port", config.development.debug_port))
+        .merge(("ip_header", false));
+
+    log::log_barleywine(&format!(
+        "Debug routes enabled on {}:{}{}/debug",
+        config.development.debug_host, config.development.debug_port, config.internal.prefix
+    ));
+
+    rocket::custom(figment)
+        // Echo the client address the main server would resolve
+        .attach(proxy::ClientAddress)
+        .manage(table)
+        .mount(format!("{}/debug", config.internal.prefix), routes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut value = json!({
+            "auth": {
+                "signing_key": "abc123",
+                "password": "hunter2",
+                "realm": "internal"
+            },
+            "cache": { "cache_control": "public", "keep_alive": 5 },
+            "users": [{ "api-token": "t0k3n", "name": "ops" }],
+            "tls": { "key": null }
+        });
+        redact_secrets(&mut value);
+
+        assert_eq!(value["auth"]["signing_key"], REDACTED);
+        assert_eq!(value["auth"]["password"], REDACTED);
+        assert_eq!(value["auth"]["realm"], "internal");
+        assert_eq!(value["cache"]["cache_control"], "public");
+        assert_eq!(value["cache"]["keep_alive"], 5);
+        assert_eq!(value["users"][0]["api-token"], REDACTED);
+        assert_eq!(value["users"][0]["name"], "ops");
+        assert!(value["tls"]["key"].is_null());
+    }
+}
@@ -0,0 +1,444 @@
+use crate::collection::{self, Collection, Entry};
+use crate::config::Config;
+use crate::render::{self, escape_html};
+use crate::{frontmatter, web};
+use chrono::{DateTime, Utc};
+use rocket::http::{ContentType, RawStr};
+use serde_json::json;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Most entries a feed carries, newest first
+const FEED_LENGTH: usize = 20;
+
+/// Generated feeds by collection directory and format, with the
+/// fingerprint of the files they were built from
+type FeedCache = HashMap<(PathBuf, Format), (u64, String)>;
+
+static CACHE: Mutex<Option<FeedCache>> = Mutex::new(None);
+
+/// The feed formats served for every collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Rss, Format::Atom, Format::Json];
+
+    /// The file name the feed is served at inside its collection
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Format::Rss => "feed.xml",
+            Format::Atom => "atom.xml",
+            Format::Json => "feed.json",
+        }
+    }
+
+    /// How a resolved request for the feed is handled
+    pub fn handling(self) -> &'static str {
+        match self {
+            Format::Rss => "rss_feed",
+            Format::Atom => "atom_feed",
+            Format::Json => "json_feed",
+        }
+    }
+
+    pub fn from_handling(handling: &str) -> Option<Self> {
+        Format::ALL
+            .into_iter()
+            .find(|format| format.handling() == handling)
+    }
+
+    fn media_type(self) -> (&'static str, &'static str) {
+        match self {
+            Format::Rss => ("application", "rss+xml"),
+            Format::Atom => ("application", "atom+xml"),
+            Format::Json => ("application", "feed+json"),
+        }
+    }
+
+    pub fn content_type(self) -> ContentType {
+        let (top, sub) = self.media_type();
+        ContentType::new(top, sub)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Rss => "RSS",
+            Format::Atom => "Atom",
+            Format::Json => "JSON Feed",
+        }
+    }
+}
+
+/// The collection and format for a request such as `blog/feed.xml`
+pub fn request(config: &Config, file: &Path) -> Option<(Collection, Format)> {
+    let name = file.file_name()?.to_str()?;
+    let format = Format::ALL
+        .into_iter()
+        .find(|format| format.file_name() == name)?;
+    let dir = config.content.webroot.join(file.parent()?);
+    if !dir.is_dir() {
+        return None;
+    }
+    collection::find(config, &dir).map(|collection| (collection, format))
+}
+
+/// Percent-encode a URL path and put it under the site URL
+pub fn absolute(site_url: &str, path: &str) -> String {
+    let encoded: Vec<String> = path
+        .split('/')
+        .map(|segment| RawStr::new(segment).percent_encode().to_string())
+        .collect();
+    format!("{}{}", site_url.trim_end_matches('/'), encoded.join("/"))
+}
+
+/// When a file was last changed
+fn modified(path: &Path) -> Option<DateTime<Utc>> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(DateTime::<Utc>::from)
+}
+
+/// The markdown index of a collection
+fn index_file(config: &Config, collection: &Collection) -> Option<PathBuf> {
+    config
+        .content
+        .index_files
+        .iter()
+        .filter(|index| index.ends_with(".md"))
+        .map(|index| collection.dir.join(index))
+        .find(|index| index.is_file())
+}
+
+/// A collection's title: its index's front matter title or first heading,
+/// else the site title
+fn title(config: &Config, collection: &Collection) -> String {
+    index_file(config, collection)
+        .and_then(|index| web::read_to_string(config, &index).ok())
+        .and_then(|source| {
+            let (front_matter, body) = frontmatter::split(&source);
+            front_matter.title.or_else(|| render::title(body))
+        })
+        .unwrap_or_else(|| config.template.site_title.clone())
+}
+
+/// Fingerprint of everything a collection's feeds are built from: the
+/// names, sizes and modification times of its files and the site settings
+fn fingerprint(config: &Config, collection: &Collection) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config.template.site_url.hash(&mut hasher);
+    config.template.site_title.hash(&mut hasher);
+    config.template.site_description.hash(&mut hasher);
+
+    let mut files: Vec<(PathBuf, u64, Option<std::time::SystemTime>)> = Vec::new();
+    let mut note = |path: PathBuf| {
+        if let Ok(metadata) = fs::metadata(&path) {
+            files.push((path, metadata.len(), metadata.modified().ok()));
+        }
+    };
+    if let Ok(children) = fs::read_dir(&collection.dir) {
+        for child in children.flatten() {
+            let path = child.path();
+            if path.is_dir() {
+                for index in &config.content.index_files {
+                    note(path.join(index));
+                }
+            } else {
+                note(path);
+            }
+        }
+    }
+    files.sort();
+    files.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A collection's feed in the given format, from the cache while none of
+/// its files have changed. `None` when `template.site_url` isn't set.
+pub fn document(config: &Config, dir: &Path, format: Format) -> Option<String> {
+    let site_url = config.template.site_url.as_deref()?;
+    let collection = collection::find(config, dir)?;
+    let fingerprint = fingerprint(config, &collection);
+    let key = (collection.dir.clone(), format);
+    if let Ok(cache) = CACHE.lock()
+        && let Some((cached, document)) = cache.as_ref().and_then(|cache| cache.get(&key))
+        && *cached == fingerprint
+    {
+        return Some(document.clone());
+    }
+
+    let document = generate(config, site_url, &collection, format);
+    if let Ok(mut cache) = CACHE.lock() {
+        cache
+            .get_or_insert_with(HashMap::new)
+            .insert(key, (fingerprint, document.clone()));
+    }
+    Some(document)
+}
+
+/// An entry's last change: its date, else its file's modification time
+fn updated(entry: &Entry) -> DateTime<Utc> {
+    entry
+        .date
+        .or_else(|| modified(&entry.path))
+        .unwrap_or_default()
+}
+
+fn generate(config: &Config, site_url: &str, collection: &Collection, format: Format) -> String {
+    let mut entries = collection::entries(config, collection);
+    entries.truncate(FEED_LENGTH);
+    let title = title(config, collection);
+    let description = &config.template.site_description;
+    let home = absolute(site_url, &collection.url);
+    let self_url = absolute(
+        site_url,
+        &format!("{}{}", collection.url, format.file_name()),
+    );
+    let updated_at = entries
+        .iter()
+        .map(updated)
+        .max()
+        .or_else(|| modified(&collection.dir))
+        .unwrap_or_default();
+
+    match format {
+        Format::Rss => {
+            let mut xml = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+                 <channel>\n\
+                 <title>{}</title>\n<link>{}</link>\n<description>{}</description>\n\
+                 <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n\
+                 <lastBuildDate>{}</lastBuildDate>\n",
+                escape_html(&title),
+                escape_html(&home),
+                escape_html(description),
+                escape_html(&self_url),
+                updated_at.to_rfc2822()
+            );
+            for entry in &entries {
+                let link = escape_html(&absolute(site_url, &entry.url));
+                xml.push_str(&format!(
+                    "<item>\n<title>{}</title>\n<link>{}</link>\n<guid isPermaLink=\"true\">{}</guid>\n",
+                    escape_html(&entry.title),
+                    link,
+                    link
+                ));
+                if let Some(date) = entry.date {
+                    xml.push_str(&format!("<pubDate>{}</pubDate>\n", date.to_rfc2822()));
+                }
+                if let Some(ref author) = entry.author {
+                    xml.push_str(&format!(
+                        "<dc:creator>{}</dc:creator>\n",
+                        escape_html(author)
+                    ));
+                }
+                xml.push_str(&format!(
+                    "<description>{}</description>\n</item>\n",
+                    escape_html(&entry.summary)
+                ));
+            }
+            xml.push_str("</channel>\n</rss>\n");
+            xml
+        }
+        Format::Atom => {
+            let mut xml = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+                 <title>{}</title>\n<subtitle>{}</subtitle>\n\
+                 <link href=\"{}\"/>\n<link rel=\"self\" href=\"{}\"/>\n\
+                 <id>{}</id>\n<updated>{}</updated>\n\
+                 <author><name>{}</name></author>\n",
+                escape_html(&title),
+                escape_html(description),
+                escape_html(&home),
+                escape_html(&self_url),
+                escape_html(&home),
+                updated_at.to_rfc3339(),
+                escape_html(&config.template.site_title)
+            );
+            for entry in &entries {
+                let link = escape_html(&absolute(site_url, &entry.url));
+                xml.push_str(&format!(
+                    "<entry>\n<title>{}</title>\n<link href=\"{}\"/>\n<id>{}</id>\n<updated>{}</updated>\n",
+                    escape_html(&entry.title),
+                    link,
+                    link,
+                    updated(entry).to_rfc3339()
+                ));
+                if let Some(date) = entry.date {
+                    xml.push_str(&format!("<published>{}</published>\n", date.to_rfc3339()));
+                }
+                if let Some(ref author) = entry.author {
+                    xml.push_str(&format!(
+                        "<author><name>{}</name></author>\n",
+                        escape_html(author)
+                    ));
+                }
+                xml.push_str(&format!(
+                    "<summary>{}</summary>\n</entry>\n",
+                    escape_html(&entry.summary)
+                ));
+            }
+            xml.push_str("</feed>\n");
+            xml
+        }
+        Format::Json => {
+            let items: Vec<serde_json::Value> = entries
+                .iter()
+                .map(|entry| {
+                    let url = absolute(site_url, &entry.url);
+                    let mut item = json!({
+                        "id": url,
+                        "url": url,
+                        "title": entry.title,
+                        "summary": entry.summary,
+                        "content_text": entry.summary,
+                    });
+                    if let Some(date) = entry.date {
+                        item["date_published"] = json!(date.to_rfc3339());
+                    }
+                    if let Some(ref author) = entry.author {
+                        item["authors"] = json!([{ "name": author }]);
+                    }
+                    item
+                })
+                .collect();
+            let feed = json!({
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": title,
+                "home_page_url": home,
+                "feed_url": self_url,
+                "description": description,
+                "items": items,
+            });
+            serde_json::to_string_pretty(&feed).unwrap_or_default()
+        }
+    }
+}
+
+/// Add `<link rel="alternate">` tags for a collection's feeds to a page's
+/// `<head>`
+pub fn inject_links(html: &str, collection: &Collection) -> String {
+    let links: String = Format::ALL
+        .into_iter()
+        .map(|format| {
+            let (top, sub) = format.media_type();
+            format!(
+                "    <link rel=\"alternate\" type=\"{}/{}\" title=\"{}\" href=\"{}\">\n",
+                top,
+                sub,
+                format.name(),
+                escape_html(&absolute(
+                    "",
+                    &format!("{}{}", collection.url, format.file_name())
+                ))
+            )
+        })
+        .collect();
+    match html.to_ascii_lowercase().find("</head>") {
+        Some(index) => format!("{}{}{}", &html[..index], links, &html[index..]),
+        None => html.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(name: &str) -> (Config, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("barleywine_feed_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let blog = root.join("blog");
+        fs::create_dir_all(&blog).unwrap();
+        fs::write(
+            blog.join("index.md"),
+            "---\ntitle: Our <Blog>\ncollection: true\n---\nWelcome\n",
+        )
+        .unwrap();
+        fs::write(
+            blog.join("first post.md"),
+            "---\ntitle: First & best\ndate: 2024-01-10\nauthor: Ann\nsummary: The start\n---\nBody\n",
+        )
+        .unwrap();
+        fs::write(blog.join("draft.md"), "---\ndraft: true\n---\n# Hidden\n").unwrap();
+
+        let mut config = Config::default();
+        config.content.webroot = root.clone();
+        config.template.site_url = Some("https://example.com/".to_string());
+        (config, blog)
+    }
+
+    #[test]
+    fn test_feeds() {
+        let (config, blog) = site("formats");
+
+        let rss = document(&config, &blog, Format::Rss).unwrap();
+        assert!(rss.contains("<title>Our &lt;Blog&gt;</title>"));
+        assert!(rss.contains("<link>https://example.com/blog/first%20post.md</link>"));
+        assert!(rss.contains("<title>First &amp; best</title>"));
+        assert!(rss.contains("<pubDate>Wed, 10 Jan 2024 00:00:00 +0000</pubDate>"));
+        assert!(rss.contains("<dc:creator>Ann</dc:creator>"));
+        assert!(!rss.contains("Hidden"));
+
+        let atom = document(&config, &blog, Format::Atom).unwrap();
+        assert!(atom.contains("<link rel=\"self\" href=\"https://example.com/blog/atom.xml\"/>"));
+        assert!(atom.contains("<published>2024-01-10T00:00:00+00:00</published>"));
+        assert!(atom.contains("<summary>The start</summary>"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&document(&config, &blog, Format::Json).unwrap()).unwrap();
+        assert_eq!(json["feed_url"], "https://example.com/blog/feed.json");
+        assert_eq!(json["items"][0]["authors"][0]["name"], "Ann");
+        assert_eq!(json["items"].as_array().unwrap().len(), 1);
+
+        // Without a site URL there are no feeds
+        let mut config = config;
+        config.template.site_url = None;
+        assert!(document(&config, &blog, Format::Rss).is_none());
+
+        fs::remove_dir_all(blog.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_feed_cache_follows_changes() {
+        let (config, blog) = site("cache");
+        let before = document(&config, &blog, Format::Rss).unwrap();
+        assert_eq!(document(&config, &blog, Format::Rss).unwrap(), before);
+
+        fs::write(
+            blog.join("second.md"),
+            "---\ntitle: Second\ndate: 2024-02-01\n---\nMore\n",
+        )
+        .unwrap();
+        let after = document(&config, &blog, Format::Rss).unwrap();
+        assert!(after.contains("<title>Second</title>"));
+
+        fs::remove_dir_all(blog.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_inject_links() {
+        let collection = Collection {
+            dir: PathBuf::from("webroot/blog"),
+            url: "/blog/".to_string(),
+            per_page: 10,
+        };
+        let html = inject_links("<html><head><title>x</title></head></html>", &collection);
+        assert!(html.contains(
+            "<link rel=\"alternate\" type=\"application/rss+xml\" title=\"RSS\" href=\"/blog/feed.xml\">"
+        ));
+        assert!(html.contains("type=\"application/atom+xml\""));
+        assert!(html.contains("href=\"/blog/feed.json\">\n</head>"));
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::Deserialize;
+use toml::{Table, Value};
+
+/// Metadata at the top of a markdown page, between `---` lines as simple
+/// YAML or between `+++` lines as TOML
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    /// Page title, in place of the first `#` heading
+    pub title: Option<String>,
+    /// Publication date, such as `2024-01-15` or an RFC 3339 timestamp
+    pub date: Option<String>,
+    /// Short description for listings, in place of the first paragraph
+    pub summary: Option<String>,
+    /// Who wrote the page
+    pub author: Option<String>,
+    /// Unfinished pages are left out of listings
+    pub draft: bool,
+    /// Keep search engines away: left out of the sitemap and disallowed in
+    /// the generated `robots.txt`
+    pub noindex: bool,
+    /// On an index page, list the directory's pages below it
+    pub collection: bool,
+    /// Entries per page of a collection listing
+    pub per_page: Option<usize>,
+}
+
+impl FrontMatter {
+    /// The publication date, if it is set and readable
+    pub fn date_time(&self) -> Option<DateTime<Utc>> {
+        let date = self.date.as_deref()?.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(date) {
+            return Some(time.with_timezone(&Utc));
+        }
+        for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+            if let Ok(time) = NaiveDateTime::parse_from_str(date, format) {
+                return Some(time.and_utc());
+            }
+        }
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()
+            .and_then(|day| day.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc())
+    }
+}
+
+/// Split a page into its front matter and the markdown after it. A page
+/// without front matter, or with front matter that can't be read, is all
+/// body.
+pub fn split(source: &str) -> (FrontMatter, &str) {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    for (fence, is_toml) in [("---", false), ("+++", true)] {
+        let Some(rest) = source.strip_prefix(fence).and_then(|rest| {
+            rest.strip_prefix("\r\n")
+                .or_else(|| rest.strip_prefix('\n'))
+        }) else {
+            continue;
+        };
+
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            if line.trim_end() == fence {
+                let block = &rest[..offset];
+                let table = if is_toml {
+                    block.parse::<Table>().ok()
+                } else {
+                    yaml_table(block)
+                };
+                return match table.and_then(front_matter) {
+                    Some(front_matter) => (front_matter, &rest[offset + line.len()..]),
+                    None => (FrontMatter::default(), source),
+                };
+            }
+            offset += line.len();
+        }
+    }
+    (FrontMatter::default(), source)
+}
+
+fn front_matter(mut table: Table) -> Option<FrontMatter> {
+    // TOML dates are read as the text they were written as
+    for (_, value) in table.iter_mut() {
+        if let Value::Datetime(date) = value {
+            *value = Value::String(date.to_string());
+        }
+    }
+    Value::Table(table).try_into().ok()
+}
+
+/// Read the `key: value` lines of YAML front matter. Nested blocks are
+/// skipped; anything else that isn't a `key: value` line fails.
+fn yaml_table(block: &str) -> Option<Table> {
+    let mut table = Table::new();
+    for line in block.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if line.starts_with([' ', '\t', '-']) {
+            continue;
+        }
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return None;
+        }
+        let value = value.trim();
+        if !value.is_empty() {
+            table.insert(key.to_string(), yaml_value(value));
+        }
+    }
+    Some(table)
+}
+
+fn yaml_value(value: &str) -> Value {
+    if let Some(items) = value
+        .strip_prefix('[')
+        .and_then(|items| items.strip_suffix(']'))
+    {
+        return Value::Array(
+            items
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(yaml_value)
+                .collect(),
+        );
+    }
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            let inner = &value[1..value.len() - 1];
+            return Value::String(if quote == '"' {
+                inner.replace("\\\"", "\"")
+            } else {
+                inner.replace("''", "'")
+            });
+        }
+    }
+    match value {
+        "true" | "yes" => Value::Boolean(true),
+        "false" | "no" => Value::Boolean(false),
+        _ => value
+            .parse::<i64>()
+            .map(Value::Integer)
+            .unwrap_or_else(|_| Value::String(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let page = "---\ntitle: \"Rust: a love letter\"\ndate: 2024-01-15\ndraft: true\ntags: [rust, web]\nextra:\n  nested: ignored\n---\n# Heading\n";
+        let (front_matter, body) = split(page);
+        assert_eq!(front_matter.title.as_deref(), Some("Rust: a love letter"));
+        assert_eq!(front_matter.date.as_deref(), Some("2024-01-15"));
+        assert!(front_matter.draft);
+        assert_eq!(body, "# Heading\n");
+        assert_eq!(
+            front_matter.date_time().unwrap().to_rfc3339(),
+            "2024-01-15T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_toml_front_matter() {
+        let page = "+++\ntitle = 'Blog'\ncollection = true\nper_page = 5\ndate = 2024-02-01T10:00:00Z\n+++\nBody";
+        let (front_matter, body) = split(page);
+        assert_eq!(front_matter.title.as_deref(), Some("Blog"));
+        assert!(front_matter.collection);
+        assert_eq!(front_matter.per_page, Some(5));
+        assert_eq!(
+            front_matter.date_time().unwrap().to_rfc3339(),
+            "2024-02-01T10:00:00+00:00"
+        );
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn test_without_front_matter() {
+        // A thematic break is not front matter
+        let page = "---\n\nJust a rule above.\n";
+        assert_eq!(split(page), (FrontMatter::default(), page));
+        let page = "# Title\n---\nkey: value\n---\n";
+        assert_eq!(split(page), (FrontMatter::default(), page));
+        // Unreadable front matter leaves the page as it is
+        let page = "+++\ntitle = \n+++\nBody";
+        assert_eq!(split(page), (FrontMatter::default(), page));
+    }
+}
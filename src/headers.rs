@@ -0,0 +1,203 @@
+use crate::config::{self, Config};
+use crate::proxy;
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+/// Bytes of randomness in a CSP nonce
+const NONCE_BYTES: usize = 16;
+
+/// The nonce a rendered page used for its inline `<style>` and `<script>`
+/// elements. Pages stash it in the request's local cache while responding so
+/// the fairing can allow exactly those elements.
+#[derive(Debug, Clone, Default)]
+pub struct CspNonce(pub Option<String>);
+
+/// Adds the configured security headers to every response
+pub struct SecurityHeaders;
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Security Headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let config = config::get_config();
+        if !is_enabled(&config) {
+            return;
+        }
+
+        let headers = &config.security.headers;
+        let nonce = request.local_cache(CspNonce::default);
+        let policy = match nonce.0 {
+            Some(ref nonce) => csp_with_nonce(&headers.content_security_policy, nonce),
+            None => headers.content_security_policy.clone(),
+        };
+
+        let mut set = |name: &'static str, value: String| {
+            // Leave headers a handler chose deliberately alone
+            if !value.is_empty() && !response.headers().contains(name) {
+                response.set_header(Header::new(name, value));
+            }
+        };
+        set("Content-Security-Policy", policy);
+        set(
+            "X-Content-Type-Options",
+            headers.content_type_options.clone(),
+        );
+        set("X-Frame-Options", headers.frame_options.clone());
+        set("Referrer-Policy", headers.referrer_policy.clone());
+        set("Permissions-Policy", headers.permissions_policy.clone());
+        // Browsers ignore HSTS over plain HTTP, and it must not outlive a TLS
+        // setup, whether our own or a trusted proxy's
+        if proxy::is_https(request, &config) {
+            set(
+                "Strict-Transport-Security",
+                headers.strict_transport_security.clone(),
+            );
+        }
+    }
+}
+
+/// Whether security headers are turned on for this configuration
+pub fn is_enabled(config: &Config) -> bool {
+    config.security.security_headers && config.middleware.security
+}
+
+/// A fresh nonce for a rendered page, or `None` when no CSP is sent
+pub fn page_nonce(config: &Config) -> Option<String> {
+    if !is_enabled(config) || config.security.headers.content_security_policy.is_empty() {
+        return None;
+    }
+
+    let mut bytes = [0u8; NONCE_BYTES];
+    SystemRandom::new().fill(&mut bytes).ok()?;
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Add a nonce source to the policy's `script-src` and `style-src`. A missing
+/// directive is created from `default-src` so other sources stay allowed; with
+/// neither present inline elements are already allowed and nothing changes.
+/// Directives allowing `'unsafe-inline'` are left alone too, since browsers
+/// ignore it once a nonce is present and un-nonced inline elements would break.
+pub fn csp_with_nonce(policy: &str, nonce: &str) -> String {
+    let source = format!("'nonce-{}'", nonce);
+    let mut directives: Vec<String> = policy
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(str::to_string)
+        .collect();
+    let default_sources = directives
+        .iter()
+        .find_map(|directive| directive.strip_prefix("default-src "))
+        .map(str::to_string);
+
+    for name in ["script-src", "style-src"] {
+        let prefix = format!("{} ", name);
+        match directives
+            .iter_mut()
+            .find(|directive| directive.starts_with(&prefix) || directive.as_str() == name)
+        {
+            Some(directive) if allows_unsafe_inline(directive) => {}
+            Some(directive) => {
+                directive.push(' ');
+                directive.push_str(&source);
+            }
+            None => {
+                if let Some(ref sources) = default_sources
+                    && !allows_unsafe_inline(sources)
+                {
+                    directives.push(format!("{} {} {}", name, sources, source));
+                }
+            }
+        }
+    }
+    directives.join("; ")
+}
+
+/// Whether a directive or source list allows every inline element
+fn allows_unsafe_inline(sources: &str) -> bool {
+    sources
+        .split_whitespace()
+        .any(|source| source.eq_ignore_ascii_case("'unsafe-inline'"))
+}
+
+/// ` nonce="..."` for an inline element, or nothing
+pub fn nonce_attribute(nonce: Option<&str>) -> String {
+    nonce
+        .map(|nonce| format!(" nonce=\"{}\"", nonce))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::FileResponse;
+    use rocket::local::blocking::Client;
+    use rocket::response::content::RawHtml;
+    use rocket::shield::Shield;
+    use rocket::{get, routes};
+
+    #[test]
+    fn test_csp_with_nonce() {
+        assert_eq!(
+            csp_with_nonce("default-src 'self'; img-src 'self' data:", "abc"),
+            "default-src 'self'; img-src 'self' data:; script-src 'self' 'nonce-abc'; style-src 'self' 'nonce-abc'"
+        );
+        assert_eq!(
+            csp_with_nonce("script-src 'self'; style-src https://cdn.test;", "abc"),
+            "script-src 'self' 'nonce-abc'; style-src https://cdn.test 'nonce-abc'"
+        );
+        assert_eq!(csp_with_nonce("img-src *", "abc"), "img-src *");
+        assert_eq!(
+            csp_with_nonce(
+                "default-src 'self'; style-src 'self' 'unsafe-inline'",
+                "abc"
+            ),
+            "default-src 'self'; style-src 'self' 'unsafe-inline'; script-src 'self' 'nonce-abc'"
+        );
+        assert_eq!(
+            csp_with_nonce("default-src 'self' 'unsafe-inline'", "abc"),
+            "default-src 'self' 'unsafe-inline'"
+        );
+    }
+
+    #[get("/page")]
+    fn page() -> FileResponse {
+        FileResponse::Html(
+            RawHtml("<p>page</p>".to_string()),
+            Some("n0nce".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_security_headers_fairing() {
+        let _ = config::init_config(None);
+        let rocket = rocket::build()
+            .attach(Shield::new())
+            .attach(SecurityHeaders)
+            .mount("/", routes![page]);
+        let client = Client::tracked(rocket).unwrap();
+        let response = client.get("/page").dispatch();
+        let headers = response.headers();
+
+        let csp = headers.get_one("Content-Security-Policy").unwrap();
+        assert!(csp.contains("script-src 'self' 'nonce-n0nce'"));
+        // The default policy leaves the template's <style> to the nonce alone
+        assert!(csp.contains("style-src 'self' 'nonce-n0nce'"));
+        assert!(!csp.contains("'unsafe-inline'"));
+        assert!(csp.contains("frame-ancestors 'none'"));
+        assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(headers.get_one("X-Frame-Options"), Some("DENY"));
+        assert!(headers.get_one("Referrer-Policy").is_some());
+        assert!(headers.get_one("Permissions-Policy").is_some());
+        // Plain HTTP never advertises HSTS
+        assert!(headers.get_one("Strict-Transport-Security").is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::web::JsonResponse;
+use crate::{config, log};
+use rocket::http::Status;
+use rocket::http::uri::Origin;
+use rocket::{Route, get, routes};
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// Liveness response
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+/// Result of a single readiness check
+#[derive(Debug, Serialize)]
+pub struct ReadinessCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Readiness response
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+/// Build information reported by the version endpoint
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    /// Build information captured at compile time
+    pub fn current() -> Self {
+        VersionInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: env!("BARLEYWINE_GIT_HASH"),
+            features: env!("BARLEYWINE_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// Liveness probe: answers as long as the server is handling requests
+#[get("/healthz")]
+fn healthz(remote_addr: Option<SocketAddr>, origin: &Origin<'_>) -> JsonResponse<Health> {
+    log_internal_access(remote_addr, origin, Status::Ok);
+    JsonResponse(Health { status: "ok" })
+}
+
+/// Readiness probe: fails when the server cannot usefully serve traffic
+#[get("/readyz")]
+fn readyz(
+    remote_addr: Option<SocketAddr>,
+    origin: &Origin<'_>,
+) -> (Status, JsonResponse<Readiness>) {
+    let readiness = check_readiness();
+    let status = if readiness.ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    log_internal_access(remote_addr, origin, status);
+    (status, JsonResponse(readiness))
+}
+
+/// Build information: crate version, git hash and enabled features
+#[get("/version")]
+fn version(remote_addr: Option<SocketAddr>, origin: &Origin<'_>) -> JsonResponse<VersionInfo> {
+    log_internal_access(remote_addr, origin, Status::Ok);
+    JsonResponse(VersionInfo::current())
+}
+
+/// Run all readiness checks
+pub fn check_readiness() -> Readiness {
+    let config = config::get_config();
+
+    let webroot = &config.content.webroot;
+    let webroot_check = ReadinessCheck {
+        name: "webroot",
+        ok: webroot.is_dir(),
+        detail: (!webroot.is_dir())
+            .then(|| format!("webroot directory missing: {}", webroot.display())),
+    };
+
+    let logs = log::check_log_health();
+    let logs_check = ReadinessCheck {
+        name: "logs",
+        ok: logs.is_ok(),
+        detail: logs.err(),
+    };
+
+    let reload_error = config::last_reload_error();
+    let config_check = ReadinessCheck {
+        name: "config",
+        ok: reload_error.is_none(),
+        detail: reload_error.map(|e| format!("configuration reload failed: {}", e)),
+    };
+
+    let checks = vec![webroot_check, logs_check, config_check];
+    Readiness {
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+/// Write an access log entry for an internal endpoint, if enabled
+pub fn log_internal_access(remote_addr: Option<SocketAddr>, origin: &Origin<'_>, status: Status) {
+    if !config::get_config().internal.access_log {
+        return;
+    }
+
+    let addr_str = remote_addr
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    log::log_access(
+        &addr_str,
+        "GET",
+        origin.path().as_str(),
+        status.code,
+        None,
+        None,
+    );
+}
+
+/// Routes to mount under the internal endpoint prefix
+pub fn routes() -> Vec<Route> {
+    routes![healthz, readyz, version]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    fn client() -> Client {
+        let _ = config::init_config(None);
+        let rocket = rocket::build().mount(&config::get_config().internal.prefix, routes());
+        Client::tracked(rocket).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn test_healthz() {
+        let client = client();
+        let response = client.get("/_barleywine/healthz").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("\"ok\""));
+    }
+
+    #[test]
+    fn test_version() {
+        let client = client();
+        let response = client.get("/_barleywine/version").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().unwrap();
+        assert!(body.contains(env!("CARGO_PKG_VERSION")));
+        assert!(body.contains("git_hash"));
+    }
+
+    #[test]
+    fn test_readiness_reports_reload_failure() {
+        let _ = config::init_config(None);
+        config::set_reload_error(Some("bad toml".to_string()));
+        let readiness = check_readiness();
+        config::set_reload_error(None);
+
+        assert!(!readiness.ready);
+        let config_check = readiness
+            .checks
+            .iter()
+            .find(|check| check.name == "config")
+            .unwrap();
+        assert!(!config_check.ok);
+        assert!(config_check.detail.as_ref().unwrap().contains("bad toml"));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::config;
+use crate::{log, symlinks, web};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, Response};
+use std::io::Read;
+use std::path::Path;
+
+/// The image to send in place of a refused hotlinked file, set by the
+/// guard for the fairing
+#[derive(Debug, Clone, Default)]
+struct Replacement(Option<String>);
+
+/// Request guard applying `[[security.hotlink]]`: files a rule covers may
+/// only be fetched from our own pages, the listed domains and, when allowed,
+/// without a Referer
+pub struct Hotlink;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Hotlink {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        let path = request.uri().path();
+        let Some(rule) = config.hotlink_rule(path.as_str()) else {
+            return Outcome::Success(Hotlink);
+        };
+
+        // Requests from scripts may carry only an Origin
+        let headers = request.headers();
+        let referer = headers
+            .get_one("Referer")
+            .or_else(|| headers.get_one("Origin"));
+        let host = request.host().map(|host| host.domain().to_string());
+        if rule.allows(referer, host.as_deref()) {
+            return Outcome::Success(Hotlink);
+        }
+
+        let addr_str = request
+            .remote()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        log::log_barleywine(&format!(
+            "Hotlinked request for {} refused from {}: referred by {}",
+            path,
+            addr_str,
+            referer.unwrap_or("nothing")
+        ));
+        if let Some(ref replacement) = rule.replacement {
+            request.local_cache(|| Replacement(Some(replacement.clone())));
+        }
+        web::log_denied(request, Status::Forbidden, None);
+        Outcome::Error((Status::Forbidden, "hotlinked".to_string()))
+    }
+}
+
+/// Sends the configured replacement image as the body of refused hotlinks
+pub struct HotlinkReplacement;
+
+#[rocket::async_trait]
+impl Fairing for HotlinkReplacement {
+    fn info(&self) -> Info {
+        Info {
+            name: "Hotlink Replacement",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(ref replacement) = request.local_cache(Replacement::default).0 else {
+            return;
+        };
+        if response.status() != Status::Forbidden {
+            return;
+        }
+
+        let config = config::get_config();
+        let resolution = web::resolve_path(&config, Path::new(replacement.trim_start_matches('/')));
+        let Some(path) = resolution.path.filter(|_| resolution.error.is_none()) else {
+            log::log_warning(&format!(
+                "Hotlink replacement {} can't be served: {}",
+                replacement,
+                resolution.error.unwrap_or_default()
+            ));
+            return;
+        };
+        let mut image = Vec::new();
+        let read = symlinks::open(&config.content.webroot, &path, config.security.symlinks)
+            .and_then(|mut file| file.read_to_end(&mut image));
+        if let Err(e) = read {
+            log::log_warning(&format!(
+                "Hotlink replacement {} can't be read: {}",
+                replacement, e
+            ));
+            return;
+        }
+
+        // The status stays 403; browsers still draw an image body
+        let content_type = path
+            .extension()
+            .and_then(|extension| ContentType::from_extension(&extension.to_string_lossy()))
+            .unwrap_or(ContentType::Binary);
+        response.set_header(content_type);
+        response.set_header(Header::new("Cache-Control", "no-store"));
+        response.set_sized_body(image.len(), std::io::Cursor::new(image));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::config::{self, IpAction, IpRule};
+use crate::{log, web};
+use rocket::Request;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use std::net::IpAddr;
+
+/// An IPv4 or IPv6 network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Parse `192.0.2.0/24`, `2001:db8::/32` or a single address
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (address, prefix_len) = match text.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (text, None),
+        };
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address or CIDR network", text))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("'{}' has an invalid prefix length", text))?,
+            None => max_len,
+        };
+        Ok(Cidr {
+            network: network.to_canonical(),
+            prefix_len,
+        })
+    }
+
+    /// Whether `ip` is inside this network. IPv4-mapped IPv6 addresses match
+    /// IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse an entry of an IP rule's `cidrs`: a network, or `all` for every
+/// IPv4 and IPv6 address
+pub fn parse_networks(entry: &str) -> Result<Vec<Cidr>, String> {
+    if entry.eq_ignore_ascii_case("all") {
+        return Ok(vec![Cidr::parse("0.0.0.0/0")?, Cidr::parse("::/0")?]);
+    }
+    Ok(vec![Cidr::parse(entry)?])
+}
+
+/// The first rule covering `path` whose networks contain `ip`, with its
+/// position in the configuration. Clients no rule matches are allowed. The
+/// path is normalized first so encoded spellings of a prefix are covered too.
+pub fn matching_rule<'a>(
+    rules: &'a [IpRule],
+    path: &str,
+    ip: IpAddr,
+) -> Option<(usize, &'a IpRule)> {
+    let path = config::normalize_path(path);
+    rules.iter().enumerate().find(|(_, rule)| {
+        config::path_has_prefix(&path, &rule.prefix)
+            && rule
+                .cidrs
+                .iter()
+                .filter_map(|entry| parse_networks(entry).ok())
+                .flatten()
+                .any(|cidr| cidr.contains(ip))
+    })
+}
+
+/// Whether a client may fetch `path` under the IP rules. Clients without a
+/// known address are allowed, as the request guard allows them.
+pub fn is_allowed(rules: &[IpRule], path: &str, ip: Option<IpAddr>) -> bool {
+    ip.and_then(|ip| matching_rule(rules, path, ip))
+        .is_none_or(|(_, rule)| rule.action != IpAction::Deny)
+}
+
+/// Request guard applying `[[security.ip_rules]]` to the client's address
+pub struct IpAccess;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IpAccess {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        if config.security.ip_rules.is_empty() {
+            return Outcome::Success(IpAccess);
+        }
+        let Some(ip) = request.remote().map(|addr| addr.ip()) else {
+            return Outcome::Success(IpAccess);
+        };
+
+        let path = request.uri().path();
+        match matching_rule(&config.security.ip_rules, path.as_str(), ip) {
+            Some((index, rule)) if rule.action == IpAction::Deny => {
+                let reason = format!(
+                    "{} denied by IP rule {} (prefix {})",
+                    ip,
+                    index + 1,
+                    rule.prefix
+                );
+                log::log_warning(&format!("Request for {} refused: {}", path, reason));
+                web::log_denied(request, Status::Forbidden, None);
+                Outcome::Error((Status::Forbidden, reason))
+            }
+            _ => Outcome::Success(IpAccess),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(prefix: &str, action: IpAction, cidrs: &[&str]) -> IpRule {
+        IpRule {
+            prefix: prefix.to_string(),
+            action,
+            cidrs: cidrs.iter().map(|cidr| cidr.to_string()).collect(),
+        }
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let office = Cidr::parse("198.51.100.0/24").unwrap();
+        assert!(office.contains(ip("198.51.100.7")));
+        assert!(office.contains(ip("::ffff:198.51.100.7")));
+        assert!(!office.contains(ip("198.51.101.7")));
+        assert!(!office.contains(ip("2001:db8::1")));
+
+        let v6 = Cidr::parse("2001:db8:bad::/48").unwrap();
+        assert!(v6.contains(ip("2001:db8:bad:1::5")));
+        assert!(!v6.contains(ip("2001:db8:bae::5")));
+
+        assert!(
+            Cidr::parse("0.0.0.0/0")
+                .unwrap()
+                .contains(ip("203.0.113.9"))
+        );
+        assert!(
+            Cidr::parse("203.0.113.9")
+                .unwrap()
+                .contains(ip("203.0.113.9"))
+        );
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn test_rules_apply_in_order() {
+        let rules = vec![
+            rule("/", IpAction::Deny, &["203.0.113.0/24"]),
+            rule(
+                "/admin-docs",
+                IpAction::Allow,
+                &["198.51.100.0/24", "2001:db8::/32"],
+            ),
+            rule("/admin-docs", IpAction::Deny, &["all"]),
+        ];
+
+        let action = |path: &str, address: &str| {
+            matching_rule(&rules, path, ip(address)).map(|(_, rule)| rule.action)
+        };
+        assert_eq!(action("/index.md", "203.0.113.5"), Some(IpAction::Deny));
+        assert_eq!(action("/index.md", "192.0.2.1"), None);
+        assert_eq!(
+            action("/admin-docs/a.md", "198.51.100.20"),
+            Some(IpAction::Allow)
+        );
+        assert_eq!(
+            action("/admin-docs/a.md", "2001:db8::7"),
+            Some(IpAction::Allow)
+        );
+        assert_eq!(
+            action("/admin-docs/a.md", "192.0.2.1"),
+            Some(IpAction::Deny)
+        );
+        assert_eq!(action("/admin-docs-old/a.md", "192.0.2.1"), None);
+        assert_eq!(
+            action("/%61dmin-docs/a.md", "192.0.2.1"),
+            Some(IpAction::Deny)
+        );
+        assert_eq!(
+            action("//admin-docs/a.md", "192.0.2.1"),
+            Some(IpAction::Deny)
+        );
+        // The site-wide deny comes first, so it wins even under /admin-docs
+        assert_eq!(
+            action("/admin-docs/a.md", "203.0.113.5"),
+            Some(IpAction::Deny)
+        );
+    }
+}
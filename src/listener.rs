@@ -0,0 +1,575 @@
+use crate::config::{self, Config};
+use crate::{log, proxy};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::sync::watch;
+use rocket::tokio::task::JoinHandle;
+use rocket::tokio::time::{Instant, sleep_until, timeout};
+use rocket::{Ignite, Orbit, Rocket, Shutdown};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// TLS record type carrying application data
+const TLS_APPLICATION_DATA: u8 = 23;
+
+/// TLS record type opening a handshake
+const TLS_HANDSHAKE: u8 = 22;
+
+/// Loopback port Rocket listens on while the connection guard owns the
+/// public port, 0 until Rocket has bound it. Rocket picks the port itself,
+/// so no other process can take it between choosing and binding.
+static INTERNAL_PORT: OnceLock<watch::Sender<u16>> = OnceLock::new();
+
+/// Clients of relayed connections, keyed by the relay's end of its
+/// connection to Rocket
+static RELAYED: Mutex<Option<HashMap<SocketAddr, RelayedClient>>> = Mutex::new(None);
+
+/// The original client of a relayed connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelayedClient {
+    pub addr: SocketAddr,
+    /// Whether a trusted proxy reported the client in a PROXY protocol header
+    pub proxied: bool,
+}
+
+/// The original client of a connection relayed by the connection guard
+pub fn relayed_client(peer: SocketAddr) -> Option<RelayedClient> {
+    RELAYED.lock().ok()?.as_ref()?.get(&peer).copied()
+}
+
+/// Whether the connection guard owns the public port, so Rocket should
+/// listen on a loopback port of its choosing instead
+pub fn is_guarding() -> bool {
+    INTERNAL_PORT.get().is_some()
+}
+
+/// A launched server, finishing once it has stopped and drained its connections
+pub type Server = JoinHandle<Result<Rocket<Ignite>, rocket::Error>>;
+
+/// Follow the loopback port Rocket listens on, when the connection guard
+/// is running
+pub fn subscribe() -> Option<watch::Receiver<u16>> {
+    INTERNAL_PORT.get().map(watch::Sender::subscribe)
+}
+
+/// Replace a running server with `next` without refusing connections: `next`
+/// is launched on a loopback port of its own, the connection guard sends new
+/// connections to it once it is listening, and only then is the current one
+/// told to stop, draining its in-flight requests. If `next` fails to launch,
+/// the current server keeps running.
+pub async fn hand_off(
+    current: Shutdown,
+    next: Rocket<Ignite>,
+    internal_port: &mut watch::Receiver<u16>,
+) -> Result<Server, String> {
+    internal_port.mark_unchanged();
+    let mut server = rocket::tokio::spawn(next.launch());
+    rocket::tokio::select! {
+        changed = internal_port.changed() => {
+            changed.map_err(|_| "the connection guard has stopped".to_string())?;
+        }
+        launched = &mut server => {
+            return Err(match launched {
+                Ok(Err(e)) => e.to_string(),
+                _ => "the new server stopped before listening".to_string(),
+            });
+        }
+    }
+    current.notify();
+    Ok(server)
+}
+
+/// Hands the loopback port Rocket bound to the connection guard
+pub struct InternalPort;
+
+#[rocket::async_trait]
+impl Fairing for InternalPort {
+    fn info(&self) -> Info {
+        Info {
+            name: "Internal Port",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if let Some(port) = INTERNAL_PORT.get() {
+            port.send_replace(rocket.config().port);
+        }
+    }
+}
+
+/// Watches the bytes a client sends for the end of each request's headers,
+/// since Rocket can't put a deadline on reading them
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderWatch {
+    /// Plain HTTP: the length of the header line being read, `None` before
+    /// the request line starts
+    Headers { since: Instant, line: Option<usize> },
+    /// Plain HTTP headers are in; a new request starts once the client sends
+    /// again after a response
+    Body { responded: bool },
+    /// TLS: the handshake runs until the client's first application data.
+    /// `header` collects a record header and `remaining` skips its body.
+    Handshake {
+        since: Instant,
+        header: Vec<u8>,
+        remaining: usize,
+    },
+    /// TLS after the handshake: the requests themselves are encrypted, so
+    /// whatever the client sends must be answered in time instead. `since`
+    /// is when the client started sending after the server last did.
+    Encrypted { since: Option<Instant> },
+}
+
+impl HeaderWatch {
+    /// A watch for a new connection, detecting TLS from its first byte
+    pub fn new(first: u8, since: Instant) -> Self {
+        if first == TLS_HANDSHAKE {
+            HeaderWatch::Handshake {
+                since,
+                header: Vec::new(),
+                remaining: 0,
+            }
+        } else {
+            HeaderWatch::Headers { since, line: None }
+        }
+    }
+
+    /// Note bytes the client sent
+    pub fn client(&mut self, data: &[u8], now: Instant) {
+        if let HeaderWatch::Body { responded: true } = self {
+            *self = HeaderWatch::Headers {
+                since: now,
+                line: None,
+            };
+        }
+        match self {
+            HeaderWatch::Headers { line, .. } => {
+                for byte in data {
+                    match (*byte, *line) {
+                        (b'\n', Some(0)) => {
+                            *self = HeaderWatch::Body { responded: false };
+                            return;
+                        }
+                        (b'\n', Some(_)) => *line = Some(0),
+                        // Blank lines before the request line are allowed
+                        (b'\r' | b'\n', _) => {}
+                        (_, length) => *line = Some(length.unwrap_or(0) + 1),
+                    }
+                }
+            }
+            HeaderWatch::Handshake {
+                header, remaining, ..
+            } => {
+                let mut data = data;
+                while !data.is_empty() {
+                    if *remaining > 0 {
+                        let skipped = (*remaining).min(data.len());
+                        *remaining -= skipped;
+                        data = &data[skipped..];
+                        continue;
+                    }
+                    let wanted = (5 - header.len()).min(data.len());
+                    header.extend_from_slice(&data[..wanted]);
+                    data = &data[wanted..];
+                    if header.len() == 5 {
+                        if header[0] == TLS_APPLICATION_DATA {
+                            *self = HeaderWatch::Encrypted { since: Some(now) };
+                            return;
+                        }
+                        *remaining = u16::from_be_bytes([header[3], header[4]]) as usize;
+                        header.clear();
+                    }
+                }
+            }
+            HeaderWatch::Encrypted { since } => {
+                since.get_or_insert(now);
+            }
+            HeaderWatch::Body { .. } => {}
+        }
+    }
+
+    /// Note bytes the server sent back
+    pub fn server(&mut self) {
+        match self {
+            HeaderWatch::Body { responded } => *responded = true,
+            HeaderWatch::Encrypted { since } => *since = None,
+            _ => {}
+        }
+    }
+
+    /// When the headers being read must be complete, if any are
+    pub fn deadline(&self, limit: Duration) -> Option<Instant> {
+        match self {
+            HeaderWatch::Headers { since, .. } | HeaderWatch::Handshake { since, .. } => {
+                Some(*since + limit)
+            }
+            HeaderWatch::Body { .. } | HeaderWatch::Encrypted { .. } => None,
+        }
+    }
+
+    /// When the server must have answered what the client sent over TLS,
+    /// since trickling an encrypted request keeps the connection from idling
+    pub fn answer_deadline(&self, limit: Duration) -> Option<Instant> {
+        match self {
+            HeaderWatch::Encrypted { since: Some(since) } => Some(*since + limit),
+            _ => None,
+        }
+    }
+}
+
+/// Take over the public listener when connections need guarding: PROXY
+/// protocol headers are read and request header and idle deadlines enforced
+/// here, and the rest relayed to Rocket on a loopback port. Does nothing
+/// unless `server.connection_guard` or `server.proxy.proxy_protocol` is on.
+pub async fn spawn_listener(config: &Config) -> std::io::Result<()> {
+    let proxy_protocol = config.server.proxy.proxy_protocol;
+    if !proxy_protocol && !config.server.connection_guard {
+        return Ok(());
+    }
+
+    let public = TcpListener::bind((config.server.host.as_str(), config.server.port)).await?;
+    let internal_port = INTERNAL_PORT
+        .get_or_init(|| watch::Sender::new(0))
+        .subscribe();
+
+    if proxy_protocol {
+        log::log_barleywine(&format!(
+            "Accepting PROXY protocol connections on {}:{}",
+            config.server.host, config.server.port
+        ));
+    }
+    rocket::tokio::spawn(accept(public, internal_port, proxy_protocol));
+    Ok(())
+}
+
+/// Relay every connection to the public port to wherever Rocket listens
+async fn accept(
+    public: TcpListener,
+    mut internal_port: watch::Receiver<u16>,
+    proxy_protocol: bool,
+) {
+    // Connections wait in the backlog until Rocket is listening
+    if internal_port.wait_for(|port| *port != 0).await.is_err() {
+        return;
+    }
+    loop {
+        match public.accept().await {
+            Ok((stream, peer)) => {
+                rocket::tokio::spawn(relay(stream, peer, internal_port.clone(), proxy_protocol));
+            }
+            Err(e) => log::log_warning(&format!("Accepting a connection failed: {}", e)),
+        }
+    }
+}
+
+async fn relay(
+    mut client: TcpStream,
+    peer: SocketAddr,
+    internal_port: watch::Receiver<u16>,
+    proxy_protocol: bool,
+) {
+    let accepted = Instant::now();
+    let source = if proxy_protocol {
+        match proxy::accept(&mut client, peer).await {
+            Some(source) => source,
+            None => return,
+        }
+    } else {
+        peer
+    };
+
+    // A server being handed off may stop listening just after the port was
+    // read; its successor is already listening by then
+    let connect = || TcpStream::connect((Ipv4Addr::LOCALHOST, *internal_port.borrow()));
+    let Ok(mut upstream) = (match connect().await {
+        Ok(upstream) => Ok(upstream),
+        Err(_) => connect().await,
+    }) else {
+        return;
+    };
+    let Ok(local) = upstream.local_addr() else {
+        return;
+    };
+    if let Ok(mut relayed) = RELAYED.lock() {
+        relayed.get_or_insert_with(HashMap::new).insert(
+            local,
+            RelayedClient {
+                addr: source,
+                proxied: proxy_protocol,
+            },
+        );
+    }
+    if let Err(reason) = guard(&mut client, &mut upstream, accepted).await {
+        log::log_warning(&format!("Closed connection from {}: {}", source, reason));
+    }
+    let _ = upstream.shutdown().await;
+    if let Ok(mut relayed) = RELAYED.lock()
+        && let Some(relayed) = relayed.as_mut()
+    {
+        relayed.remove(&local);
+    }
+}
+
+/// Copy bytes both ways until Rocket is done, closing the connection
+/// when the client is too slow with its headers, an encrypted request goes
+/// unanswered for `server.timeout` or either side stalls for that long
+async fn guard(
+    client: &mut TcpStream,
+    upstream: &mut TcpStream,
+    accepted: Instant,
+) -> Result<(), String> {
+    let config = config::get_config();
+    let idle = config.server.timeout;
+    let header_limit = config.server.header_timeout;
+    drop(config);
+
+    let (mut client_read, mut client_write) = client.split();
+    let (mut upstream_read, mut upstream_write) = upstream.split();
+    let mut from_client = vec![0u8; 16 * 1024];
+    let mut from_upstream = vec![0u8; 16 * 1024];
+    let mut watch: Option<HeaderWatch> = None;
+    let mut last_activity = accepted;
+    let mut client_open = true;
+
+    // Waits that never end stand in for disabled limits
+    let far_future = accepted + Duration::from_secs(100 * 365 * 24 * 60 * 60);
+    let write_limit = if idle.is_zero() {
+        Duration::MAX
+    } else {
+        idle.0
+    };
+
+    loop {
+        let header_deadline = if header_limit.is_zero() {
+            None
+        } else {
+            match watch {
+                Some(ref watch) => watch.deadline(header_limit.0),
+                // Nothing received yet: the first request's headers are due
+                None => Some(accepted + header_limit.0),
+            }
+        };
+        let idle_deadline = (!idle.is_zero()).then(|| last_activity + idle.0);
+        let answer_deadline = watch
+            .as_ref()
+            .filter(|_| !idle.is_zero())
+            .and_then(|watch| watch.answer_deadline(idle.0));
+        let deadline = [header_deadline, idle_deadline, answer_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(far_future);
+
+        rocket::tokio::select! {
+            read = client_read.read(&mut from_client), if client_open => {
+                let count = read.unwrap_or(0);
+                if count == 0 {
+                    client_open = false;
+                    let _ = upstream_write.shutdown().await;
+                    continue;
+                }
+                let now = Instant::now();
+                last_activity = now;
+                watch
+                    .get_or_insert_with(|| HeaderWatch::new(from_client[0], accepted))
+                    .client(&from_client[..count], now);
+                match timeout(write_limit, upstream_write.write_all(&from_client[..count])).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) => return Ok(()),
+                    Err(_) => return Err(format!("server stalled for {}", idle)),
+                }
+            }
+            read = upstream_read.read(&mut from_upstream) => {
+                let count = read.unwrap_or(0);
+                if count == 0 {
+                    // Rocket closed the connection
+                    let _ = client_write.shutdown().await;
+                    return Ok(());
+                }
+                last_activity = Instant::now();
+                if let Some(ref mut watch) = watch {
+                    watch.server();
+                }
+                match timeout(write_limit, client_write.write_all(&from_upstream[..count])).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) => return Ok(()),
+                    Err(_) => return Err(format!("client stopped reading for {}", idle)),
+                }
+            }
+            _ = sleep_until(deadline) => {
+                if header_deadline == Some(deadline) {
+                    return Err(format!(
+                        "request headers not received within {}",
+                        header_limit
+                    ));
+                }
+                if answer_deadline == Some(deadline) {
+                    return Err(format!("request not answered within {}", idle));
+                }
+                // Idle connections are routine; close them quietly
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::fairing::AdHoc;
+    use rocket::{State, get, routes};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test]
+    fn test_header_watch_http() {
+        let start = Instant::now();
+        let limit = Duration::from_secs(10);
+        let mut watch = HeaderWatch::new(b'G', start);
+        assert_eq!(watch.deadline(limit), Some(start + limit));
+
+        watch.client(b"GET / HTTP/1.1\r\nHost: exa", start);
+        assert!(watch.deadline(limit).is_some());
+        watch.client(b"mple.com\r\n", start);
+        assert!(watch.deadline(limit).is_some());
+        watch.client(b"\r\nbody", start);
+        assert_eq!(watch.deadline(limit), None);
+
+        // More body before the response keeps the deadline off
+        watch.client(b"more body", start);
+        assert_eq!(watch.deadline(limit), None);
+
+        // The next request on a kept-alive connection gets its own deadline
+        watch.server();
+        let later = start + Duration::from_secs(30);
+        watch.client(b"\r\nGET /next HTTP/1.1\r\n", later);
+        assert_eq!(watch.deadline(limit), Some(later + limit));
+        watch.client(b"\n", later);
+        assert_eq!(watch.deadline(limit), None);
+    }
+
+    #[test]
+    fn test_header_watch_tls() {
+        let start = Instant::now();
+        let limit = Duration::from_secs(10);
+        let mut watch = HeaderWatch::new(TLS_HANDSHAKE, start);
+
+        // A ClientHello split across reads, then change_cipher_spec
+        watch.client(&[22, 3, 1, 0], start);
+        watch.client(&[4, 1, 2], start);
+        watch.client(&[3, 4, 20, 3, 3, 0, 1, 1], start);
+        assert_eq!(watch.deadline(limit), Some(start + limit));
+
+        // The first application data record ends the handshake, and the
+        // request in it must be answered
+        let idle = Duration::from_secs(30);
+        watch.client(&[23, 3, 3, 0, 2, 9, 9], start);
+        assert_eq!(watch.deadline(limit), None);
+        assert_eq!(watch.answer_deadline(idle), Some(start + idle));
+
+        // Trickling more of it doesn't push the deadline back
+        let later = start + Duration::from_secs(20);
+        watch.client(b"x", later);
+        assert_eq!(watch.answer_deadline(idle), Some(start + idle));
+
+        // A response clears it until the client sends again
+        watch.server();
+        assert_eq!(watch.answer_deadline(idle), None);
+        watch.client(b"anything", later);
+        assert_eq!(watch.deadline(limit), None);
+        assert_eq!(watch.answer_deadline(idle), Some(later + idle));
+    }
+
+    #[get("/")]
+    fn which(name: &State<&'static str>) -> &'static str {
+        name
+    }
+
+    /// A server on a loopback port of its choosing, reporting the port the
+    /// way `InternalPort` does
+    fn instance(name: &'static str, port: Arc<watch::Sender<u16>>) -> Rocket<rocket::Build> {
+        let figment = rocket::Config::figment()
+            .merge(("address", "127.0.0.1"))
+            .merge(("port", 0))
+            .merge(("log_level", "off"))
+            .merge(("shutdown.ctrlc", false))
+            .merge(("shutdown.grace", 1))
+            .merge(("shutdown.mercy", 1));
+        rocket::custom(figment)
+            .manage(name)
+            .mount("/", routes![which])
+            .attach(AdHoc::on_liftoff("Port", move |rocket| {
+                Box::pin(async move {
+                    port.send_replace(rocket.config().port);
+                })
+            }))
+    }
+
+    /// The body served for a fresh connection to the public port
+    async fn fetch(public: SocketAddr) -> Option<String> {
+        let mut stream = TcpStream::connect(public).await.ok()?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok()?;
+        let (head, body) = response.split_once("\r\n\r\n")?;
+        head.starts_with("HTTP/1.1 200").then(|| body.to_string())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_hand_off_keeps_accepting_connections() {
+        let _ = config::init_config(None);
+        let port = Arc::new(watch::Sender::new(0));
+        let mut internal_port = port.subscribe();
+        let public = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let public_addr = public.local_addr().unwrap();
+        rocket::tokio::spawn(accept(public, port.subscribe(), false));
+
+        let first = instance("first", port.clone()).ignite().await.unwrap();
+        let first_shutdown = first.shutdown();
+        let first_server = rocket::tokio::spawn(first.launch());
+        internal_port.wait_for(|port| *port != 0).await.unwrap();
+        assert_eq!(fetch(public_addr).await.as_deref(), Some("first"));
+
+        // Clients keep connecting throughout, as during a certificate change
+        let stop = Arc::new(AtomicBool::new(false));
+        let failures = Arc::new(AtomicUsize::new(0));
+        let clients = {
+            let (stop, failures) = (stop.clone(), failures.clone());
+            rocket::tokio::spawn(async move {
+                let mut served = 0;
+                while !stop.load(Ordering::SeqCst) {
+                    match fetch(public_addr).await {
+                        Some(_) => served += 1,
+                        None => {
+                            failures.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+                served
+            })
+        };
+
+        let second = instance("second", port.clone()).ignite().await.unwrap();
+        let second_shutdown = second.shutdown();
+        let second_server = hand_off(first_shutdown, second, &mut internal_port)
+            .await
+            .unwrap();
+        // The first server drains and stops while the second serves
+        assert!(first_server.await.unwrap().is_ok());
+        assert_eq!(fetch(public_addr).await.as_deref(), Some("second"));
+
+        stop.store(true, Ordering::SeqCst);
+        assert!(clients.await.unwrap() > 0);
+        assert_eq!(failures.load(Ordering::SeqCst), 0);
+
+        second_shutdown.notify();
+        assert!(second_server.await.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::config::{self, Config};
+use crate::{headers, watch};
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Route, Shutdown, get, routes};
+
+/// Browser side of live reload: reloads the page on change, or hot-swaps
+/// stylesheets when only CSS files changed
+const LIVE_RELOAD_SCRIPT: &str = r#"(function() {
+    var source = new EventSource('{endpoint}');
+    var pending = [];
+    var timer = null;
+
+    function apply() {
+        var changed = pending;
+        pending = [];
+
+        var cssOnly = changed.every(function(path) {
+            return /\.css$/i.test(path);
+        });
+        if (!cssOnly) {
+            window.location.reload();
+            return;
+        }
+
+        var links = Array.prototype.slice.call(document.querySelectorAll('link[rel="stylesheet"]'));
+        var matching = links.filter(function(link) {
+            return changed.indexOf(new URL(link.href).pathname) !== -1;
+        });
+        (matching.length ? matching : links).forEach(function(link) {
+            var url = new URL(link.href);
+            url.searchParams.set('livereload', Date.now());
+            link.href = url.toString();
+        });
+    }
+
+    source.addEventListener('change', function(event) {
+        pending.push(JSON.parse(event.data).path);
+        clearTimeout(timer);
+        timer = setTimeout(apply, 100);
+    });
+})();"#;
+
+/// Whether live reload is active for this configuration
+pub fn is_enabled(config: &Config) -> bool {
+    config.development.dev_mode && config.development.hot_reload
+}
+
+/// Stream webroot changes to the browser as server-sent events
+#[get("/livereload")]
+fn events(mut shutdown: Shutdown) -> Result<EventStream![], Status> {
+    let mut changes = watch::subscribe().ok_or(Status::NotFound)?;
+
+    Ok(EventStream! {
+        loop {
+            let change = select! {
+                change = changes.recv() => match change {
+                    Ok(change) => change,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::json(&change).event("change");
+        }
+    })
+}
+
+/// The `<script>` element that connects a page to the live reload stream
+pub fn script_tag(nonce: Option<&str>) -> String {
+    let endpoint = format!("{}/livereload", config::get_config().internal.prefix);
+    format!(
+        "<script{}>{}</script>",
+        headers::nonce_attribute(nonce),
+        LIVE_RELOAD_SCRIPT.replace("{endpoint}", &endpoint)
+    )
+}
+
+/// Insert the live reload script before the closing `</body>` tag
+pub fn inject_script(html: &str, nonce: Option<&str>) -> String {
+    let script = script_tag(nonce);
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(index) => format!("{}{}\n{}", &html[..index], script, &html[index..]),
+        None => format!("{}\n{}", html, script),
+    }
+}
+
+/// Routes to mount under the internal endpoint prefix
+pub fn routes() -> Vec<Route> {
+    routes![events]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_script_before_body_end() {
+        let _ = config::init_config(None);
+        let html = "<html><body><p>Hi</p></BODY></html>";
+        let injected = inject_script(html, Some("abc"));
+
+        let script_at = injected.find("<script nonce=\"abc\">").unwrap();
+        assert!(script_at < injected.find("</BODY>").unwrap());
+        assert!(injected.contains("/_barleywine/livereload"));
+        assert!(injected.ends_with("</BODY></html>"));
+    }
+
+    #[test]
+    fn test_inject_script_without_body() {
+        let _ = config::init_config(None);
+        let injected = inject_script("<p>fragment</p>", None);
+        assert!(injected.starts_with("<p>fragment</p>"));
+        assert!(injected.contains("new EventSource"));
+    }
+}
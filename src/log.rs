@@ -1,16 +1,25 @@
 use crate::cli::Cli;
 use chrono::Utc;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use simplelog::*;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 /// Global logger instances for different log types
 static BARLEYWINE_LOGGER: Mutex<Option<std::fs::File>> = Mutex::new(None);
 static ACCESS_LOGGER: Mutex<Option<std::fs::File>> = Mutex::new(None);
 
+/// Directory the file loggers were opened in
+static LOG_DIRECTORY: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Whether the most recent write to each log file failed
+static BARLEYWINE_WRITE_FAILED: AtomicBool = AtomicBool::new(false);
+static ACCESS_WRITE_FAILED: AtomicBool = AtomicBool::new(false);
+
 /// Setup logging based on CLI configuration
 pub fn setup_logging(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
     // Determine log directory - use CLI option or default to "logs"
@@ -47,7 +56,6 @@ fn setup_main_logger(log_dir: &Path, level: LevelFilter) -> Result<(), Box<dyn s
     let log_file = log_dir.join("barleywine.log");
     let file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(&log_file)?;
 
@@ -70,7 +78,6 @@ fn init_file_loggers(log_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
     let barleywine_log = log_dir.join("barleywine.log");
     let barleywine_file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(&barleywine_log)?;
 
@@ -78,13 +85,13 @@ fn init_file_loggers(log_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
     let access_log = log_dir.join("access.log");
     let access_file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(&access_log)?;
 
     // Store file handles in global mutexes
     *BARLEYWINE_LOGGER.lock().unwrap() = Some(barleywine_file);
     *ACCESS_LOGGER.lock().unwrap() = Some(access_file);
+    *LOG_DIRECTORY.lock().unwrap() = Some(log_dir.to_path_buf());
 
     info!("📋 Log files initialized:");
     info!("  - General log: {}", barleywine_log.display());
@@ -98,39 +105,49 @@ pub fn log_barleywine(message: &str) {
     let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
     let log_entry = format!("[{}] {}\n", timestamp, message);
 
-    if let Ok(mut logger) = BARLEYWINE_LOGGER.lock() {
-        if let Some(ref mut file) = *logger {
-            if let Err(e) = file.write_all(log_entry.as_bytes()) {
-                eprintln!("Failed to write to barleywine.log: {}", e);
-            } else {
-                let _ = file.flush();
-            }
+    if let Ok(mut logger) = BARLEYWINE_LOGGER.lock()
+        && let Some(ref mut file) = *logger
+    {
+        if let Err(e) = file.write_all(log_entry.as_bytes()) {
+            BARLEYWINE_WRITE_FAILED.store(true, Ordering::Relaxed);
+            eprintln!("Failed to write to barleywine.log: {}", e);
+        } else {
+            BARLEYWINE_WRITE_FAILED.store(false, Ordering::Relaxed);
+            let _ = file.flush();
         }
     }
 }
 
-/// Log an access entry to the access.log file
+/// Log an access entry to the access.log file. `user` is the authenticated
+/// identity (such as a client certificate subject) and is appended when known.
 pub fn log_access(
     remote_addr: &str,
     method: &str,
     uri: &str,
     status: u16,
     user_agent: Option<&str>,
+    user: Option<&str>,
 ) {
     let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
     let user_agent_str = user_agent.unwrap_or("-");
-    let log_entry = format!(
-        "[{}] {} \"{}\" {} \"{}\" \"{}\"\n",
+    let mut log_entry = format!(
+        "[{}] {} \"{}\" {} \"{}\" \"{}\"",
         timestamp, remote_addr, method, uri, status, user_agent_str
     );
-
-    if let Ok(mut logger) = ACCESS_LOGGER.lock() {
-        if let Some(ref mut file) = *logger {
-            if let Err(e) = file.write_all(log_entry.as_bytes()) {
-                eprintln!("Failed to write to access.log: {}", e);
-            } else {
-                let _ = file.flush();
-            }
+    if let Some(user) = user {
+        log_entry.push_str(&format!(" \"{}\"", user.replace('"', "\\\"")));
+    }
+    log_entry.push('\n');
+
+    if let Ok(mut logger) = ACCESS_LOGGER.lock()
+        && let Some(ref mut file) = *logger
+    {
+        if let Err(e) = file.write_all(log_entry.as_bytes()) {
+            ACCESS_WRITE_FAILED.store(true, Ordering::Relaxed);
+            eprintln!("Failed to write to access.log: {}", e);
+        } else {
+            ACCESS_WRITE_FAILED.store(false, Ordering::Relaxed);
+            let _ = file.flush();
         }
     }
 }
@@ -149,9 +166,31 @@ pub fn log_server_startup(port: u16, webroot: &str) {
 pub fn log_server_shutdown() {
     let message = "Server shutting down";
     info!("{}", message);
+    log_barleywine(message);
+}
+
+/// Log a summary of the server's lifetime once shutdown has completed
+pub fn log_shutdown_summary(uptime: Duration, requests_served: u64) {
+    let message = format!(
+        "Server stopped after {} having served {} requests",
+        format_duration(uptime),
+        requests_served
+    );
+    info!("{}", message);
     log_barleywine(&message);
 }
 
+/// Format a duration as hours, minutes and seconds
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}h {:02}m {:02}s",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
 /// Log file serving information
 pub fn log_file_served(path: &str, file_type: &str) {
     let message = format!("Served {} file: {}", file_type, path);
@@ -171,6 +210,11 @@ pub fn log_warning(warning_msg: &str) {
     log_barleywine(&format!("WARNING: {}", warning_msg));
 }
 
+/// Log debugging detail, shown only when the log level is `debug` or `trace`
+pub fn log_debug(debug_msg: &str) {
+    debug!("{}", debug_msg);
+}
+
 /// Parse log level string to LevelFilter
 fn parse_log_level(level: &str) -> LevelFilter {
     match level.to_lowercase().as_str() {
@@ -213,18 +257,40 @@ pub fn init_directory_logging(log_dir: &Path) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// Check that the log files can still be written
+pub fn check_log_health() -> Result<(), String> {
+    // A successful write to one file says nothing about the other
+    for (name, failed) in [
+        ("barleywine.log", &BARLEYWINE_WRITE_FAILED),
+        ("access.log", &ACCESS_WRITE_FAILED),
+    ] {
+        if failed.load(Ordering::Relaxed) {
+            return Err(format!("last write to {} failed", name));
+        }
+    }
+
+    if let Ok(log_dir) = LOG_DIRECTORY.lock()
+        && let Some(ref dir) = *log_dir
+        && !dir.is_dir()
+    {
+        return Err(format!("log directory missing: {}", dir.display()));
+    }
+
+    Ok(())
+}
+
 /// Flush all log files
 pub fn flush_logs() {
-    if let Ok(mut logger) = BARLEYWINE_LOGGER.lock() {
-        if let Some(ref mut file) = *logger {
-            let _ = file.flush();
-        }
+    if let Ok(mut logger) = BARLEYWINE_LOGGER.lock()
+        && let Some(ref mut file) = *logger
+    {
+        let _ = file.flush();
     }
 
-    if let Ok(mut logger) = ACCESS_LOGGER.lock() {
-        if let Some(ref mut file) = *logger {
-            let _ = file.flush();
-        }
+    if let Ok(mut logger) = ACCESS_LOGGER.lock()
+        && let Some(ref mut file) = *logger
+    {
+        let _ = file.flush();
     }
 }
 
@@ -264,6 +330,14 @@ mod tests {
         assert!(levels.contains(&"trace"));
     }
 
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0h 00m 00s");
+        assert_eq!(format_duration(Duration::from_secs(59)), "0h 00m 59s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 02m 03s");
+        assert_eq!(format_duration(Duration::from_secs(90000)), "25h 00m 00s");
+    }
+
     #[test]
     fn test_init_directory_logging() {
         let temp_dir = std::env::temp_dir().join("barleywine_test_logs");
@@ -284,4 +358,15 @@ mod tests {
         // Clean up
         fs::remove_dir_all(&temp_dir).ok();
     }
+
+    #[test]
+    fn test_log_health_tracks_each_file() {
+        ACCESS_WRITE_FAILED.store(true, Ordering::Relaxed);
+        BARLEYWINE_WRITE_FAILED.store(false, Ordering::Relaxed);
+        assert_eq!(
+            check_log_health(),
+            Err("last write to access.log failed".to_string())
+        );
+        ACCESS_WRITE_FAILED.store(false, Ordering::Relaxed);
+    }
 }
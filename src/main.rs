@@ -1,6 +1,33 @@
+pub mod auth;
 pub mod cli;
+pub mod collection;
 pub mod config;
+pub mod cors;
+pub mod debug;
+pub mod feed;
+pub mod frontmatter;
+pub mod headers;
+pub mod health;
+pub mod hotlink;
+pub mod ipfilter;
+pub mod listener;
+pub mod livereload;
 pub mod log;
+pub mod math;
+pub mod metrics;
+pub mod mtls;
+pub mod proxy;
+pub mod ratelimit;
+pub mod reload;
+pub mod render;
+pub mod search;
+pub mod session;
+pub mod signed;
+pub mod sitemap;
+pub mod symlinks;
+pub mod tls;
+pub mod units;
+pub mod watch;
 pub mod web;
 
 use ::log::info;
@@ -25,6 +52,18 @@ async fn main() {
     }
     let config = config::get_config();
 
+    // Print a signed URL and exit
+    if let Some(cli::Command::Sign { ref path, ref ttl }) = cli.command {
+        match signed::link(&config, path, ttl, chrono::Utc::now().timestamp()) {
+            Ok(url) => println!("{}", url),
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     // Print configuration if verify mode
     if cli.verify {
         config.print_config(
@@ -59,6 +98,11 @@ async fn main() {
             println!("  Markdown support: ⚠️  Disabled");
         }
 
+        // Check the TLS certificate and key
+        if let Some(ref tls_config) = config.server.tls {
+            tls::print_verification(tls_config);
+        }
+
         // Test rocket configuration
         println!("  Rocket framework: ✅ Ready");
 
@@ -73,6 +117,31 @@ async fn main() {
         process::exit(1);
     }
 
+    // Generate development certificates and serve HTTPS with them
+    let config = if cli.dev_tls {
+        match tls::generate_dev_certificates(&config.development.state_dir, &config.server.host) {
+            Ok(files) => {
+                if config.server.tls.is_some() {
+                    log::log_warning("--dev-tls replaces the configured [server.tls] settings");
+                }
+                log::log_barleywine(&format!(
+                    "Serving HTTPS with a development certificate issued by {}",
+                    files.ca.display()
+                ));
+                println!("🔐 Development CA certificate: {}", files.ca.display());
+                println!("   Add it to your browser or system trust store to avoid warnings.");
+                config::override_tls(files.tls_config());
+                config::get_config()
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to generate development certificates: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        config
+    };
+
     // Log startup information
     info!("🍺 Starting Barleywine...");
     if let Some(ref config_file) = cli.config {
@@ -117,13 +186,109 @@ async fn main() {
         config.server.port,
         &config.content.webroot.display().to_string(),
     );
-    let rocket = web::build_rocket();
-    if let Err(e) = rocket.launch().await {
-        let error_msg = format!("Failed to start server: {}", e);
-        log::log_error(&error_msg);
-        eprintln!("❌ {}", error_msg);
-        log::log_server_shutdown();
-        log::flush_logs();
+    reload::spawn_config_reloader();
+    search::spawn_indexer();
+    ratelimit::spawn_pruner();
+    if let Err(e) = listener::spawn_listener(&config).await {
+        eprintln!("❌ Failed to start the connection listener: {}", e);
         process::exit(1);
     }
+    if let Some(redirect_rocket) = tls::build_redirect_rocket() {
+        tokio::spawn(async move {
+            if let Err(e) = redirect_rocket.launch().await {
+                log::log_error(&format!("Failed to start HTTP redirect listener: {}", e));
+            }
+        });
+    }
+
+    let rocket = web::build_rocket();
+    if config.development.debug_routes {
+        let debug_rocket = debug::build_debug_rocket(&rocket);
+        tokio::spawn(async move {
+            if let Err(e) = debug_rocket.launch().await {
+                log::log_error(&format!("Failed to start debug routes listener: {}", e));
+            }
+        });
+    }
+    let rocket = match rocket.ignite().await {
+        Ok(rocket) => rocket,
+        Err(e) => exit_with_launch_error(e),
+    };
+    // Kept alive until the server stops
+    let _cert_watchers = match config.server.tls {
+        Some(ref tls_config) => tls::watch_certificates(tls_config),
+        None => Vec::new(),
+    };
+    let mut internal_port = listener::subscribe();
+    let mut shutdown = rocket.shutdown();
+    let mut server: listener::Server = tokio::spawn(rocket.launch());
+
+    loop {
+        tokio::select! {
+            stopped = &mut server => match stopped {
+                Ok(Ok(_)) => {
+                    // Rocket has stopped accepting connections and drained in-flight requests
+                    log::log_shutdown_summary(metrics::uptime(), metrics::requests_served());
+                    log::flush_logs();
+                    break;
+                }
+                Ok(Err(e)) => exit_with_launch_error(e),
+                Err(e) => {
+                    log::log_error(&format!("Server task failed: {}", e));
+                    log::flush_logs();
+                    process::exit(1);
+                }
+            },
+            _ = tls::restart_requested() => {
+                let rocket = match web::build_rocket().ignite().await {
+                    Ok(rocket) => rocket,
+                    Err(e) => exit_with_launch_error(e),
+                };
+                let next_shutdown = rocket.shutdown();
+                match internal_port {
+                    // The connection guard owns the public port, so the new
+                    // server can take over before the current one stops
+                    Some(ref mut internal_port) => {
+                        match listener::hand_off(shutdown.clone(), rocket, internal_port).await {
+                            Ok(next) => {
+                                log::log_barleywine(
+                                    "Handed connections over to a server with the new TLS certificate",
+                                );
+                                let previous = std::mem::replace(&mut server, next);
+                                // The previous server drains its requests in the background
+                                tokio::spawn(previous);
+                                shutdown = next_shutdown;
+                            }
+                            Err(e) => log::log_error(&format!(
+                                "Could not launch a server with the new TLS certificate, keeping the current one: {}",
+                                e
+                            )),
+                        }
+                    }
+                    None => {
+                        log::log_warning(
+                            "Relaunching the server with the new TLS certificate: connections are refused until it is listening again. Turn on server.connection_guard to hand over without a gap",
+                        );
+                        shutdown.clone().notify();
+                        if let Ok(Err(e)) = (&mut server).await {
+                            exit_with_launch_error(e);
+                        }
+                        server = tokio::spawn(rocket.launch());
+                        shutdown = next_shutdown;
+                    }
+                }
+                tls::finish_restart();
+            }
+        }
+    }
+}
+
+/// Report a server that failed to start and exit
+fn exit_with_launch_error(e: rocket::Error) -> ! {
+    let error_msg = format!("Failed to start server: {}", e);
+    log::log_error(&error_msg);
+    eprintln!("❌ {}", error_msg);
+    log::log_server_shutdown();
+    log::flush_logs();
+    process::exit(1);
 }
@@ -0,0 +1,1043 @@
+use crate::log;
+use crate::render::escape_html;
+
+/// How the markdown renderer marks up math
+const INLINE_OPEN: &str = "<code class=\"language-math math-inline\">";
+const INLINE_CLOSE: &str = "</code>";
+const DISPLAY_OPEN: &str = "<pre><code class=\"language-math math-display\">";
+const DISPLAY_CLOSE: &str = "</code></pre>";
+
+/// Marks around the number of a formula set aside while HTML is sanitized
+const PLACEHOLDER_OPEN: char = '\u{E000}';
+const PLACEHOLDER_CLOSE: char = '\u{E001}';
+
+/// Deepest nesting of groups a formula may have
+const MAX_DEPTH: usize = 64;
+
+/// Replace the math in rendered markdown with numbered placeholders, returning
+/// the HTML and the MathML for each placeholder. Formulas that can't be
+/// converted become their TeX with an error marker. `page` names the page in
+/// logs.
+pub fn extract(html: &str, page: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(html.len());
+    let mut formulas = Vec::new();
+    let mut rest = html;
+    loop {
+        let inline = rest.find(INLINE_OPEN);
+        let display = rest.find(DISPLAY_OPEN);
+        let (start, open, close, is_display) = match (inline, display) {
+            (Some(i), Some(d)) if d < i => (d, DISPLAY_OPEN, DISPLAY_CLOSE, true),
+            (Some(i), _) => (i, INLINE_OPEN, INLINE_CLOSE, false),
+            (None, Some(d)) => (d, DISPLAY_OPEN, DISPLAY_CLOSE, true),
+            (None, None) => break,
+        };
+        let body_start = start + open.len();
+        let Some(length) = rest[body_start..].find(close) else {
+            break;
+        };
+        let tex = unescape_html(&rest[body_start..body_start + length]);
+        let formula = match to_mathml(tex.trim(), is_display) {
+            Ok(mathml) => mathml,
+            Err(message) => {
+                log::log_debug(&format!("Math error in {}: {}", page, message));
+                error_html(&tex, is_display, &message)
+            }
+        };
+        out.push_str(&rest[..start]);
+        out.push(PLACEHOLDER_OPEN);
+        out.push_str(&formulas.len().to_string());
+        out.push(PLACEHOLDER_CLOSE);
+        formulas.push(formula);
+        rest = &rest[body_start + length + close.len()..];
+    }
+    out.push_str(rest);
+    (out, formulas)
+}
+
+/// Put the formulas set aside by [`extract`] back in place
+pub fn restore(html: &str, formulas: &[String]) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(PLACEHOLDER_OPEN) {
+        let after = start + PLACEHOLDER_OPEN.len_utf8();
+        let formula = rest[after..].find(PLACEHOLDER_CLOSE).and_then(|length| {
+            let number = rest[after..after + length].parse::<usize>().ok()?;
+            Some((formulas.get(number)?, after + length))
+        });
+        match formula {
+            Some((formula, end)) => {
+                out.push_str(&rest[..start]);
+                out.push_str(formula);
+                rest = &rest[end + PLACEHOLDER_CLOSE.len_utf8()..];
+            }
+            None => {
+                out.push_str(&rest[..after]);
+                rest = &rest[after..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The TeX of a formula that couldn't be converted, marked as an error with
+/// the reason in its tooltip
+fn error_html(tex: &str, display: bool, message: &str) -> String {
+    if display {
+        format!(
+            "<pre class=\"math-error\" title=\"{}\"><span class=\"math-error-marker\">⚠ {}</span>\n{}</pre>",
+            escape_html(message),
+            escape_html(message),
+            escape_html(tex.trim_end())
+        )
+    } else {
+        format!(
+            "<code class=\"math-error\" title=\"{}\"><span class=\"math-error-marker\">⚠</span> {}</code>",
+            escape_html(message),
+            escape_html(tex)
+        )
+    }
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Convert a TeX formula to a MathML `<math>` element, keeping the TeX as
+/// an annotation
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        at: 0,
+        depth: 0,
+        display,
+    };
+    let (nodes, ending) = parser.expression()?;
+    match ending {
+        Ending::Eof => {}
+        Ending::Brace => return Err("Unmatched }".to_string()),
+        Ending::Cell | Ending::Row => {
+            return Err("& and \\\\ belong in an environment such as aligned".to_string());
+        }
+        Ending::End(name) => return Err(format!("\\end{{{}}} without \\begin", name)),
+        Ending::Right(_) => return Err("\\right without \\left".to_string()),
+    }
+    Ok(format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"{}><semantics>{}<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { " display=\"block\"" } else { "" },
+        row(nodes),
+        escape_html(tex)
+    ))
+}
+
+/// What stopped an expression
+#[derive(Debug, PartialEq)]
+enum Ending {
+    Eof,
+    Brace,
+    Cell,
+    Row,
+    End(String),
+    Right(String),
+}
+
+/// Kinds of symbols, by the element they become
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// An identifier, `<mi>`
+    Identifier,
+    /// An operator, `<mo>`
+    Operator,
+    /// A large operator whose scripts go above and below in display math
+    Limits,
+    /// A large operator whose scripts stay at its side, such as `\int`
+    Integral,
+}
+
+fn symbol(name: &str) -> Option<(Kind, &'static str)> {
+    use Kind::{Identifier as I, Integral as N, Limits as L, Operator as O};
+    Some(match name {
+        // Greek
+        "alpha" => (I, "α"),
+        "beta" => (I, "β"),
+        "gamma" => (I, "γ"),
+        "delta" => (I, "δ"),
+        "epsilon" => (I, "ϵ"),
+        "varepsilon" => (I, "ε"),
+        "zeta" => (I, "ζ"),
+        "eta" => (I, "η"),
+        "theta" => (I, "θ"),
+        "vartheta" => (I, "ϑ"),
+        "iota" => (I, "ι"),
+        "kappa" => (I, "κ"),
+        "lambda" => (I, "λ"),
+        "mu" => (I, "μ"),
+        "nu" => (I, "ν"),
+        "xi" => (I, "ξ"),
+        "pi" => (I, "π"),
+        "varpi" => (I, "ϖ"),
+        "rho" => (I, "ρ"),
+        "varrho" => (I, "ϱ"),
+        "sigma" => (I, "σ"),
+        "varsigma" => (I, "ς"),
+        "tau" => (I, "τ"),
+        "upsilon" => (I, "υ"),
+        "phi" => (I, "ϕ"),
+        "varphi" => (I, "φ"),
+        "chi" => (I, "χ"),
+        "psi" => (I, "ψ"),
+        "omega" => (I, "ω"),
+        "Gamma" => (I, "Γ"),
+        "Delta" => (I, "Δ"),
+        "Theta" => (I, "Θ"),
+        "Lambda" => (I, "Λ"),
+        "Xi" => (I, "Ξ"),
+        "Pi" => (I, "Π"),
+        "Sigma" => (I, "Σ"),
+        "Upsilon" => (I, "Υ"),
+        "Phi" => (I, "Φ"),
+        "Psi" => (I, "Ψ"),
+        "Omega" => (I, "Ω"),
+        // Letter-like symbols
+        "infty" => (I, "∞"),
+        "partial" => (I, "∂"),
+        "nabla" => (I, "∇"),
+        "hbar" => (I, "ℏ"),
+        "ell" => (I, "ℓ"),
+        "Re" => (I, "ℜ"),
+        "Im" => (I, "ℑ"),
+        "aleph" => (I, "ℵ"),
+        "emptyset" | "varnothing" => (I, "∅"),
+        // Binary operators and relations
+        "cdot" => (O, "⋅"),
+        "times" => (O, "×"),
+        "div" => (O, "÷"),
+        "pm" => (O, "±"),
+        "mp" => (O, "∓"),
+        "ast" => (O, "∗"),
+        "star" => (O, "⋆"),
+        "circ" => (O, "∘"),
+        "bullet" => (O, "∙"),
+        "oplus" => (O, "⊕"),
+        "otimes" => (O, "⊗"),
+        "leq" | "le" => (O, "≤"),
+        "geq" | "ge" => (O, "≥"),
+        "neq" | "ne" => (O, "≠"),
+        "ll" => (O, "≪"),
+        "gg" => (O, "≫"),
+        "approx" => (O, "≈"),
+        "equiv" => (O, "≡"),
+        "sim" => (O, "∼"),
+        "simeq" => (O, "≃"),
+        "cong" => (O, "≅"),
+        "propto" => (O, "∝"),
+        "in" => (O, "∈"),
+        "notin" => (O, "∉"),
+        "ni" => (O, "∋"),
+        "subset" => (O, "⊂"),
+        "subseteq" => (O, "⊆"),
+        "supset" => (O, "⊃"),
+        "supseteq" => (O, "⊇"),
+        "cup" => (O, "∪"),
+        "cap" => (O, "∩"),
+        "setminus" => (O, "∖"),
+        "forall" => (O, "∀"),
+        "exists" => (O, "∃"),
+        "neg" | "lnot" => (O, "¬"),
+        "land" | "wedge" => (O, "∧"),
+        "lor" | "vee" => (O, "∨"),
+        "perp" => (O, "⊥"),
+        "parallel" => (O, "∥"),
+        "mid" => (O, "∣"),
+        "angle" => (O, "∠"),
+        "prime" => (O, "′"),
+        // Arrows
+        "to" | "rightarrow" => (O, "→"),
+        "leftarrow" | "gets" => (O, "←"),
+        "leftrightarrow" => (O, "↔"),
+        "Rightarrow" => (O, "⇒"),
+        "Leftarrow" => (O, "⇐"),
+        "Leftrightarrow" => (O, "⇔"),
+        "implies" => (O, "⟹"),
+        "iff" => (O, "⟺"),
+        "mapsto" => (O, "↦"),
+        "uparrow" => (O, "↑"),
+        "downarrow" => (O, "↓"),
+        // Dots
+        "ldots" | "dots" => (O, "…"),
+        "cdots" => (O, "⋯"),
+        "vdots" => (O, "⋮"),
+        "ddots" => (O, "⋱"),
+        // Delimiters
+        "langle" => (O, "⟨"),
+        "rangle" => (O, "⟩"),
+        "lfloor" => (O, "⌊"),
+        "rfloor" => (O, "⌋"),
+        "lceil" => (O, "⌈"),
+        "rceil" => (O, "⌉"),
+        "lvert" | "rvert" | "vert" => (O, "|"),
+        "lVert" | "rVert" | "Vert" | "|" => (O, "‖"),
+        "{" | "lbrace" => (O, "{"),
+        "}" | "rbrace" => (O, "}"),
+        "colon" => (O, ":"),
+        // Escaped characters
+        "%" => (O, "%"),
+        "#" => (O, "#"),
+        "$" => (I, "$"),
+        "&" => (O, "&"),
+        "_" => (I, "_"),
+        // Large operators
+        "sum" => (L, "∑"),
+        "prod" => (L, "∏"),
+        "coprod" => (L, "∐"),
+        "bigcup" => (L, "⋃"),
+        "bigcap" => (L, "⋂"),
+        "bigoplus" => (L, "⨁"),
+        "bigotimes" => (L, "⨂"),
+        "int" => (N, "∫"),
+        "iint" => (N, "∬"),
+        "iiint" => (N, "∭"),
+        "oint" => (N, "∮"),
+        _ => return None,
+    })
+}
+
+/// Functions set upright, the ones taking limits below them in display math
+fn function(name: &str) -> Option<Kind> {
+    match name {
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "sinh" | "cosh" | "tanh" | "coth"
+        | "arcsin" | "arccos" | "arctan" | "log" | "ln" | "lg" | "exp" | "det" | "dim" | "ker"
+        | "arg" | "deg" | "hom" | "gcd" | "Pr" => Some(Kind::Identifier),
+        "lim" | "liminf" | "limsup" | "max" | "min" | "sup" | "inf" => Some(Kind::Limits),
+        _ => None,
+    }
+}
+
+/// Widths of the spacing commands
+fn space(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," | "thinspace" => "0.167em",
+        ":" | ">" | "medspace" => "0.222em",
+        ";" | "thickspace" => "0.278em",
+        " " => "0.333em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        "!" | "negthinspace" => "-0.167em",
+        _ => return None,
+    })
+}
+
+/// Accents and the character drawn over or under their argument
+fn accent(name: &str) -> Option<(bool, &'static str)> {
+    Some(match name {
+        "hat" | "widehat" => (true, "^"),
+        "bar" | "overline" => (true, "¯"),
+        "vec" => (true, "→"),
+        "dot" => (true, "˙"),
+        "ddot" => (true, "¨"),
+        "tilde" | "widetilde" => (true, "~"),
+        "overbrace" => (true, "⏞"),
+        "underline" => (false, "_"),
+        "underbrace" => (false, "⏟"),
+        _ => return None,
+    })
+}
+
+/// Where a mathematical alphabet sits in Unicode, with the letters that
+/// predate it and live elsewhere
+struct Alphabet {
+    upper: u32,
+    lower: Option<u32>,
+    digits: Option<u32>,
+    exceptions: &'static [(char, char)],
+}
+
+/// Letters in one of the mathematical alphabets
+fn styled(style: &str, c: char) -> char {
+    let alphabet = match style {
+        "mathbb" => Alphabet {
+            upper: 0x1D538,
+            lower: Some(0x1D552),
+            digits: Some(0x1D7D8),
+            exceptions: &[
+                ('C', 'ℂ'),
+                ('H', 'ℍ'),
+                ('N', 'ℕ'),
+                ('P', 'ℙ'),
+                ('Q', 'ℚ'),
+                ('R', 'ℝ'),
+                ('Z', 'ℤ'),
+            ],
+        },
+        "mathcal" | "mathscr" => Alphabet {
+            upper: 0x1D49C,
+            lower: None,
+            digits: None,
+            exceptions: &[
+                ('B', 'ℬ'),
+                ('E', 'ℰ'),
+                ('F', 'ℱ'),
+                ('H', 'ℋ'),
+                ('I', 'ℐ'),
+                ('L', 'ℒ'),
+                ('M', 'ℳ'),
+                ('R', 'ℛ'),
+            ],
+        },
+        "mathfrak" => Alphabet {
+            upper: 0x1D504,
+            lower: Some(0x1D51E),
+            digits: None,
+            exceptions: &[('C', 'ℭ'), ('H', 'ℌ'), ('I', 'ℑ'), ('R', 'ℜ'), ('Z', 'ℨ')],
+        },
+        "mathbf" | "boldsymbol" => Alphabet {
+            upper: 0x1D400,
+            lower: Some(0x1D41A),
+            digits: Some(0x1D7CE),
+            exceptions: &[],
+        },
+        _ => return c,
+    };
+    if let Some(&(_, special)) = alphabet.exceptions.iter().find(|(plain, _)| *plain == c) {
+        return special;
+    }
+    let mapped = match c {
+        'A'..='Z' => Some(alphabet.upper + (c as u32 - 'A' as u32)),
+        'a'..='z' => alphabet.lower.map(|base| base + (c as u32 - 'a' as u32)),
+        '0'..='9' => alphabet.digits.map(|base| base + (c as u32 - '0' as u32)),
+        _ => None,
+    };
+    mapped.and_then(char::from_u32).unwrap_or(c)
+}
+
+fn row(nodes: Vec<String>) -> String {
+    if nodes.len() == 1 {
+        nodes.into_iter().next().unwrap_or_default()
+    } else {
+        format!("<mrow>{}</mrow>", nodes.concat())
+    }
+}
+
+fn operator(text: &str) -> String {
+    format!("<mo>{}</mo>", escape_html(text))
+}
+
+fn fence(text: &str) -> String {
+    format!(
+        "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+        escape_html(text)
+    )
+}
+
+/// A parsed atom: its MathML and the kind of operator it is, if any
+struct Atom {
+    mathml: String,
+    kind: Option<Kind>,
+}
+
+impl Atom {
+    fn plain(mathml: String) -> Self {
+        Atom { mathml, kind: None }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+    depth: usize,
+    display: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.at += 1;
+        }
+    }
+
+    /// Read a command name after its backslash: a run of letters, or one
+    /// other character
+    fn command_name(&mut self) -> Result<String, String> {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.at += 1;
+        }
+        if self.at == start {
+            let c = self
+                .peek()
+                .ok_or_else(|| "Formula ends with a backslash".to_string())?;
+            self.at += 1;
+            return Ok(c.to_string());
+        }
+        Ok(self.chars[start..self.at].iter().collect())
+    }
+
+    /// The text of a `{...}` group, with nested braces kept
+    fn raw_group(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return Err("Expected {".to_string());
+        }
+        self.at += 1;
+        let start = self.at;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => self.at += 1,
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    let text = self.chars[start..self.at].iter().collect();
+                    self.at += 1;
+                    return Ok(text);
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+            self.at += 1;
+        }
+        Err("Missing }".to_string())
+    }
+
+    /// Parse up to the end of the formula or of the enclosing construct
+    fn expression(&mut self) -> Result<(Vec<String>, Ending), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("Formula is nested too deeply".to_string());
+        }
+        let mut nodes = Vec::new();
+        let ending = loop {
+            self.skip_whitespace();
+            let Some(c) = self.peek() else {
+                break Ending::Eof;
+            };
+            match c {
+                '}' => {
+                    self.at += 1;
+                    break Ending::Brace;
+                }
+                '&' => {
+                    self.at += 1;
+                    break Ending::Cell;
+                }
+                '\\' => {
+                    let start = self.at;
+                    self.at += 1;
+                    match self.command_name()?.as_str() {
+                        "\\" | "cr" => break Ending::Row,
+                        "end" => break Ending::End(self.raw_group()?),
+                        "right" => break Ending::Right(self.delimiter()?),
+                        _ => self.at = start,
+                    }
+                }
+                _ => {}
+            }
+            let atom = self.atom(false)?;
+            nodes.push(self.scripts(atom)?);
+        };
+        self.depth -= 1;
+        Ok((nodes, ending))
+    }
+
+    /// Attach any `_` and `^` scripts following an atom
+    fn scripts(&mut self, base: Atom) -> Result<String, String> {
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.at += 1;
+                    sub = Some(self.argument()?);
+                }
+                Some('^') if sup.is_none() => {
+                    self.at += 1;
+                    sup = Some(self.argument()?);
+                }
+                Some('_') => return Err("Double subscript".to_string()),
+                Some('^') => return Err("Double superscript".to_string()),
+                Some('\'') => {
+                    // Primes are superscripts
+                    let mut primes = String::new();
+                    while self.peek() == Some('\'') {
+                        self.at += 1;
+                        primes.push('′');
+                    }
+                    let primes = operator(&primes);
+                    sup = Some(match sup {
+                        Some(sup) => format!("<mrow>{}{}</mrow>", primes, sup),
+                        None => primes,
+                    });
+                }
+                _ => break,
+            }
+        }
+        let limits = base.kind == Some(Kind::Limits) && self.display;
+        let base = base.mathml;
+        Ok(match (sub, sup, limits) {
+            (None, None, _) => base,
+            (Some(sub), None, true) => format!("<munder>{}{}</munder>", base, sub),
+            (None, Some(sup), true) => format!("<mover>{}{}</mover>", base, sup),
+            (Some(sub), Some(sup), true) => {
+                format!("<munderover>{}{}{}</munderover>", base, sub, sup)
+            }
+            (Some(sub), None, false) => format!("<msub>{}{}</msub>", base, sub),
+            (None, Some(sup), false) => format!("<msup>{}{}</msup>", base, sup),
+            (Some(sub), Some(sup), false) => {
+                format!("<msubsup>{}{}{}</msubsup>", base, sub, sup)
+            }
+        })
+    }
+
+    /// A command argument or script: a group, or a single token
+    fn argument(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err("Missing argument at the end of the formula".to_string()),
+            Some('}') => Err("Missing argument before }".to_string()),
+            Some('&') => Err("Missing argument before &".to_string()),
+            _ => Ok(self.atom(true)?.mathml),
+        }
+    }
+
+    /// The contents of a `{...}` group that has been opened
+    fn group(&mut self) -> Result<String, String> {
+        let (nodes, ending) = self.expression()?;
+        match ending {
+            Ending::Brace => Ok(row(nodes)),
+            Ending::Eof => Err("Missing }".to_string()),
+            Ending::Cell | Ending::Row => Err("& and \\\\ belong in an environment".to_string()),
+            Ending::End(name) => Err(format!("\\end{{{}}} inside a group", name)),
+            Ending::Right(_) => Err("\\right without \\left".to_string()),
+        }
+    }
+
+    /// One token or construct. `single` limits numbers to one digit, as in
+    /// `x^23`.
+    fn atom(&mut self, single: bool) -> Result<Atom, String> {
+        self.skip_whitespace();
+        let c = self
+            .peek()
+            .ok_or_else(|| "Unexpected end of formula".to_string())?;
+        self.at += 1;
+        Ok(match c {
+            '{' => Atom::plain(self.group()?),
+            '\\' => return self.command(),
+            '0'..='9' | '.'
+                if c.is_ascii_digit() || self.peek().is_some_and(|c| c.is_ascii_digit()) =>
+            {
+                let start = self.at - 1;
+                if !single {
+                    while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                        self.at += 1;
+                    }
+                }
+                let number: String = self.chars[start..self.at].iter().collect();
+                Atom::plain(format!("<mn>{}</mn>", number))
+            }
+            '^' | '_' => {
+                // A script with nothing before it
+                self.at -= 1;
+                Atom::plain("<mrow></mrow>".to_string())
+            }
+            '~' => Atom::plain("<mspace width=\"0.333em\"></mspace>".to_string()),
+            '-' => Atom::plain(operator("−")),
+            '*' => Atom::plain(operator("∗")),
+            '\'' => Atom::plain(operator("′")),
+            c if c.is_alphabetic() => Atom::plain(format!("<mi>{}</mi>", c)),
+            c if c.is_numeric() => Atom::plain(format!("<mn>{}</mn>", c)),
+            c => Atom::plain(operator(&c.to_string())),
+        })
+    }
+
+    fn command(&mut self) -> Result<Atom, String> {
+        let name = self.command_name()?;
+        if let Some((kind, text)) = symbol(&name) {
+            let mathml = match kind {
+                Kind::Identifier => format!("<mi>{}</mi>", escape_html(text)),
+                Kind::Operator => operator(text),
+                Kind::Limits => format!("<mo movablelimits=\"true\">{}</mo>", text),
+                Kind::Integral => format!("<mo largeop=\"true\">{}</mo>", text),
+            };
+            return Ok(Atom {
+                mathml,
+                kind: Some(kind),
+            });
+        }
+        if let Some(kind) = function(&name) {
+            let mathml = match kind {
+                Kind::Limits => format!("<mo movablelimits=\"true\" form=\"prefix\">{}</mo>", name),
+                _ => format!("<mi>{}</mi>", name),
+            };
+            return Ok(Atom {
+                mathml,
+                kind: Some(kind),
+            });
+        }
+        if let Some(width) = space(&name) {
+            return Ok(Atom::plain(format!(
+                "<mspace width=\"{}\"></mspace>",
+                width
+            )));
+        }
+        if let Some((over, mark)) = accent(&name) {
+            let base = self.argument()?;
+            let mark = format!("<mo stretchy=\"true\">{}</mo>", escape_html(mark));
+            return Ok(Atom::plain(if over {
+                format!("<mover accent=\"true\">{}{}</mover>", base, mark)
+            } else {
+                format!("<munder accentunder=\"true\">{}{}</munder>", base, mark)
+            }));
+        }
+
+        Ok(Atom::plain(match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.argument()?;
+                let denominator = self.argument()?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let top = self.argument()?;
+                let bottom = self.argument()?;
+                format!(
+                    "<mrow>{}<mfrac linethickness=\"0\">{}{}</mfrac>{}</mrow>",
+                    fence("("),
+                    top,
+                    bottom,
+                    fence(")")
+                )
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                let index = if self.peek() == Some('[') {
+                    self.at += 1;
+                    let start = self.at;
+                    let end = self.chars[start..]
+                        .iter()
+                        .position(|&c| c == ']')
+                        .ok_or_else(|| "Missing ] after \\sqrt[".to_string())?;
+                    let index: String = self.chars[start..start + end].iter().collect();
+                    self.at = start + end + 1;
+                    Some(to_row(&index, self.display)?)
+                } else {
+                    None
+                };
+                let radicand = self.argument()?;
+                match index {
+                    Some(index) => format!("<mroot>{}{}</mroot>", radicand, index),
+                    None => format!("<msqrt>{}</msqrt>", radicand),
+                }
+            }
+            "text" | "textrm" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape_html(&self.raw_group()?))
+            }
+            "textit" => format!(
+                "<mtext mathvariant=\"italic\">{}</mtext>",
+                escape_html(&self.raw_group()?)
+            ),
+            "textbf" => format!(
+                "<mtext mathvariant=\"bold\">{}</mtext>",
+                escape_html(&self.raw_group()?)
+            ),
+            "mathrm" | "operatorname" => {
+                let text = self.style_argument()?;
+                if text.chars().all(|c| c.is_alphanumeric() || c == ' ') {
+                    let text: String = text.chars().filter(|c| *c != ' ').collect();
+                    format!("<mi mathvariant=\"normal\">{}</mi>", escape_html(&text))
+                } else {
+                    to_row(&text, self.display)?
+                }
+            }
+            "mathbb" | "mathcal" | "mathscr" | "mathfrak" | "mathbf" | "boldsymbol" | "mathit"
+            | "mathsf" | "mathtt" => {
+                let text = self.style_argument()?;
+                if text.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ') {
+                    let nodes: String = text
+                        .chars()
+                        .filter(|c| *c != ' ')
+                        .map(|c| {
+                            let styled = styled(&name, c);
+                            let variant = match name.as_str() {
+                                "mathsf" => " mathvariant=\"sans-serif\"",
+                                "mathtt" => " mathvariant=\"monospace\"",
+                                _ => "",
+                            };
+                            if c.is_ascii_digit() {
+                                format!("<mn>{}</mn>", styled)
+                            } else {
+                                format!("<mi{}>{}</mi>", variant, styled)
+                            }
+                        })
+                        .collect();
+                    if text.chars().filter(|c| *c != ' ').count() == 1 {
+                        nodes
+                    } else {
+                        format!("<mrow>{}</mrow>", nodes)
+                    }
+                } else {
+                    // Commands inside keep their own look
+                    to_row(&text, self.display)?
+                }
+            }
+            "left" => {
+                let open = self.delimiter()?;
+                let (nodes, ending) = self.expression()?;
+                let Ending::Right(close) = ending else {
+                    return Err("\\left without \\right".to_string());
+                };
+                format!(
+                    "<mrow>{}{}{}</mrow>",
+                    fence(&open),
+                    nodes.concat(),
+                    fence(&close)
+                )
+            }
+            "begin" => {
+                let environment = self.raw_group()?;
+                self.environment(&environment)?
+            }
+            "limits" | "nolimits" | "displaystyle" | "textstyle" | "scriptstyle" | "big"
+            | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" => String::new(),
+            _ => return Err(format!("Unknown command \\{}", name)),
+        }))
+    }
+
+    /// The argument of a font command, as text
+    fn style_argument(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.peek() == Some('{') {
+            return self.raw_group();
+        }
+        let c = self
+            .peek()
+            .ok_or_else(|| "Missing argument at the end of the formula".to_string())?;
+        self.at += 1;
+        Ok(c.to_string())
+    }
+
+    /// The delimiter after `\left` or `\right`; `.` is none
+    fn delimiter(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let c = self
+            .peek()
+            .ok_or_else(|| "Missing delimiter after \\left or \\right".to_string())?;
+        self.at += 1;
+        match c {
+            '.' => Ok(String::new()),
+            '\\' => {
+                let name = self.command_name()?;
+                match symbol(&name) {
+                    Some((Kind::Operator, text)) => Ok(text.to_string()),
+                    _ => Err(format!("\\{} is not a delimiter", name)),
+                }
+            }
+            '(' | ')' | '[' | ']' | '|' | '/' | '<' | '>' => Ok(match c {
+                '<' => "⟨".to_string(),
+                '>' => "⟩".to_string(),
+                c => c.to_string(),
+            }),
+            c => Err(format!("{} is not a delimiter", c)),
+        }
+    }
+
+    /// A matrix or alignment, up to its `\end`
+    fn environment(&mut self, name: &str) -> Result<String, String> {
+        let (open, close, align) = match name.trim_end_matches('*') {
+            "matrix" | "smallmatrix" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("‖", "‖", None),
+            "cases" => ("{", "", Some("left left")),
+            "aligned" | "align" | "split" | "alignat" | "eqnarray" => ("", "", Some("right left")),
+            "gathered" | "gather" => ("", "", None),
+            "array" => {
+                // Column specifications are not used
+                self.raw_group()?;
+                ("", "", None)
+            }
+            _ => return Err(format!("Unknown environment {}", name)),
+        };
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            let (nodes, ending) = self.expression()?;
+            cells.push(format!("<mtd>{}</mtd>", row(nodes)));
+            match ending {
+                Ending::Cell => {}
+                Ending::Row => rows.push(std::mem::take(&mut cells)),
+                Ending::End(end) if end == name => break,
+                Ending::End(end) => {
+                    return Err(format!("\\begin{{{}}} ended by \\end{{{}}}", name, end));
+                }
+                _ => return Err(format!("Missing \\end{{{}}}", name)),
+            }
+        }
+        // A trailing `\\` leaves an empty last row
+        if cells.len() > 1
+            || cells
+                .first()
+                .is_some_and(|cell| cell != "<mtd><mrow></mrow></mtd>")
+        {
+            rows.push(cells);
+        }
+
+        let align = align
+            .map(|align| format!(" columnalign=\"{}\"", align))
+            .unwrap_or_default();
+        let table = format!(
+            "<mtable{}>{}</mtable>",
+            align,
+            rows.into_iter()
+                .map(|cells| format!("<mtr>{}</mtr>", cells.concat()))
+                .collect::<String>()
+        );
+        Ok(if open.is_empty() && close.is_empty() {
+            table
+        } else {
+            format!(
+                "<mrow>{}{}{}</mrow>",
+                if open.is_empty() {
+                    String::new()
+                } else {
+                    fence(open)
+                },
+                table,
+                if close.is_empty() {
+                    String::new()
+                } else {
+                    fence(close)
+                }
+            )
+        })
+    }
+}
+
+/// A piece of TeX converted on its own, as a row
+fn to_row(tex: &str, display: bool) -> Result<String, String> {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        at: 0,
+        depth: 0,
+        display,
+    };
+    match parser.expression()? {
+        (nodes, Ending::Eof) => Ok(row(nodes)),
+        _ => Err(format!("Unbalanced formula: {}", tex)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(tex: &str) -> String {
+        let mathml = to_mathml(tex, false).unwrap();
+        let start = mathml.find("<semantics>").unwrap() + "<semantics>".len();
+        let end = mathml.find("<annotation").unwrap();
+        mathml[start..end].to_string()
+    }
+
+    #[test]
+    fn test_to_mathml() {
+        assert_eq!(
+            body("x^2 + 1"),
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1</mn></mrow>"
+        );
+        assert_eq!(
+            body("\\frac{a}{b_1}"),
+            "<mfrac><mi>a</mi><msub><mi>b</mi><mn>1</mn></msub></mfrac>"
+        );
+        assert_eq!(body("\\sqrt[3]{x}"), "<mroot><mi>x</mi><mn>3</mn></mroot>");
+        assert_eq!(
+            body("\\alpha \\leq \\pi"),
+            "<mrow><mi>α</mi><mo>≤</mo><mi>π</mi></mrow>"
+        );
+        assert_eq!(body("\\mathbb{R}"), "<mi>ℝ</mi>");
+        assert_eq!(
+            body("\\text{if } x < 0"),
+            "<mrow><mtext>if </mtext><mi>x</mi><mo>&lt;</mo><mn>0</mn></mrow>"
+        );
+        assert_eq!(body("f'"), "<msup><mi>f</mi><mo>′</mo></msup>");
+        assert_eq!(
+            body("\\left( x \\right)"),
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mi>x</mi><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"
+        );
+
+        // Large operators take limits below and above in display math
+        let display = to_mathml("\\sum_{i=1}^n i", true).unwrap();
+        assert!(
+            display.starts_with(
+                "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\">"
+            )
+        );
+        assert!(display.contains("<munderover><mo movablelimits=\"true\">∑</mo>"));
+        assert!(
+            display.contains(
+                "<annotation encoding=\"application/x-tex\">\\sum_{i=1}^n i</annotation>"
+            )
+        );
+        assert!(body("\\sum_{i=1}^n i").contains("<msubsup>"));
+    }
+
+    #[test]
+    fn test_environments() {
+        assert_eq!(
+            body("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}"),
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mtable><mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr><mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr></mtable><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"
+        );
+        let cases = body("|x| = \\begin{cases} x & x \\ge 0 \\\\ -x & x < 0 \\\\ \\end{cases}");
+        assert!(cases.contains("<mtable columnalign=\"left left\">"));
+        assert_eq!(cases.matches("<mtr>").count(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            to_mathml("\\foo", false),
+            Err("Unknown command \\foo".to_string())
+        );
+        assert_eq!(to_mathml("{x", false), Err("Missing }".to_string()));
+        assert_eq!(to_mathml("x}", false), Err("Unmatched }".to_string()));
+        assert_eq!(
+            to_mathml("x^1^2", false),
+            Err("Double superscript".to_string())
+        );
+        assert!(to_mathml("\\left( x", false).is_err());
+        assert!(to_mathml("\\begin{matrix} a", false).is_err());
+        assert!(to_mathml(&"{".repeat(100), false).is_err());
+    }
+
+    #[test]
+    fn test_extract_and_restore() {
+        let html = "<p>Euler: <code class=\"language-math math-inline\">e^{i\\pi} &lt; 1</code> and <code class=\"language-math math-inline\">\\oops</code></p>\n\
+                    <pre><code class=\"language-math math-display\">x = 1\n</code></pre>";
+        let (stripped, formulas) = extract(html, "test.md");
+        assert_eq!(formulas.len(), 3);
+        assert!(!stripped.contains("math-inline"));
+
+        let restored = restore(&stripped, &formulas);
+        assert!(
+            restored
+                .contains("<msup><mi>e</mi><mrow><mi>i</mi><mi>π</mi></mrow></msup><mo>&lt;</mo>")
+        );
+        assert!(restored.contains(
+            "<code class=\"math-error\" title=\"Unknown command \\oops\"><span class=\"math-error-marker\">⚠</span> \\oops</code>"
+        ));
+        assert!(restored.contains("display=\"block\""));
+        // Text that only looks like a placeholder is left alone
+        assert_eq!(
+            restore("a \u{E000}9\u{E001} b", &formulas),
+            "a \u{E000}9\u{E001} b"
+        );
+    }
+}
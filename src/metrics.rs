@@ -0,0 +1,159 @@
+use crate::ipfilter::IpAccess;
+use crate::{config, health, log, ratelimit};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Status};
+use rocket::{Orbit, Request, Response, Rocket, Route, get, routes};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Total number of responses sent since launch
+static REQUESTS_SERVED: AtomicU64 = AtomicU64::new(0);
+
+/// When the server started accepting connections
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+/// Number of responses sent since launch
+pub fn requests_served() -> u64 {
+    REQUESTS_SERVED.load(Ordering::Relaxed)
+}
+
+/// Time since the server started accepting connections
+pub fn uptime() -> Duration {
+    STARTED_AT
+        .get()
+        .map(|started| started.elapsed())
+        .unwrap_or_default()
+}
+
+/// Fairing that tracks server lifetime and request counts
+pub struct Metrics;
+
+#[rocket::async_trait]
+impl Fairing for Metrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Barleywine Metrics",
+            kind: Kind::Liftoff | Kind::Response | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        let _ = STARTED_AT.set(Instant::now());
+    }
+
+    async fn on_response<'r>(&self, _request: &'r Request<'_>, _response: &mut Response<'r>) {
+        REQUESTS_SERVED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        log::log_server_shutdown();
+        log::log_barleywine(&format!(
+            "Stopped accepting connections, draining in-flight requests (grace {}s)",
+            config::get_config().shutdown.grace
+        ));
+    }
+}
+
+/// Prometheus text exposition of the server's counters. Unlike the health
+/// probes it is subject to the IP rules, so scrapers can be singled out.
+#[get("/metrics")]
+fn metrics(
+    remote_addr: Option<SocketAddr>,
+    origin: &Origin<'_>,
+    _ip: IpAccess,
+) -> (ContentType, String) {
+    health::log_internal_access(remote_addr, origin, Status::Ok);
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        render(),
+    )
+}
+
+/// Render every metric in the Prometheus text format
+pub fn render() -> String {
+    let config = config::get_config();
+    let metrics: [(&str, &str, &str, String); 7] = [
+        (
+            "barleywine_requests_served_total",
+            "counter",
+            "Responses sent since launch",
+            requests_served().to_string(),
+        ),
+        (
+            "barleywine_uptime_seconds",
+            "gauge",
+            "Seconds since the server started accepting connections",
+            uptime().as_secs().to_string(),
+        ),
+        (
+            "barleywine_requests_in_flight",
+            "gauge",
+            "Requests currently being handled",
+            ratelimit::in_flight().to_string(),
+        ),
+        (
+            "barleywine_max_concurrent_requests",
+            "gauge",
+            "Configured concurrent request cap (0 means no cap)",
+            config.security.rate_limit.max_concurrent.to_string(),
+        ),
+        (
+            "barleywine_rate_limited_total",
+            "counter",
+            "Requests refused with 429 by the rate limiter",
+            ratelimit::rate_limited_total().to_string(),
+        ),
+        (
+            "barleywine_load_shed_total",
+            "counter",
+            "Requests refused with 503 by the concurrent request cap",
+            ratelimit::load_shed_total().to_string(),
+        ),
+        (
+            "barleywine_rate_limit_clients",
+            "gauge",
+            "Client budgets currently tracked by the rate limiter",
+            ratelimit::tracked_clients().to_string(),
+        ),
+    ];
+
+    let mut output = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} {}", name, kind);
+        let _ = writeln!(output, "{} {}", name, value);
+    }
+    output
+}
+
+/// Routes to mount under the internal endpoint prefix
+pub fn routes() -> Vec<Route> {
+    routes![metrics]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let _ = config::init_config(None);
+        let rocket = rocket::build().mount(&config::get_config().internal.prefix, routes());
+        let client = Client::tracked(rocket).unwrap();
+        let response = client.get("/_barleywine/metrics").dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type().map(|ct| ct.to_string()),
+            Some("text/plain; version=0.0.4".to_string())
+        );
+        let body = response.into_string().unwrap();
+        assert!(body.contains("# TYPE barleywine_rate_limited_total counter"));
+        assert!(body.contains("barleywine_max_concurrent_requests 0"));
+    }
+}
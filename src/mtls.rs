@@ -0,0 +1,244 @@
+use crate::config::{self, ClientCertRule};
+use crate::{log, web};
+use rocket::Request;
+use rocket::http::Status;
+use rocket::mtls::{Certificate, x509};
+use rocket::request::{FromRequest, Outcome};
+use std::net::IpAddr;
+
+/// The verified client certificate subject for a request, when the path
+/// requires one. Requests to unprotected paths always succeed with `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientIdentity(pub Option<String>);
+
+/// Subject and alternative names read from a client certificate
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientNames {
+    pub subject: String,
+    pub sans: Vec<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientIdentity {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        let path = request.uri().path();
+        let Some(rule) = find_rule(&config.security.client_certs, path.as_str()) else {
+            return Outcome::Success(ClientIdentity(None));
+        };
+
+        let names = match request.guard::<Certificate<'_>>().await {
+            Outcome::Success(certificate) => Some(client_names(&certificate)),
+            _ => None,
+        };
+        match check(rule, names.as_ref()) {
+            Ok(subject) => Outcome::Success(ClientIdentity(Some(subject))),
+            Err(reason) => {
+                log::log_warning(&format!(
+                    "Client certificate rejected for {} from {}: {}",
+                    path,
+                    request
+                        .remote()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_else(|| "unknown".to_string()),
+                    reason
+                ));
+                web::log_denied(
+                    request,
+                    Status::Forbidden,
+                    names.as_ref().map(|names| names.subject.as_str()),
+                );
+                Outcome::Error((Status::Forbidden, reason))
+            }
+        }
+    }
+}
+
+/// The rule for the longest configured prefix covering a request path, which
+/// is normalized first so encoded spellings of a prefix are covered too
+pub fn find_rule<'a>(rules: &'a [ClientCertRule], path: &str) -> Option<&'a ClientCertRule> {
+    let path = config::normalize_path(path);
+    rules
+        .iter()
+        .filter(|rule| config::path_has_prefix(&path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.trim_end_matches('/').len())
+}
+
+/// Decide whether a client certificate satisfies a rule, returning its
+/// subject when it does and the reason when it doesn't. Deny patterns win;
+/// with no allow patterns any certificate signed by the client CA is accepted.
+pub fn check(rule: &ClientCertRule, names: Option<&ClientNames>) -> Result<String, String> {
+    let names = names.ok_or_else(|| "no verified client certificate presented".to_string())?;
+
+    let subject_matches = |patterns: &[String]| {
+        patterns
+            .iter()
+            .any(|p| config::matches_pattern(p, &names.subject))
+    };
+    let san_matches = |patterns: &[String]| {
+        names
+            .sans
+            .iter()
+            .find(|san| patterns.iter().any(|p| config::matches_pattern(p, san)))
+    };
+
+    if subject_matches(&rule.deny_subjects) {
+        return Err(format!("subject '{}' is denied", names.subject));
+    }
+    if let Some(san) = san_matches(&rule.deny_sans) {
+        return Err(format!("alternative name '{}' is denied", san));
+    }
+
+    let restricted = !rule.allow_subjects.is_empty() || !rule.allow_sans.is_empty();
+    if restricted
+        && !subject_matches(&rule.allow_subjects)
+        && san_matches(&rule.allow_sans).is_none()
+    {
+        return Err(format!(
+            "subject '{}' does not match any allowed subject or alternative name",
+            names.subject
+        ));
+    }
+
+    Ok(names.subject.clone())
+}
+
+/// Read the subject and the DNS, email, URI and IP alternative names
+pub fn client_names(certificate: &Certificate<'_>) -> ClientNames {
+    let mut sans = Vec::new();
+    for extension in certificate.extensions() {
+        if let x509::ParsedExtension::SubjectAlternativeName(names) = extension.parsed_extension() {
+            for name in &names.general_names {
+                match name {
+                    x509::GeneralName::DNSName(value)
+                    | x509::GeneralName::RFC822Name(value)
+                    | x509::GeneralName::URI(value) => sans.push(value.to_string()),
+                    x509::GeneralName::IPAddress(bytes) => {
+                        if let Some(ip) = ip_from_bytes(bytes) {
+                            sans.push(ip.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    ClientNames {
+        subject: certificate.subject().to_string(),
+        sans,
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+    use rocket::{get, routes};
+
+    fn rule(prefix: &str) -> ClientCertRule {
+        ClientCertRule {
+            prefix: prefix.to_string(),
+            allow_subjects: vec![],
+            allow_sans: vec![],
+            deny_subjects: vec![],
+            deny_sans: vec![],
+        }
+    }
+
+    fn names(subject: &str, sans: &[&str]) -> ClientNames {
+        ClientNames {
+            subject: subject.to_string(),
+            sans: sans.iter().map(|san| san.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_find_rule_prefers_longest_prefix() {
+        let rules = vec![rule("/internal"), rule("/internal/ops/")];
+        assert_eq!(
+            find_rule(&rules, "/internal/ops/runbook.md")
+                .unwrap()
+                .prefix,
+            "/internal/ops/"
+        );
+        assert_eq!(find_rule(&rules, "/internal").unwrap().prefix, "/internal");
+        assert!(find_rule(&rules, "/internals.md").is_none());
+    }
+
+    #[test]
+    fn test_find_rule_normalizes_path() {
+        let rules = vec![rule("/internal")];
+        assert!(find_rule(&rules, "/%69nternal/secret.txt").is_some());
+        assert!(find_rule(&rules, "//internal/secret.txt").is_some());
+        assert!(find_rule(&rules, "/docs/../internal/secret.txt").is_some());
+        assert!(find_rule(&rules, "/internal%2Fsecret.txt").is_some());
+    }
+
+    #[test]
+    fn test_check_allow_and_deny() {
+        let mut docs = rule("/internal");
+        assert!(check(&docs, None).is_err());
+        assert!(check(&docs, Some(&names("CN=anyone", &[]))).is_ok());
+
+        docs.allow_sans = vec!["*.build.example.com".to_string()];
+        docs.allow_subjects = vec!["CN=ops-*, O=Example".to_string()];
+        docs.deny_subjects = vec!["CN=ops-retired, *".to_string()];
+        assert!(
+            check(
+                &docs,
+                Some(&names("CN=ci, O=Example", &["ci.build.example.com"]))
+            )
+            .is_ok()
+        );
+        assert!(check(&docs, Some(&names("CN=ops-alice, O=Example", &[]))).is_ok());
+        assert!(check(&docs, Some(&names("CN=ci, O=Example", &["ci.example.com"]))).is_err());
+        assert!(check(&docs, Some(&names("CN=ops-retired, O=Example", &[]))).is_err());
+    }
+
+    #[get("/whoami")]
+    fn whoami(certificate: Certificate<'_>) -> String {
+        let names = client_names(&certificate);
+        format!("{} {}", names.subject, names.sans.join(","))
+    }
+
+    #[test]
+    fn test_client_names_from_generated_certificate() {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::default();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec![
+            "ci.build.example.com".to_string(),
+            "10.0.0.7".to_string(),
+        ])
+        .unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "ci-runner");
+        let client = params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        let rocket = rocket::build().mount("/", routes![whoami]);
+        let local = Client::tracked(rocket).unwrap();
+        let response = local
+            .get("/whoami")
+            .identity(client.pem().as_bytes())
+            .dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "CN=ci-runner ci.build.example.com,10.0.0.7"
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use crate::auth::Challenge;
+use crate::config::{self, Config};
+use crate::ipfilter::{self, Cidr};
+use crate::{listener, log, web};
+use rocket::Data;
+use rocket::Request;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::tokio::io::AsyncReadExt;
+use rocket::tokio::net::TcpStream;
+use rocket::tokio::time::timeout;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Time a proxy has to send the PROXY protocol header
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signature opening a PROXY protocol v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest PROXY protocol v1 header, including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// The scheme a trusted proxy reported the client used, if any
+#[derive(Debug, Clone, Default)]
+struct ForwardedProto(Option<String>);
+
+/// Whether the connection came from a trusted proxy rather than the client
+#[derive(Debug, Clone, Copy, Default)]
+struct FromTrustedProxy(bool);
+
+/// One proxy hop from a forwarding header: the address it received the
+/// request from and the scheme it was received over
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub addr: Option<SocketAddr>,
+    pub proto: Option<String>,
+}
+
+/// The client as seen through the trusted proxies
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub addr: SocketAddr,
+    pub proto: Option<String>,
+}
+
+/// Walk the hops right to left from the connecting peer, stopping at the
+/// first address that isn't a trusted proxy. An unreadable hop ends the walk
+/// at the proxy that reported it.
+pub fn resolve(peer: SocketAddr, trusted: &[Cidr], hops: &[Hop]) -> Resolved {
+    let mut resolved = Resolved {
+        addr: peer,
+        proto: None,
+    };
+    for hop in hops.iter().rev() {
+        if !trusted.iter().any(|cidr| cidr.contains(resolved.addr.ip())) {
+            break;
+        }
+        let Some(addr) = hop.addr else {
+            break;
+        };
+        resolved = Resolved {
+            addr,
+            proto: hop.proto.clone(),
+        };
+    }
+    resolved
+}
+
+/// Hops from RFC 7239 `Forwarded` header values, in order
+pub fn forwarded_hops(values: &[&str]) -> Vec<Hop> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            let mut hop = Hop {
+                addr: None,
+                proto: None,
+            };
+            for pair in element.split(';') {
+                let Some((name, value)) = pair.trim().split_once('=') else {
+                    continue;
+                };
+                let value = value.trim().trim_matches('"');
+                if name.eq_ignore_ascii_case("for") {
+                    hop.addr = parse_node(value);
+                } else if name.eq_ignore_ascii_case("proto") {
+                    hop.proto = Some(value.to_ascii_lowercase());
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+/// Hops from `X-Forwarded-For`, paired with `X-Forwarded-Proto` entries when
+/// both list the same number of hops. Otherwise only the last proto, set by
+/// the nearest proxy, is used.
+pub fn x_forwarded_hops(for_values: &[&str], proto_values: &[&str]) -> Vec<Hop> {
+    let split = |values: &[&str]| -> Vec<String> {
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|entry| entry.trim().to_string())
+            .collect()
+    };
+    let addrs = split(for_values);
+    let protos = split(proto_values);
+
+    let last = addrs.len().saturating_sub(1);
+    addrs
+        .iter()
+        .enumerate()
+        .map(|(index, addr)| Hop {
+            addr: parse_node(addr),
+            proto: if protos.len() == addrs.len() {
+                Some(protos[index].to_ascii_lowercase())
+            } else if index == last {
+                protos.last().map(|proto| proto.to_ascii_lowercase())
+            } else {
+                None
+            },
+        })
+        .collect()
+}
+
+/// Parse `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` or `[2001:db8::1]:4711`.
+/// Obfuscated identifiers and `unknown` give `None`.
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 0));
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 0))
+}
+
+/// The configured trusted proxy networks
+fn trusted_networks(config: &Config) -> Vec<Cidr> {
+    config
+        .server
+        .proxy
+        .trusted
+        .iter()
+        .filter_map(|entry| ipfilter::parse_networks(entry).ok())
+        .flatten()
+        .collect()
+}
+
+/// Replaces the request's remote address with the client address reported
+/// by trusted proxies, so every later guard, fairing and log sees the client
+pub struct ClientAddress;
+
+#[rocket::async_trait]
+impl Fairing for ClientAddress {
+    fn info(&self) -> Info {
+        Info {
+            name: "Client Address",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let Some(mut peer) = request.remote() else {
+            return;
+        };
+        let relayed = listener::relayed_client(peer);
+        if let Some(client) = relayed {
+            peer = client.addr;
+            request.set_remote(client.addr);
+        }
+
+        let config = config::get_config();
+        let trusted = trusted_networks(&config);
+        // PROXY protocol peers were checked against the trusted networks
+        // when their connection was accepted
+        let from_proxy = relayed.is_some_and(|client| client.proxied)
+            || trusted.iter().any(|cidr| cidr.contains(peer.ip()));
+        request.local_cache(|| FromTrustedProxy(from_proxy));
+        if trusted.is_empty() {
+            return;
+        }
+
+        let headers = request.headers();
+        let forwarded: Vec<&str> = headers.get("Forwarded").collect();
+        let hops = if forwarded.is_empty() {
+            let for_values: Vec<&str> = headers.get("X-Forwarded-For").collect();
+            let proto_values: Vec<&str> = headers.get("X-Forwarded-Proto").collect();
+            x_forwarded_hops(&for_values, &proto_values)
+        } else {
+            forwarded_hops(&forwarded)
+        };
+
+        let resolved = resolve(peer, &trusted, &hops);
+        // A proto is only believed when the hop reporting it was trusted
+        let proto = resolved.proto.or_else(|| {
+            (hops.is_empty() && trusted.iter().any(|cidr| cidr.contains(peer.ip())))
+                .then(|| headers.get_one("X-Forwarded-Proto"))
+                .flatten()
+                .map(|proto| proto.trim().to_ascii_lowercase())
+        });
+        if resolved.addr != peer {
+            request.set_remote(resolved.addr);
+        }
+        request.local_cache(|| ForwardedProto(proto));
+    }
+}
+
+/// Whether the client reached us over HTTPS, natively or through a trusted
+/// proxy that said so
+pub fn is_https(request: &Request<'_>, config: &Config) -> bool {
+    match request.local_cache(ForwardedProto::default).0.as_deref() {
+        Some(proto) => proto == "https",
+        None => config.server.tls.is_some(),
+    }
+}
+
+/// Whether the request was sent by a trusted proxy, so headers it adds can
+/// be believed
+pub fn from_trusted_proxy(request: &Request<'_>) -> bool {
+    request.local_cache(FromTrustedProxy::default).0
+}
+
+/// Whether the request came over HTTPS, for handlers setting cookies
+pub struct Https(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Https {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(Https(is_https(request, &config::get_config())))
+    }
+}
+
+/// Request guard sending clients a trusted proxy reached over plain HTTP to
+/// the HTTPS version of the page, when `https_redirect` is on
+pub struct RequireHttps;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireHttps {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        let proto = request.local_cache(ForwardedProto::default).0.as_deref();
+        if !config.server.proxy.https_redirect || proto != Some("http") {
+            return Outcome::Success(RequireHttps);
+        }
+        let Some(host) = request.host() else {
+            return Outcome::Success(RequireHttps);
+        };
+
+        let location = format!("https://{}{}", host.domain(), request.uri());
+        request.local_cache(|| Challenge::Redirect(location));
+        web::log_denied(request, Status::PermanentRedirect, None);
+        Outcome::Error((Status::PermanentRedirect, "plain HTTP".to_string()))
+    }
+}
+
+/// Check a connection from a PROXY protocol proxy and read its header,
+/// returning the client it carries. Refused connections are logged and get
+/// `None`.
+pub async fn accept(client: &mut TcpStream, peer: SocketAddr) -> Option<SocketAddr> {
+    let config = config::get_config();
+    if !trusted_networks(&config)
+        .iter()
+        .any(|cidr| cidr.contains(peer.ip()))
+    {
+        log::log_warning(&format!(
+            "Refused PROXY protocol connection from untrusted {}",
+            peer
+        ));
+        return None;
+    }
+
+    match timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(client)).await {
+        Ok(Ok(source)) => Some(source.unwrap_or(peer)),
+        Ok(Err(e)) => {
+            log::log_warning(&format!("Bad PROXY protocol header from {}: {}", peer, e));
+            None
+        }
+        Err(_) => {
+            log::log_warning(&format!("No PROXY protocol header from {}", peer));
+            None
+        }
+    }
+}
+
+/// Read a PROXY protocol v1 or v2 header, returning the client address it
+/// carries (`None` for health checks and unknown address families)
+async fn read_proxy_header(stream: &mut TcpStream) -> Result<Option<SocketAddr>, String> {
+    let mut start = [0u8; 12];
+    stream
+        .read_exact(&mut start[..6])
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if &start[..6] == b"PROXY " {
+        let mut line = start[..6].to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err("v1 header too long".to_string());
+            }
+            line.push(stream.read_u8().await.map_err(|e| e.to_string())?);
+        }
+        return parse_v1(&line);
+    }
+
+    stream
+        .read_exact(&mut start[6..])
+        .await
+        .map_err(|e| e.to_string())?;
+    if start != V2_SIGNATURE {
+        return Err("missing PROXY protocol signature".to_string());
+    }
+    let mut header = [0u8; 4];
+    stream
+        .read_exact(&mut header)
+        .await
+        .map_err(|e| e.to_string())?;
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let mut addresses = vec![0u8; len];
+    stream
+        .read_exact(&mut addresses)
+        .await
+        .map_err(|e| e.to_string())?;
+    parse_v2(header[0], header[1], &addresses)
+}
+
+/// Parse a v1 line such as `PROXY TCP4 192.0.2.1 198.51.100.1 4711 443\r\n`
+pub fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>, String> {
+    let line = std::str::from_utf8(line).map_err(|_| "v1 header is not text".to_string())?;
+    let fields: Vec<&str> = line.trim_end().split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| format!("bad source address '{}'", source))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| format!("bad source port '{}'", source_port))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err("malformed v1 header".to_string()),
+    }
+}
+
+/// Parse the fields following the v2 signature
+pub fn parse_v2(
+    version_command: u8,
+    family: u8,
+    addresses: &[u8],
+) -> Result<Option<SocketAddr>, String> {
+    if version_command >> 4 != 2 {
+        return Err("unsupported version".to_string());
+    }
+    // LOCAL connections come from the proxy itself, such as health checks
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+    match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        2 if addresses.len() >= 36 => {
+            let octets: [u8; 16] = addresses[..16].try_into().unwrap_or_default();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        1 | 2 => Err("truncated address block".to_string()),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted() -> Vec<Cidr> {
+        ["10.0.0.0/8", "2001:db8:cafe::/48"]
+            .iter()
+            .map(|cidr| Cidr::parse(cidr).unwrap())
+            .collect()
+    }
+
+    fn addr(text: &str) -> SocketAddr {
+        parse_node(text).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_walks_right_to_left() {
+        let hops = x_forwarded_hops(&["198.51.100.7, 203.0.113.9, 10.0.0.2"], &[]);
+        // The rightmost untrusted address is the client; anything to its
+        // left could have been written by the client itself
+        assert_eq!(
+            resolve(addr("10.0.0.1:5000"), &trusted(), &hops).addr,
+            addr("203.0.113.9")
+        );
+        // Headers from an untrusted peer are ignored
+        assert_eq!(
+            resolve(addr("192.0.2.1:5000"), &trusted(), &hops).addr,
+            addr("192.0.2.1:5000")
+        );
+        // Every hop trusted: the leftmost address is the client
+        let internal = x_forwarded_hops(&["10.1.1.1", "10.0.0.2"], &[]);
+        assert_eq!(
+            resolve(addr("10.0.0.1:5000"), &trusted(), &internal).addr,
+            addr("10.1.1.1")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let hops = forwarded_hops(&[
+            "for=192.0.2.43;proto=http, for=\"[2001:db8:cafe::17]:4711\";proto=https",
+            "for=unknown",
+        ]);
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[1].addr, Some(addr("[2001:db8:cafe::17]:4711")));
+        assert_eq!(hops[2].addr, None);
+
+        // The unknown hop stops the walk at the proxy that reported it
+        let resolved = resolve(addr("10.0.0.1:80"), &trusted(), &hops);
+        assert_eq!(resolved.addr, addr("10.0.0.1:80"));
+
+        let resolved = resolve(addr("10.0.0.1:80"), &trusted(), &hops[..2]);
+        assert_eq!(resolved.addr, addr("192.0.2.43"));
+        assert_eq!(resolved.proto.as_deref(), Some("http"));
+    }
+
+    #[test]
+    fn test_x_forwarded_proto_pairing() {
+        let paired = x_forwarded_hops(&["192.0.2.1, 10.0.0.5"], &["https, http"]);
+        assert_eq!(paired[0].proto.as_deref(), Some("https"));
+        let unpaired = x_forwarded_hops(&["192.0.2.1, 10.0.0.5"], &["https"]);
+        assert_eq!(unpaired[0].proto, None);
+        assert_eq!(unpaired[1].proto.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_parse_proxy_protocol() {
+        assert_eq!(
+            parse_v1(b"PROXY TCP4 192.0.2.1 198.51.100.1 4711 443\r\n").unwrap(),
+            Some(addr("192.0.2.1:4711"))
+        );
+        assert_eq!(
+            parse_v1(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\n").unwrap(),
+            Some(addr("[2001:db8::1]:4711"))
+        );
+        assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(parse_v1(b"PROXY TCP4 nonsense\r\n").is_err());
+
+        let mut v4 = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        v4.extend_from_slice(&4711u16.to_be_bytes());
+        v4.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(
+            parse_v2(0x21, 0x11, &v4).unwrap(),
+            Some(addr("192.0.2.1:4711"))
+        );
+        assert_eq!(parse_v2(0x20, 0x00, &[]).unwrap(), None);
+        assert!(parse_v2(0x21, 0x11, &v4[..6]).is_err());
+        assert!(parse_v2(0x11, 0x11, &v4).is_err());
+    }
+}
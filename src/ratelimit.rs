@@ -0,0 +1,418 @@
+use crate::config::{self, Config};
+use crate::{log, proxy, web};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Most client budgets tracked at once; the oldest is forgotten to make room
+const MAX_BUCKETS: usize = 100_000;
+
+/// How often buckets that have refilled are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Requests currently being handled
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Requests refused with 429 since launch
+static RATE_LIMITED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// Requests refused with 503 because of the concurrency cap since launch
+static LOAD_SHED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// Token buckets for every tracked client
+static BUCKETS: Mutex<Option<Buckets>> = Mutex::new(None);
+
+/// A budget's path prefix and the client key
+type BucketKey = (String, String);
+
+/// A client's remaining budget
+#[derive(Debug, Clone, Copy)]
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket will be full again, after which it can be forgotten
+    full_at: Instant,
+}
+
+impl Bucket {
+    /// A bucket holding a full burst
+    pub fn full(burst: u32, now: Instant) -> Self {
+        Bucket {
+            tokens: burst as f64,
+            updated: now,
+            full_at: now,
+        }
+    }
+
+    /// Take one token, or return how long until one is available
+    pub fn take(&mut self, rate: f64, burst: u32, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.updated = now;
+
+        if self.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - self.tokens) / rate));
+        }
+        self.tokens -= 1.0;
+        self.full_at = now + Duration::from_secs_f64((burst as f64 - self.tokens) / rate);
+        Ok(())
+    }
+}
+
+/// Token buckets keyed by budget and client, holding at most `cap` of them
+#[derive(Debug)]
+pub struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    /// Keys in the order they were first tracked, oldest first
+    order: VecDeque<BucketKey>,
+    cap: usize,
+}
+
+impl Buckets {
+    pub fn new(cap: usize) -> Self {
+        Buckets {
+            buckets: HashMap::new(),
+            order: VecDeque::new(),
+            cap: cap.max(1),
+        }
+    }
+
+    /// Take one token from a client's bucket, starting a full one for a new
+    /// client and forgetting the oldest when the cap is reached
+    pub fn take(&mut self, key: BucketKey, budget: &Budget, now: Instant) -> Result<(), Duration> {
+        if !self.buckets.contains_key(&key) {
+            while self.buckets.len() >= self.cap {
+                let Some(oldest) = self.order.pop_front() else {
+                    break;
+                };
+                self.buckets.remove(&oldest);
+            }
+            self.order.push_back(key.clone());
+        }
+        self.buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(budget.burst, now))
+            .take(budget.rate, budget.burst, now)
+    }
+
+    /// Drop buckets that are full again, which behave like new ones
+    pub fn prune(&mut self, now: Instant) {
+        self.buckets.retain(|_, bucket| bucket.full_at > now);
+        let buckets = &self.buckets;
+        self.order.retain(|key| buckets.contains_key(key));
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// The budget applying to a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    /// Prefix of the matching `[[security.rate_limit.paths]]` rule, empty for the global budget
+    pub prefix: String,
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Whether this request was admitted under the concurrency cap
+#[derive(Debug, Clone, Copy, Default)]
+struct Admission {
+    counted: bool,
+    shed: bool,
+}
+
+/// Seconds a refused client should wait, set by the guard for the fairing
+#[derive(Debug, Clone, Copy, Default)]
+struct RetryAfter(Option<u64>);
+
+/// Counts requests in flight and adds `Retry-After` to refused requests
+pub struct RateLimiter;
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limiter",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        let cap = config::get_config().security.rate_limit.max_concurrent;
+        let in_flight = IN_FLIGHT.fetch_add(1, Ordering::Relaxed) + 1;
+        request.local_cache(|| Admission {
+            counted: true,
+            shed: cap > 0 && in_flight > cap,
+        });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if request.local_cache(Admission::default).counted {
+            IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+        }
+        if let Some(seconds) = request.local_cache(RetryAfter::default).0 {
+            response.set_header(Header::new("Retry-After", seconds.to_string()));
+        }
+    }
+}
+
+/// Request guard enforcing the concurrency cap and per-client budgets on
+/// served files. Internal endpoints don't use it, so probes are never refused.
+pub struct RateLimit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        if request.local_cache(Admission::default).shed {
+            LOAD_SHED_TOTAL.fetch_add(1, Ordering::Relaxed);
+            request.local_cache(|| RetryAfter(Some(1)));
+            web::log_denied(request, Status::ServiceUnavailable, None);
+            return Outcome::Error((
+                Status::ServiceUnavailable,
+                "too many concurrent requests".to_string(),
+            ));
+        }
+
+        let config = config::get_config();
+        if !config.security.rate_limit.enabled {
+            return Outcome::Success(RateLimit);
+        }
+
+        let path = request.uri().path();
+        let budget = budget_for(&config, path.as_str());
+        let client = client_key(&config, request);
+        let wait = BUCKETS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Buckets::new(MAX_BUCKETS))
+            .take(
+                (budget.prefix.clone(), client.clone()),
+                &budget,
+                Instant::now(),
+            );
+
+        match wait {
+            Ok(()) => Outcome::Success(RateLimit),
+            Err(wait) => {
+                RATE_LIMITED_TOTAL.fetch_add(1, Ordering::Relaxed);
+                let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+                request.local_cache(|| RetryAfter(Some(seconds)));
+                log::log_barleywine(&format!(
+                    "Rate limited {} on {} (retry after {}s)",
+                    client, path, seconds
+                ));
+                web::log_denied(request, Status::TooManyRequests, None);
+                Outcome::Error((Status::TooManyRequests, "rate limit exceeded".to_string()))
+            }
+        }
+    }
+}
+
+/// The budget for the longest configured prefix covering a request path,
+/// once normalized, or the global budget
+pub fn budget_for(config: &Config, path: &str) -> Budget {
+    let path = config::normalize_path(path);
+    let rate_limit = &config.security.rate_limit;
+    rate_limit
+        .paths
+        .iter()
+        .filter(|rule| config::path_has_prefix(&path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.trim_end_matches('/').len())
+        .map(|rule| Budget {
+            prefix: rule.prefix.clone(),
+            rate: rule.rate,
+            burst: rule.burst,
+        })
+        .unwrap_or_else(|| Budget {
+            prefix: String::new(),
+            rate: rate_limit.rate,
+            burst: rate_limit.burst,
+        })
+}
+
+/// The key a client's budget is tracked under: the client IP, as resolved
+/// through trusted proxies. When a trusted proxy sent the request, the
+/// configured header is used instead if that proxy set it.
+fn client_key(config: &Config, request: &Request<'_>) -> String {
+    config
+        .security
+        .rate_limit
+        .key_header
+        .as_deref()
+        .filter(|_| proxy::from_trusted_proxy(request))
+        .and_then(|name| header_key(&request.headers().get(name).collect::<Vec<_>>()))
+        .or_else(|| request.remote().map(|addr| addr.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The last comma-separated value of a header, which the nearest proxy
+/// appended; earlier values came from further away and may be forged
+pub fn header_key(values: &[&str]) -> Option<String> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .next_back()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// Drop refilled buckets in the background so idle clients don't pile up
+pub fn spawn_pruner() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Some(buckets) = BUCKETS.lock().unwrap().as_mut() {
+                buckets.prune(Instant::now());
+            }
+        }
+    });
+}
+
+/// Requests currently being handled
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Requests refused with 429 since launch
+pub fn rate_limited_total() -> u64 {
+    RATE_LIMITED_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Requests refused with 503 by the concurrency cap since launch
+pub fn load_shed_total() -> u64 {
+    LOAD_SHED_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Client budgets currently tracked
+pub fn tracked_clients() -> usize {
+    BUCKETS
+        .lock()
+        .map(|buckets| buckets.as_ref().map_or(0, Buckets::len))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimitPathRule;
+    use rocket::local::blocking::Client;
+    use rocket::{get, routes};
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let start = Instant::now();
+        let mut bucket = Bucket::full(2, start);
+        assert!(bucket.take(1.0, 2, start).is_ok());
+        assert!(bucket.take(1.0, 2, start).is_ok());
+        let wait = bucket.take(1.0, 2, start).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+
+        let later = start + Duration::from_millis(500);
+        let wait = bucket.take(1.0, 2, later).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        // Refilling never exceeds the burst
+        let much_later = start + Duration::from_secs(60);
+        assert!(bucket.take(1.0, 2, much_later).is_ok());
+        assert!(bucket.take(1.0, 2, much_later).is_ok());
+        assert!(bucket.take(1.0, 2, much_later).is_err());
+    }
+
+    #[test]
+    fn test_buckets_are_capped_and_pruned() {
+        let budget = Budget {
+            prefix: String::new(),
+            rate: 1.0,
+            burst: 1,
+        };
+        let key = |client: &str| (String::new(), client.to_string());
+        let start = Instant::now();
+        let mut buckets = Buckets::new(2);
+        assert!(buckets.take(key("a"), &budget, start).is_ok());
+        assert!(buckets.take(key("b"), &budget, start).is_ok());
+        assert!(buckets.take(key("b"), &budget, start).is_err());
+
+        // A third client pushes out the oldest, not the one just refused
+        assert!(buckets.take(key("c"), &budget, start).is_ok());
+        assert_eq!(buckets.len(), 2);
+        assert!(buckets.take(key("b"), &budget, start).is_err());
+        assert!(buckets.take(key("a"), &budget, start).is_ok());
+        assert_eq!(buckets.len(), 2);
+
+        buckets.prune(start + Duration::from_secs(5));
+        assert!(buckets.is_empty());
+        assert!(buckets.order.is_empty());
+    }
+
+    #[test]
+    fn test_budget_for_prefers_longest_prefix() {
+        let mut config = Config::default();
+        config.security.rate_limit.paths = vec![
+            RateLimitPathRule {
+                prefix: "/downloads".to_string(),
+                rate: 1.0,
+                burst: 2,
+            },
+            RateLimitPathRule {
+                prefix: "/downloads/large/".to_string(),
+                rate: 0.1,
+                burst: 1,
+            },
+        ];
+
+        assert_eq!(budget_for(&config, "/downloads/a.zip").burst, 2);
+        assert_eq!(
+            budget_for(&config, "/downloads/large/iso.img").prefix,
+            "/downloads/large/"
+        );
+        let global = budget_for(&config, "/downloads-old/a.zip");
+        assert_eq!(global.prefix, "");
+        assert_eq!(global.burst, 20);
+
+        assert_eq!(budget_for(&config, "/%64ownloads/a.zip").burst, 2);
+        assert_eq!(budget_for(&config, "//downloads/a.zip").burst, 2);
+    }
+
+    #[test]
+    fn test_header_key_takes_last_value() {
+        assert_eq!(header_key(&["key-1"]), Some("key-1".to_string()));
+        assert_eq!(header_key(&["forged, key-1"]), Some("key-1".to_string()));
+        assert_eq!(header_key(&["forged", "key-1"]), Some("key-1".to_string()));
+        assert_eq!(header_key(&["key-1, "]), None);
+        assert_eq!(header_key(&[]), None);
+    }
+
+    #[get("/limited")]
+    fn limited(_limit: RateLimit) -> &'static str {
+        "ok"
+    }
+
+    #[test]
+    fn test_guard_allows_requests_when_disabled() {
+        let _ = config::init_config(None);
+        let rocket = rocket::build()
+            .attach(RateLimiter)
+            .mount("/", routes![limited]);
+        let client = Client::tracked(rocket).unwrap();
+        for _ in 0..30 {
+            let response = client.get("/limited").dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert!(response.headers().get_one("Retry-After").is_none());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{config, log, watch};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long to wait for a burst of file events to settle before reloading
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Reload the configuration on SIGHUP or when the configuration file changes
+pub fn spawn_config_reloader() {
+    let Some(path) = config::config_file() else {
+        log::log_barleywine("No configuration file in use, configuration reload disabled");
+        return;
+    };
+
+    let (changed_tx, mut changed_rx) = mpsc::unbounded_channel();
+    let watcher = match watch::watch_file(path, move || {
+        let _ = changed_tx.send(());
+    }) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            log::log_warning(&format!(
+                "Could not watch configuration file {}, reload only on SIGHUP: {}",
+                path.display(),
+                e
+            ));
+            None
+        }
+    };
+
+    log::log_barleywine(&format!(
+        "Configuration reload enabled for {} (SIGHUP or file change)",
+        path.display()
+    ));
+
+    tokio::spawn(async move {
+        // Keep the file watcher alive as long as the reload task runs
+        let _watcher = watcher;
+        let mut hangup = hangup_signal();
+
+        loop {
+            tokio::select! {
+                Some(()) = recv_hangup(&mut hangup) => {
+                    log::log_barleywine("Received SIGHUP, reloading configuration");
+                }
+                Some(()) = changed_rx.recv() => {
+                    // Editors often write a file in several steps
+                    tokio::time::sleep(DEBOUNCE).await;
+                    while changed_rx.try_recv().is_ok() {}
+                    log::log_barleywine("Configuration file changed, reloading configuration");
+                }
+                else => break,
+            }
+
+            let _ = config::reload_config();
+        }
+    });
+}
+
+#[cfg(unix)]
+type Hangup = Option<tokio::signal::unix::Signal>;
+
+#[cfg(not(unix))]
+type Hangup = Option<()>;
+
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            log::log_warning(&format!("Could not listen for SIGHUP: {}", e));
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {
+    None
+}
+
+#[cfg(unix)]
+async fn recv_hangup(hangup: &mut Hangup) -> Option<()> {
+    match hangup {
+        Some(signal) => signal.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn recv_hangup(_hangup: &mut Hangup) -> Option<()> {
+    std::future::pending().await
+}
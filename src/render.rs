@@ -0,0 +1,347 @@
+use crate::config::{Config, HtmlConfig};
+use crate::{log, math};
+use markdown::{CompileOptions, Constructs, Options, ParseOptions};
+use std::collections::{BTreeMap, HashSet};
+
+/// Tags dropped together with everything inside them, which can never be
+/// allowed
+pub const REMOVED_WITH_CONTENT: [&str; 2] = ["script", "style"];
+
+/// Attributes whose values are URLs checked against the allowed schemes
+const URL_ATTRIBUTES: [&str; 4] = ["href", "src", "cite", "action"];
+
+/// The sanitizer's safe tags, which include `<details>`, `<kbd>` and `<sup>`
+pub fn default_tags() -> Vec<String> {
+    let mut tags: Vec<String> = ammonia::Builder::default()
+        .clone_tags()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    tags.sort();
+    tags
+}
+
+/// The sanitizer's safe attributes, plus `class` on `<code>` for the language
+/// of fenced code blocks
+pub fn default_attributes() -> BTreeMap<String, Vec<String>> {
+    let builder = ammonia::Builder::default();
+    let mut attributes: BTreeMap<String, Vec<String>> = builder
+        .clone_tag_attributes()
+        .into_iter()
+        .map(|(tag, names)| {
+            (
+                tag.to_string(),
+                names.into_iter().map(String::from).collect(),
+            )
+        })
+        .collect();
+    attributes.insert(
+        "*".to_string(),
+        builder
+            .clone_generic_attributes()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    );
+    attributes
+        .entry("code".to_string())
+        .or_default()
+        .push("class".to_string());
+    for names in attributes.values_mut() {
+        names.sort();
+    }
+    attributes
+}
+
+/// Escape text for use in HTML content and attribute values
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// The text of the first `#` heading
+pub fn title(markdown: &str) -> Option<String> {
+    markdown.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("# ")
+            .map(|title| title.trim().to_string())
+    })
+}
+
+/// Convert a markdown page to HTML. Raw HTML is escaped unless
+/// `content.allow_html` is set, in which case it is kept and the result
+/// sanitized against `[content.html]`. With `content.math`, formulas become
+/// MathML, which is added after sanitizing so the allowlist needn't cover
+/// it. `page` names the page in logs.
+pub fn markdown_to_html(config: &Config, source: &str, page: &str) -> String {
+    let math = config.content.math;
+    let options = Options {
+        parse: ParseOptions {
+            constructs: Constructs {
+                math_flow: math,
+                math_text: math,
+                ..Constructs::default()
+            },
+            ..ParseOptions::default()
+        },
+        compile: CompileOptions {
+            allow_dangerous_html: config.content.allow_html,
+            ..CompileOptions::default()
+        },
+    };
+    // Only MDX constructs can fail, and those are never enabled
+    let html = markdown::to_html_with_options(source, &options)
+        .unwrap_or_else(|_| markdown::to_html(source));
+    let (html, formulas) = if math {
+        math::extract(&html, page)
+    } else {
+        (html, Vec::new())
+    };
+    let html = if config.content.allow_html {
+        sanitize(&html, &config.content.html, page)
+    } else {
+        html
+    };
+    math::restore(&html, &formulas)
+}
+
+/// Clean HTML against an allowlist, logging what was removed at debug level
+pub fn sanitize(html: &str, allowlist: &HtmlConfig, page: &str) -> String {
+    let allowlist = lowercase(allowlist);
+    if ::log::log_enabled!(::log::Level::Debug) {
+        let removed = stripped(html, &allowlist);
+        if !removed.is_empty() {
+            log::log_debug(&format!(
+                "Sanitized {}: removed {}",
+                page,
+                removed.join(", ")
+            ));
+        }
+    }
+
+    let tags: HashSet<&str> = allowlist.tags.iter().map(String::as_str).collect();
+    let mut generic = HashSet::new();
+    let mut tag_attributes = std::collections::HashMap::new();
+    for (tag, names) in &allowlist.attributes {
+        let names: HashSet<&str> = names.iter().map(String::as_str).collect();
+        if tag == "*" {
+            generic = names;
+        } else {
+            tag_attributes.insert(tag.as_str(), names);
+        }
+    }
+    let schemes: HashSet<&str> = allowlist.url_schemes.iter().map(String::as_str).collect();
+
+    ammonia::Builder::default()
+        .tags(tags)
+        .generic_attributes(generic)
+        .tag_attributes(tag_attributes)
+        .url_schemes(schemes)
+        .clean(html)
+        .to_string()
+}
+
+/// The allowlist with names lowercased, as the HTML parser reports them
+fn lowercase(allowlist: &HtmlConfig) -> HtmlConfig {
+    let lower = |names: &[String]| names.iter().map(|name| name.to_ascii_lowercase()).collect();
+    HtmlConfig {
+        tags: lower(&allowlist.tags),
+        attributes: allowlist
+            .attributes
+            .iter()
+            .map(|(tag, names)| (tag.to_ascii_lowercase(), lower(names)))
+            .collect(),
+        url_schemes: lower(&allowlist.url_schemes),
+    }
+}
+
+/// What the sanitizer will remove from `html`: tags, attributes and URLs
+/// outside the allowlist, described for the log
+fn stripped(html: &str, allowlist: &HtmlConfig) -> Vec<String> {
+    let mut removed: Vec<String> = Vec::new();
+    let mut note = |item: String| {
+        if !removed.contains(&item) {
+            removed.push(item);
+        }
+    };
+    let allowed = |tag: &str, attribute: &str| {
+        ["*", tag].iter().any(|key| {
+            allowlist
+                .attributes
+                .get(*key)
+                .is_some_and(|names| names.iter().any(|name| name == attribute))
+        })
+    };
+
+    let bytes = html.as_bytes();
+    let mut at = 0;
+    while let Some(offset) = html[at..].find('<') {
+        at += offset + 1;
+        if !bytes.get(at).is_some_and(u8::is_ascii_alphabetic) {
+            continue;
+        }
+        let name_end = html[at..]
+            .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+            .map_or(html.len(), |end| at + end);
+        let tag = html[at..name_end].to_ascii_lowercase();
+        at = name_end;
+        let tag_allowed = allowlist.tags.contains(&tag);
+        if !tag_allowed {
+            if REMOVED_WITH_CONTENT.contains(&tag.as_str()) {
+                note(format!("<{}> and its content", tag));
+            } else {
+                note(format!("<{}>", tag));
+            }
+        }
+
+        // Attributes up to the end of the tag
+        loop {
+            while bytes
+                .get(at)
+                .is_some_and(|b| b.is_ascii_whitespace() || *b == b'/')
+            {
+                at += 1;
+            }
+            if at >= bytes.len() || bytes[at] == b'>' {
+                break;
+            }
+            let name_end = html[at..]
+                .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '>' || c == '/')
+                .map_or(html.len(), |end| at + end);
+            let attribute = html[at..name_end].to_ascii_lowercase();
+            at = name_end;
+            while bytes.get(at).is_some_and(u8::is_ascii_whitespace) {
+                at += 1;
+            }
+            let mut value = "";
+            if bytes.get(at) == Some(&b'=') {
+                at += 1;
+                while bytes.get(at).is_some_and(u8::is_ascii_whitespace) {
+                    at += 1;
+                }
+                let (range, next) = match bytes.get(at) {
+                    Some(&quote) if quote == b'"' || quote == b'\'' => {
+                        let close = html[at + 1..]
+                            .find(quote as char)
+                            .map_or(html.len(), |end| at + 1 + end);
+                        (at + 1..close, close + 1)
+                    }
+                    _ => {
+                        let end = html[at..]
+                            .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                            .map_or(html.len(), |end| at + end);
+                        (at..end, end)
+                    }
+                };
+                value = &html[range];
+                at = next.min(html.len());
+            }
+            if !tag_allowed || attribute.is_empty() {
+                continue;
+            }
+            if !allowed(&tag, &attribute) {
+                note(format!("{} on <{}>", attribute, tag));
+            } else if URL_ATTRIBUTES.contains(&attribute.as_str())
+                && let Some((scheme, _)) = value.split_once(':')
+                && !scheme.contains(['/', '?', '#'])
+                && !allowlist
+                    .url_schemes
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(scheme.trim()))
+            {
+                note(format!(
+                    "{}: URL in {} on <{}>",
+                    scheme.trim().to_ascii_lowercase(),
+                    attribute,
+                    tag
+                ));
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"# Notes
+
+Press <kbd>Ctrl</kbd>+<kbd>C</kbd>, see note<sup>1</sup>.
+
+<details><summary>More</summary>Hidden <b onclick="steal()">text</b></details>
+
+<script>alert("hi")</script>
+
+<a href="javascript:alert(1)">bad</a> <a href="https://example.com/" rel="opener">good</a>
+"#;
+
+    #[test]
+    fn test_html_escaped_by_default() {
+        let config = Config::default();
+        let html = markdown_to_html(&config, PAGE, "notes.md");
+        assert!(html.contains("&lt;kbd&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn test_allow_html_sanitizes() {
+        let mut config = Config::default();
+        config.content.allow_html = true;
+        let html = markdown_to_html(&config, PAGE, "notes.md");
+
+        assert!(html.contains("<kbd>Ctrl</kbd>"));
+        assert!(html.contains("<sup>1</sup>"));
+        assert!(html.contains("<details><summary>More</summary>"));
+        assert!(html.contains("<b>text</b>"));
+        assert!(!html.contains("onclick"));
+        assert!(!html.contains("script"));
+        assert!(!html.contains("alert"));
+        assert!(html.contains(r#"<a href="https://example.com/" rel="noopener noreferrer">"#));
+
+        // A narrower allowlist drops the rest, keeping the text
+        config.content.html.tags.retain(|tag| tag != "kbd");
+        let html = markdown_to_html(&config, PAGE, "notes.md");
+        assert!(html.contains("Ctrl+C"));
+    }
+
+    #[test]
+    fn test_math() {
+        let page = "Inline $a^2 + b^2$ and\n\n$$\n\\frac{1}{2}\n$$\n\nBroken $\\nope$\n";
+        let mut config = Config::default();
+        let html = markdown_to_html(&config, page, "math.md");
+        assert!(html.contains("<msup><mi>a</mi><mn>2</mn></msup>"));
+        assert!(html.contains("display=\"block\"><semantics><mfrac><mn>1</mn><mn>2</mn></mfrac>"));
+        assert!(html.contains("<span class=\"math-error-marker\">⚠</span> \\nope</code>"));
+
+        // MathML is added after sanitizing, so the allowlist needn't cover it
+        config.content.allow_html = true;
+        assert_eq!(markdown_to_html(&config, page, "math.md"), html);
+
+        config.content.math = false;
+        let html = markdown_to_html(&config, page, "math.md");
+        assert!(html.contains("$a^2 + b^2$"));
+        assert!(!html.contains("<math"));
+    }
+
+    #[test]
+    fn test_stripped_report() {
+        let allowlist = lowercase(&HtmlConfig::default());
+        let html = r#"<p>Hi <B OnClick="x()">there</B> <blink>!</blink></p>
+<script type="text/javascript">alert(1)</script>
+<a href='javascript:go()' title=ok>x</a> <img src="https://example.com/a.png" alt="a">
+<code class="language-rust">fn</code>"#;
+        assert_eq!(
+            stripped(html, &allowlist),
+            vec![
+                "onclick on <b>",
+                "<blink>",
+                "<script> and its content",
+                "javascript: URL in href on <a>",
+            ]
+        );
+    }
+}
@@ -0,0 +1,726 @@
+use crate::config::{self, Config};
+use crate::render::escape_html;
+use crate::sitemap::{self, PageFile};
+use crate::web::{self, Access, FileResponse, JsonResponse};
+use crate::{frontmatter, headers, ipfilter, livereload, log, watch};
+use markdown::ParseOptions;
+use markdown::mdast::Node;
+use rocket::Either;
+use rocket::http::Accept;
+use rocket::http::uri::Origin;
+use rocket::response::content::RawHtml;
+use rocket::{Route, get, routes};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+
+/// How much a word counts in each part of a page
+const TITLE_WEIGHT: f32 = 5.0;
+const HEADING_WEIGHT: f32 = 3.0;
+const BODY_WEIGHT: f32 = 1.0;
+
+/// Share of the score a word gets when it only starts with the query's
+/// last word, as while typing
+const PREFIX_FACTOR: f32 = 0.5;
+
+/// Length of a result's snippet, and how much of it comes before the first
+/// match, in characters
+const SNIPPET_LENGTH: usize = 200;
+const SNIPPET_LEAD: usize = 60;
+
+/// How long to wait for a burst of file events to settle before updating
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// The index of the webroot's markdown pages, built on first use
+static INDEX: RwLock<Option<SearchIndex>> = RwLock::new(None);
+
+/// Held while the index is brought up to date, so updates don't interleave
+static UPDATING: Mutex<()> = Mutex::new(());
+
+/// Whether file changes keep the index up to date; if not, it is checked
+/// against the webroot on every query
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// An inverted index from words to the pages that contain them
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// Indexed pages by URL
+    pages: HashMap<String, IndexedPage>,
+    /// Words and the weight they carry in each page, by page URL
+    words: BTreeMap<String, HashMap<String, f32>>,
+    /// Size and modification time of every file seen, drafts included,
+    /// to tell which changed
+    files: HashMap<String, (u64, Option<SystemTime>)>,
+}
+
+#[derive(Debug)]
+struct IndexedPage {
+    title: String,
+    /// The page's text with whitespace collapsed, for snippets
+    text: String,
+    words: Vec<String>,
+}
+
+/// A page read for indexing
+#[derive(Debug)]
+struct ParsedPage {
+    title: String,
+    text: String,
+    weights: HashMap<String, f32>,
+}
+
+/// A page matching a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub url: String,
+    pub title: String,
+    pub score: f32,
+    /// Text around the first match
+    pub snippet: String,
+    /// Matched words in the snippet, as `[start, end)` character offsets
+    pub highlights: Vec<[usize; 2]>,
+}
+
+/// Results of a query, best first
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    /// Pages matching, of which at most `search.max_results` are listed
+    pub total: usize,
+    pub results: Vec<SearchHit>,
+}
+
+/// Split text into lowercase words, with their byte ranges in the text
+fn words(text: &str) -> Vec<(usize, usize, String)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(index),
+            (Some(from), false) => {
+                words.push((from, index, text[from..index].to_lowercase()));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// The text blocks of a markdown document, with the depth of the headings
+/// among them
+fn blocks(node: &Node, out: &mut Vec<(Option<u8>, String)>) {
+    let text = match node {
+        Node::Heading(heading) => {
+            out.push((Some(heading.depth), node.to_string()));
+            return;
+        }
+        Node::Paragraph(_) | Node::Code(_) | Node::Math(_) | Node::TableCell(_) => node.to_string(),
+        // Raw HTML is markup, not text
+        Node::Html(_) => return,
+        _ => {
+            for child in node.children().into_iter().flatten() {
+                blocks(child, out);
+            }
+            return;
+        }
+    };
+    out.push((None, text));
+}
+
+/// Read a markdown page for the index; `None` for drafts and pages that
+/// can't be read
+fn parse(config: &Config, file: &PageFile) -> Option<ParsedPage> {
+    let source = web::read_to_string(config, &file.path).ok()?;
+    let (front_matter, body) = frontmatter::split(&source);
+    if front_matter.draft {
+        return None;
+    }
+    let root = markdown::to_mdast(body, &ParseOptions::gfm()).ok()?;
+    let mut found = Vec::new();
+    blocks(&root, &mut found);
+
+    let title = front_matter
+        .title
+        .or_else(|| {
+            found
+                .iter()
+                .find(|(depth, _)| *depth == Some(1))
+                .map(|(_, text)| text.clone())
+        })
+        .unwrap_or_else(|| {
+            file.path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+
+    let mut weights: HashMap<String, f32> = HashMap::new();
+    let mut add = |text: &str, weight: f32| {
+        for (_, _, word) in words(text) {
+            *weights.entry(word).or_default() += weight;
+        }
+    };
+    add(&title, TITLE_WEIGHT);
+    let mut text = Vec::new();
+    for (depth, block) in &found {
+        add(
+            block,
+            if depth.is_some() {
+                HEADING_WEIGHT
+            } else {
+                BODY_WEIGHT
+            },
+        );
+        let block = block.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !block.is_empty() {
+            text.push(block);
+        }
+    }
+    Some(ParsedPage {
+        title,
+        text: text.join(" "),
+        weights,
+    })
+}
+
+impl SearchIndex {
+    fn remove(&mut self, url: &str) {
+        let Some(page) = self.pages.remove(url) else {
+            return;
+        };
+        for word in page.words {
+            if let Some(pages) = self.words.get_mut(&word) {
+                pages.remove(url);
+                if pages.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, url: &str, page: ParsedPage) {
+        self.remove(url);
+        let mut words = Vec::with_capacity(page.weights.len());
+        for (word, weight) in page.weights {
+            self.words
+                .entry(word.clone())
+                .or_default()
+                .insert(url.to_string(), weight);
+            words.push(word);
+        }
+        self.pages.insert(
+            url.to_string(),
+            IndexedPage {
+                title: page.title,
+                text: page.text,
+                words,
+            },
+        );
+    }
+
+    /// Number of pages in the index
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Pages containing every word of the query, best first. The last word
+    /// also matches longer words it starts. `visible` filters out pages the
+    /// current rules keep from the public.
+    pub fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        visible: impl Fn(&str) -> bool,
+    ) -> SearchResults {
+        let terms: Vec<String> = words(query).into_iter().map(|(_, _, word)| word).collect();
+        let mut scores: Option<HashMap<&str, f32>> = None;
+        for (position, term) in terms.iter().enumerate() {
+            let prefix = position + 1 == terms.len() && term.chars().count() > 1;
+            let mut term_scores: HashMap<&str, f32> = HashMap::new();
+            for (word, pages) in self.words.range(term.clone()..) {
+                let factor = if word == term {
+                    1.0
+                } else if prefix && word.starts_with(term.as_str()) {
+                    PREFIX_FACTOR
+                } else {
+                    break;
+                };
+                // Rare words say more about a page than common ones
+                let rarity = (1.0 + self.pages.len() as f32 / pages.len() as f32).ln();
+                for (url, weight) in pages {
+                    let score = factor * (1.0 + weight.ln()) * rarity;
+                    let best = term_scores.entry(url.as_str()).or_default();
+                    *best = best.max(score);
+                }
+            }
+            scores = Some(match scores {
+                None => term_scores,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(url, score)| term_scores.get(url).map(|more| (url, score + more)))
+                    .collect(),
+            });
+        }
+
+        let mut ranked: Vec<(&str, f32)> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(url, _)| visible(url))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        let total = ranked.len();
+        let results = ranked
+            .into_iter()
+            .take(limit)
+            .filter_map(|(url, score)| {
+                let page = self.pages.get(url)?;
+                let (snippet, highlights) = snippet(&page.text, &terms);
+                Some(SearchHit {
+                    url: url.to_string(),
+                    title: page.title.clone(),
+                    score,
+                    snippet,
+                    highlights,
+                })
+            })
+            .collect();
+        SearchResults {
+            query: query.to_string(),
+            total,
+            results,
+        }
+    }
+}
+
+/// The part of a page's text around the first word matching the query,
+/// with the character ranges of the matching words in it
+fn snippet(text: &str, terms: &[String]) -> (String, Vec<[usize; 2]>) {
+    let matches = |word: &str| {
+        terms.iter().enumerate().any(|(position, term)| {
+            word == term
+                || (position + 1 == terms.len()
+                    && term.chars().count() > 1
+                    && word.starts_with(term.as_str()))
+        })
+    };
+    let found: Vec<(usize, usize)> = words(text)
+        .into_iter()
+        .filter(|(_, _, word)| matches(word))
+        .map(|(start, end, _)| (start, end))
+        .collect();
+
+    // Start a little before the first match, at a word boundary
+    let mut start = 0;
+    if let Some(&(first, _)) = found.first()
+        && let Some((lead, _)) = text[..first].char_indices().rev().nth(SNIPPET_LEAD)
+    {
+        start = text[lead..first]
+            .find(' ')
+            .map_or(first, |space| lead + space + 1);
+    }
+    let mut end = text.len();
+    if let Some((length, _)) = text[start..].char_indices().nth(SNIPPET_LENGTH) {
+        end = text[start..start + length]
+            .rfind(' ')
+            .map_or(start + length, |space| start + space);
+    }
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let offset = snippet.chars().count();
+    snippet.push_str(&text[start..end]);
+    if end < text.len() {
+        snippet.push('…');
+    }
+    let highlights = found
+        .into_iter()
+        .filter(|&(from, to)| from >= start && to <= end)
+        .map(|(from, to)| {
+            let from_chars = offset + text[start..from].chars().count();
+            [from_chars, from_chars + text[from..to].chars().count()]
+        })
+        .collect();
+    (snippet, highlights)
+}
+
+/// A snippet as HTML, with its highlights marked
+fn highlighted(snippet: &str, highlights: &[[usize; 2]]) -> String {
+    let chars: Vec<char> = snippet.chars().collect();
+    let mut html = String::new();
+    let mut at = 0;
+    for &[from, to] in highlights {
+        let (from, to) = (from.min(chars.len()), to.min(chars.len()));
+        if from < at {
+            continue;
+        }
+        html.push_str(&escape_html(&chars[at..from].iter().collect::<String>()));
+        html.push_str("<mark>");
+        html.push_str(&escape_html(&chars[from..to].iter().collect::<String>()));
+        html.push_str("</mark>");
+        at = to;
+    }
+    html.push_str(&escape_html(&chars[at..].iter().collect::<String>()));
+    html
+}
+
+/// Whether a page URL is open to anyone under the current rules, and the IP
+/// rules let the searching client fetch it
+fn is_visible(config: &Config, url: &str, client: Option<IpAddr>) -> bool {
+    let path = config.content.webroot.join(url.trim_start_matches('/'));
+    !sitemap::is_protected(config, url)
+        && web::blocked_component(config, &path).is_none()
+        && ipfilter::is_allowed(&config.security.ip_rules, url, client)
+}
+
+/// Bring the index up to date with the webroot: pages that were added or
+/// changed are read again and pages that are gone are dropped
+pub fn update(config: &Config) {
+    let _updating = UPDATING.lock();
+    let files: Vec<PageFile> = sitemap::page_files(config)
+        .into_iter()
+        .filter(|file| {
+            file.path
+                .extension()
+                .is_some_and(|extension| extension == "md")
+        })
+        .collect();
+
+    let (changed, gone): (Vec<&PageFile>, Vec<String>) = {
+        let index = INDEX.read().unwrap_or_else(|e| e.into_inner());
+        let known = index.as_ref().map(|index| &index.files);
+        let changed: Vec<&PageFile> = files
+            .iter()
+            .filter(|file| {
+                known.and_then(|known| known.get(&file.url)) != Some(&(file.len, file.modified))
+            })
+            .collect();
+        let current: HashSet<&str> = files.iter().map(|file| file.url.as_str()).collect();
+        let gone: Vec<String> = known
+            .map(|known| {
+                known
+                    .keys()
+                    .filter(|url| !current.contains(url.as_str()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        if index.is_some() && changed.is_empty() && gone.is_empty() {
+            return;
+        }
+        (changed, gone)
+    };
+
+    // Pages are read without holding up queries
+    let parsed: Vec<(&PageFile, Option<ParsedPage>)> = changed
+        .into_iter()
+        .map(|file| (file, parse(config, file)))
+        .collect();
+
+    let mut index = INDEX.write().unwrap_or_else(|e| e.into_inner());
+    let index = index.get_or_insert_with(SearchIndex::default);
+    for url in &gone {
+        index.remove(url);
+        index.files.remove(url);
+    }
+    let updated = parsed.len();
+    for (file, page) in parsed {
+        index
+            .files
+            .insert(file.url.clone(), (file.len, file.modified));
+        match page {
+            Some(page) => index.insert(&file.url, page),
+            None => index.remove(&file.url),
+        }
+    }
+    log::log_debug(&format!(
+        "Search index updated: {} pages read, {} removed, {} indexed",
+        updated,
+        gone.len(),
+        index.len()
+    ));
+}
+
+/// Search the markdown pages of the webroot that `client` may see
+pub fn search(config: &Config, query: &str, client: Option<IpAddr>) -> SearchResults {
+    let built = INDEX.read().is_ok_and(|index| index.is_some());
+    if !built || !WATCHING.load(Ordering::Relaxed) {
+        update(config);
+    }
+    let index = INDEX.read().unwrap_or_else(|e| e.into_inner());
+    match index.as_ref() {
+        Some(index) => index.search(query, config.search.max_results, |url| {
+            is_visible(config, url, client)
+        }),
+        None => SearchResults {
+            query: query.to_string(),
+            total: 0,
+            results: Vec::new(),
+        },
+    }
+}
+
+/// Build the index in the background and keep it up to date as files in
+/// the webroot change. Does nothing unless search is enabled.
+pub fn spawn_indexer() {
+    let config = config::get_config();
+    if !config.search.enabled {
+        return;
+    }
+    let changes = match watch::watch_webroot(&config.content.webroot) {
+        Ok(()) => watch::subscribe(),
+        Err(e) => {
+            log::log_warning(&format!(
+                "Could not watch webroot, search will check for changes on every query: {}",
+                e
+            ));
+            None
+        }
+    };
+    WATCHING.store(changes.is_some(), Ordering::Relaxed);
+
+    tokio::spawn(async move {
+        let _ = tokio::task::spawn_blocking(|| update(&config::get_config())).await;
+        let Some(mut changes) = changes else {
+            return;
+        };
+        loop {
+            match changes.recv().await {
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+            // Saves and checkouts touch many files at once
+            tokio::time::sleep(DEBOUNCE).await;
+            while changes.try_recv().is_ok() {}
+            let _ = tokio::task::spawn_blocking(|| update(&config::get_config())).await;
+        }
+    });
+}
+
+/// The results page: a search form and the results of the query, if any
+fn results_page(config: &Config, query: Option<&str>, client: Option<IpAddr>) -> String {
+    let mut html = format!(
+        "<h1>Search</h1>\n<form class=\"search-form\" role=\"search\" method=\"get\" action=\"{}\">\n\
+         <input type=\"search\" name=\"q\" value=\"{}\" aria-label=\"Search\" autofocus>\n\
+         <button type=\"submit\">Search</button>\n</form>\n",
+        escape_html(&config.search.path),
+        escape_html(query.unwrap_or_default())
+    );
+    let Some(query) = query.filter(|query| !query.trim().is_empty()) else {
+        return html;
+    };
+
+    let results = search(config, query, client);
+    html.push_str(&format!(
+        "<p class=\"search-summary\">{} {} for “{}”</p>\n",
+        results.total,
+        if results.total == 1 {
+            "result"
+        } else {
+            "results"
+        },
+        escape_html(query)
+    ));
+    if results.results.is_empty() {
+        return html;
+    }
+    html.push_str("<ol class=\"search-results\">\n");
+    for hit in &results.results {
+        html.push_str(&format!(
+            "<li>\n<a href=\"{}\">{}</a>\n<p>{}</p>\n</li>\n",
+            escape_html(&hit.url),
+            escape_html(&hit.title),
+            highlighted(&hit.snippet, &hit.highlights)
+        ));
+    }
+    html.push_str("</ol>\n");
+    html
+}
+
+/// Search as JSON, or as a results page for browsers
+#[get("/?<q>")]
+fn search_route(
+    q: Option<&str>,
+    accept: Option<&Accept>,
+    remote_addr: Option<SocketAddr>,
+    origin: &Origin<'_>,
+    access: Access,
+) -> Either<JsonResponse<SearchResults>, FileResponse> {
+    let config = config::get_config();
+    let addr = remote_addr
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    log::log_access(
+        &addr,
+        "GET",
+        origin.path().as_str(),
+        200,
+        None,
+        access.user.as_deref(),
+    );
+
+    let client = remote_addr.map(|addr| addr.ip());
+    let wants_html = accept.is_some_and(|accept| accept.preferred().is_html());
+    if !wants_html {
+        return Either::Left(JsonResponse(search(&config, q.unwrap_or_default(), client)));
+    }
+
+    let nonce = headers::page_nonce(&config);
+    let title = match q.filter(|query| !query.trim().is_empty()) {
+        Some(query) => format!("Search: {}", query),
+        None => "Search".to_string(),
+    };
+    let mut html =
+        web::create_html_template(&results_page(&config, q, client), &title, nonce.as_deref());
+    if livereload::is_enabled(&config) {
+        html = livereload::inject_script(&html, nonce.as_deref());
+    }
+    Either::Right(FileResponse::Markdown(RawHtml(html), nonce))
+}
+
+/// Routes to mount at `search.path`
+pub fn routes() -> Vec<Route> {
+    routes![search_route]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{IpAction, IpRule};
+    use std::fs;
+
+    fn index(name: &str) -> SearchIndex {
+        let mut config = Config::default();
+        let root =
+            std::env::temp_dir().join(format!("barleywine_search_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        config.content.webroot = root.clone();
+
+        let pages = [
+            (
+                "rust.md",
+                "---\ntitle: Learning Rust\n---\nRust is a systems language. Ownership keeps memory safe.\n",
+            ),
+            (
+                "web.md",
+                "# Web servers\n\n## Routing in Rust\n\nRequests are routed to handlers.\n",
+            ),
+            (
+                "notes.md",
+                "# Notes\n\n- We mention rust once\n- and memory too\n",
+            ),
+            ("draft.md", "---\ndraft: true\n---\nRust draft\n"),
+        ];
+        let mut index = SearchIndex::default();
+        for (name, source) in pages {
+            let path = root.join(name);
+            fs::write(&path, source).unwrap();
+            let file = PageFile {
+                path,
+                url: format!("/{}", name),
+                len: 0,
+                modified: None,
+            };
+            if let Some(page) = parse(&config, &file) {
+                index.insert(&file.url, page);
+            }
+        }
+        fs::remove_dir_all(&root).ok();
+        index
+    }
+
+    #[test]
+    fn test_words() {
+        let found: Vec<String> = words("Héllo, wörld! x86_64")
+            .into_iter()
+            .map(|(_, _, word)| word)
+            .collect();
+        assert_eq!(found, vec!["héllo", "wörld", "x86", "64"]);
+    }
+
+    #[test]
+    fn test_titles_and_headings_rank_first() {
+        let index = index("rank");
+        let results = index.search("rust", 10, |_| true);
+        let urls: Vec<&str> = results.results.iter().map(|hit| hit.url.as_str()).collect();
+        assert_eq!(urls, vec!["/rust.md", "/web.md", "/notes.md"]);
+        assert_eq!(results.total, 3);
+
+        // Every word must match, and the last may be the start of one
+        let results = index.search("rust mem", 10, |_| true);
+        let urls: Vec<&str> = results.results.iter().map(|hit| hit.url.as_str()).collect();
+        assert_eq!(urls, vec!["/rust.md", "/notes.md"]);
+
+        // Hidden pages are left out
+        let results = index.search("rust", 10, |url| url != "/rust.md");
+        assert_eq!(results.total, 2);
+        assert!(index.search("nothing", 10, |_| true).results.is_empty());
+    }
+
+    #[test]
+    fn test_ip_rules_hide_pages() {
+        let index = index("ip_rules");
+        let mut config = Config::default();
+        config.security.ip_rules = vec![
+            IpRule {
+                prefix: "/rust.md".to_string(),
+                action: IpAction::Allow,
+                cidrs: vec!["10.0.0.0/8".to_string()],
+            },
+            IpRule {
+                prefix: "/rust.md".to_string(),
+                action: IpAction::Deny,
+                cidrs: vec!["all".to_string()],
+            },
+        ];
+        let search_from = |ip: &str| {
+            let client = Some(ip.parse().unwrap());
+            index
+                .search("ownership", 10, |url| is_visible(&config, url, client))
+                .results
+        };
+
+        assert!(search_from("203.0.113.5").is_empty());
+        let hits = search_from("10.1.2.3");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].url, "/rust.md");
+    }
+
+    #[test]
+    fn test_remove_page() {
+        let mut index = index("remove");
+        index.remove("/web.md");
+        assert!(index.search("handlers", 10, |_| true).results.is_empty());
+        assert!(!index.words.contains_key("handlers"));
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn test_snippet_highlights() {
+        let text = format!(
+            "{} The word Ownership matters. {}",
+            "filler ".repeat(20),
+            "tail ".repeat(60)
+        );
+        let (snippet, highlights) = snippet(&text, &["ownership".to_string()]);
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        let chars: Vec<char> = snippet.chars().collect();
+        let [from, to] = highlights[0];
+        assert_eq!(chars[from..to].iter().collect::<String>(), "Ownership");
+        assert_eq!(
+            highlighted("a <b> c", &[[2, 5]]),
+            "a <mark>&lt;b&gt;</mark> c"
+        );
+    }
+}
@@ -0,0 +1,671 @@
+use crate::auth::{self, Challenge};
+use crate::config::{self, AuthRule, Config};
+use crate::headers;
+use crate::ipfilter::IpAccess;
+use crate::log;
+use crate::proxy::Https;
+use crate::ratelimit::RateLimit;
+use crate::render::escape_html;
+use crate::web::{self, FileResponse};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::form::{Form, FromForm};
+use rocket::http::{Cookie, CookieJar, RawStr, SameSite, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::Redirect;
+use rocket::response::content::RawHtml;
+use rocket::time::Duration;
+use rocket::{Either, Request, Route, get, post, routes};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+
+/// Cookie holding the token the login form must echo back
+const CSRF_COOKIE: &str = "barleywine_csrf";
+
+/// Key the session cookies are signed with
+static SESSION_KEY: OnceLock<hmac::Key> = OnceLock::new();
+
+/// Sessions signed out before they expired, keyed by their CSRF token, which
+/// is unique to each sign in, with the time they would have expired
+static REVOKED: Mutex<Option<HashMap<String, i64>>> = Mutex::new(None);
+
+/// A signed-in user, as carried in the session cookie
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub user: String,
+    /// Unix time the session ends
+    pub expires: i64,
+    /// Token forms must echo back while signed in (logout)
+    pub csrf: String,
+}
+
+impl Session {
+    /// The cookie value: the session fields followed by their signature
+    pub fn sign(&self, key: &hmac::Key) -> String {
+        let payload = format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(&self.user),
+            self.expires,
+            self.csrf
+        );
+        let tag = hmac::sign(key, payload.as_bytes());
+        format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    /// Read a cookie value, rejecting bad signatures and expired sessions
+    pub fn verify(value: &str, key: &hmac::Key, now: i64) -> Option<Session> {
+        let (payload, tag) = value.rsplit_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        hmac::verify(key, payload.as_bytes(), &tag).ok()?;
+
+        let mut fields = payload.split('.');
+        let user = String::from_utf8(URL_SAFE_NO_PAD.decode(fields.next()?).ok()?).ok()?;
+        let expires = fields.next()?.parse().ok()?;
+        let csrf = fields.next()?.to_string();
+        if fields.next().is_some() || expires <= now {
+            return None;
+        }
+        Some(Session {
+            user,
+            expires,
+            csrf,
+        })
+    }
+}
+
+/// The key session cookies are signed with, derived from Rocket's
+/// `secret_key`. Without one a random key is used, so logins end on restart.
+fn session_key() -> &'static hmac::Key {
+    SESSION_KEY.get_or_init(|| {
+        let secret = rocket::Config::figment()
+            .extract_inner::<String>("secret_key")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let secret = match secret {
+            Some(secret) => secret.into_bytes(),
+            None => {
+                log::log_warning(
+                    "No secret_key in Rocket.toml or ROCKET_SECRET_KEY; login sessions will not survive a restart",
+                );
+                random_token().into_bytes()
+            }
+        };
+        let mut material = b"barleywine session\0".to_vec();
+        material.extend_from_slice(&secret);
+        let digest = ring::digest::digest(&ring::digest::SHA256, &material);
+        hmac::Key::new(hmac::HMAC_SHA256, digest.as_ref())
+    })
+}
+
+/// 32 random bytes, URL-safe encoded
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random number generator failed");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Compare two tokens without leaking where they differ
+fn tokens_match(a: &str, b: &str) -> bool {
+    let key = session_key();
+    hmac::verify(key, a.as_bytes(), hmac::sign(key, b.as_bytes()).as_ref()).is_ok()
+}
+
+/// The request's valid session, if any
+pub fn current(request: &Request<'_>, config: &Config) -> Option<Session> {
+    let cookie = request.cookies().get(&config.auth.session.cookie_name)?;
+    Session::verify(cookie.value(), session_key(), Utc::now().timestamp())
+        .filter(|session| !is_revoked(session))
+}
+
+/// End a session on the server, so copies of its cookie stop working too
+fn revoke(session: &Session, now: i64) {
+    if let Ok(mut revoked) = REVOKED.lock() {
+        let revoked = revoked.get_or_insert_with(HashMap::new);
+        // Expired sessions are refused anyway
+        revoked.retain(|_, expires| *expires > now);
+        revoked.insert(session.csrf.clone(), session.expires);
+    }
+}
+
+/// Whether a session was signed out
+fn is_revoked(session: &Session) -> bool {
+    match REVOKED.lock() {
+        Ok(revoked) => revoked
+            .as_ref()
+            .is_some_and(|revoked| revoked.contains_key(&session.csrf)),
+        // Refuse sessions rather than risk accepting a revoked one
+        Err(_) => true,
+    }
+}
+
+/// The signed-in user for a path covered by `[[auth.rules]]`. Visitors
+/// without a session are sent to the login page and users without an
+/// allowed role get 403. Unprotected paths always succeed with `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionUser(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionUser {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        let path = request.uri().path();
+        let Some(rule) = find_rule(&config.auth.rules, path.as_str()) else {
+            return Outcome::Success(SessionUser(None));
+        };
+
+        let users = config
+            .auth
+            .session
+            .users
+            .as_deref()
+            .map(auth::load_users)
+            .transpose()
+            .unwrap_or_else(|e| {
+                log::log_error(&format!("Could not read user file {}", e));
+                None
+            });
+        let session = current(request, &config);
+        // Users removed from the user file lose their sessions immediately
+        let roles = session
+            .as_ref()
+            .zip(users.as_ref())
+            .and_then(|(session, users)| users.roles(&session.user).map(<[String]>::to_vec));
+
+        match (session, roles) {
+            (Some(session), Some(roles)) if has_access(rule, &roles) => {
+                Outcome::Success(SessionUser(Some(session.user)))
+            }
+            (Some(session), Some(_)) => {
+                log::log_warning(&format!(
+                    "User '{}' has no role allowed for {}",
+                    session.user, path
+                ));
+                web::log_denied(request, Status::Forbidden, Some(&session.user));
+                Outcome::Error((
+                    Status::Forbidden,
+                    format!("user '{}' has no allowed role", session.user),
+                ))
+            }
+            _ => {
+                let location = login_location(&config, &request.uri().to_string());
+                request.local_cache(|| Challenge::Login(location));
+                web::log_denied(request, Status::SeeOther, None);
+                Outcome::Error((Status::Unauthorized, "not signed in".to_string()))
+            }
+        }
+    }
+}
+
+/// The rule for the longest configured prefix covering a request path, which
+/// is normalized first so encoded spellings of a prefix are covered too
+pub fn find_rule<'a>(rules: &'a [AuthRule], path: &str) -> Option<&'a AuthRule> {
+    let path = config::normalize_path(path);
+    rules
+        .iter()
+        .filter(|rule| config::path_has_prefix(&path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.trim_end_matches('/').len())
+}
+
+/// Whether a user with these roles may access the rule's prefix
+pub fn has_access(rule: &AuthRule, roles: &[String]) -> bool {
+    rule.roles.is_empty() || roles.iter().any(|role| rule.roles.contains(role))
+}
+
+/// The login page, returning to `next` after signing in
+fn login_location(config: &Config, next: &str) -> String {
+    format!(
+        "{}/login?next={}",
+        config.internal.prefix,
+        RawStr::new(next).percent_encode()
+    )
+}
+
+/// Only return to paths on this site after signing in
+fn safe_next(next: Option<&str>) -> &str {
+    match next {
+        Some(next) if next.starts_with('/') && !next.starts_with("//") && !next.contains('\\') => {
+            next
+        }
+        _ => "/",
+    }
+}
+
+/// A cookie only sent over TLS when the visitor is using it
+fn cookie<'c>(secure: bool, name: String, value: String) -> Cookie<'c> {
+    Cookie::build((name, value))
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(secure)
+        .path("/")
+        .build()
+}
+
+/// Render a page with the markdown page template
+fn page(config: &Config, title: &str, body: String) -> FileResponse {
+    let nonce = headers::page_nonce(config);
+    let html = web::create_html_template(&body, title, nonce.as_deref());
+    FileResponse::Html(RawHtml(html), nonce)
+}
+
+fn login_page(config: &Config, csrf: &str, next: &str, error: Option<&str>) -> FileResponse {
+    let error = error
+        .map(|error| format!("<p><strong>{}</strong></p>\n", escape_html(error)))
+        .unwrap_or_default();
+    let body = format!(
+        r#"<h1>Sign in</h1>
+{error}<form method="post" action="{prefix}/login">
+    <input type="hidden" name="csrf" value="{csrf}">
+    <input type="hidden" name="next" value="{next}">
+    <p><label for="username">User name</label><br>
+    <input id="username" name="username" autocomplete="username" required autofocus></p>
+    <p><label for="password">Password</label><br>
+    <input id="password" name="password" type="password" autocomplete="current-password" required></p>
+    <p><button type="submit">Sign in</button></p>
+</form>"#,
+        prefix = config.internal.prefix,
+        csrf = escape_html(csrf),
+        next = escape_html(next),
+    );
+    page(config, "Sign in", body)
+}
+
+/// The form's CSRF token, issuing a new one when the browser has none
+fn csrf_token(https: &Https, cookies: &CookieJar<'_>) -> String {
+    if let Some(cookie) = cookies.get(CSRF_COOKIE) {
+        return cookie.value().to_string();
+    }
+    let token = random_token();
+    cookies.add(cookie(https.0, CSRF_COOKIE.to_string(), token.clone()));
+    token
+}
+
+fn log_request(
+    remote_addr: Option<SocketAddr>,
+    method: &str,
+    path: &str,
+    status: Status,
+    user: Option<&str>,
+) {
+    let addr_str = remote_addr
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    log::log_access(&addr_str, method, path, status.code, None, user);
+}
+
+#[derive(FromForm)]
+struct LoginForm<'r> {
+    username: &'r str,
+    password: &'r str,
+    csrf: &'r str,
+    next: Option<&'r str>,
+}
+
+#[derive(FromForm)]
+struct LogoutForm<'r> {
+    csrf: &'r str,
+}
+
+#[get("/login?<next>")]
+fn login_form(
+    next: Option<&str>,
+    cookies: &CookieJar<'_>,
+    remote_addr: Option<SocketAddr>,
+    request_path: LoginPath,
+    https: Https,
+    _ip: IpAccess,
+) -> (Status, FileResponse) {
+    let config = config::get_config();
+    if config.auth.session.users.is_none() {
+        log_request(remote_addr, "GET", &request_path.0, Status::NotFound, None);
+        return (
+            Status::NotFound,
+            page(
+                &config,
+                "Sign in",
+                "<p>Signing in is not enabled.</p>".to_string(),
+            ),
+        );
+    }
+
+    let csrf = csrf_token(&https, cookies);
+    log_request(remote_addr, "GET", &request_path.0, Status::Ok, None);
+    (
+        Status::Ok,
+        login_page(&config, &csrf, safe_next(next), None),
+    )
+}
+
+#[post("/login", data = "<form>")]
+async fn login(
+    form: Form<LoginForm<'_>>,
+    cookies: &CookieJar<'_>,
+    remote_addr: Option<SocketAddr>,
+    request_path: LoginPath,
+    https: Https,
+    _ip: IpAccess,
+    _limit: RateLimit,
+) -> Result<Redirect, (Status, FileResponse)> {
+    let config = config::get_config();
+    let next = safe_next(form.next);
+    let addr_str = remote_addr
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let expected = cookies
+        .get(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    if !expected.is_some_and(|expected| tokens_match(&expected, form.csrf)) {
+        log::log_warning(&format!(
+            "Sign in refused from {}: missing or mismatched CSRF token",
+            addr_str
+        ));
+        log_request(
+            remote_addr,
+            "POST",
+            &request_path.0,
+            Status::Forbidden,
+            None,
+        );
+        cookies.remove(Cookie::build(CSRF_COOKIE).path("/"));
+        let csrf = csrf_token(&https, cookies);
+        return Err((
+            Status::Forbidden,
+            login_page(
+                &config,
+                &csrf,
+                next,
+                Some("The form expired. Please sign in again."),
+            ),
+        ));
+    }
+
+    let users = match config.auth.session.users.as_deref().map(auth::load_users) {
+        Some(Ok(users)) => users,
+        Some(Err(e)) => {
+            log::log_error(&format!("Could not read user file {}", e));
+            log_request(
+                remote_addr,
+                "POST",
+                &request_path.0,
+                Status::ServiceUnavailable,
+                None,
+            );
+            return Err((
+                Status::ServiceUnavailable,
+                page(
+                    &config,
+                    "Sign in",
+                    "<p>Signing in is unavailable.</p>".to_string(),
+                ),
+            ));
+        }
+        None => {
+            log_request(remote_addr, "POST", &request_path.0, Status::NotFound, None);
+            return Err((
+                Status::NotFound,
+                page(
+                    &config,
+                    "Sign in",
+                    "<p>Signing in is not enabled.</p>".to_string(),
+                ),
+            ));
+        }
+    };
+
+    let username = form.username.to_string();
+    if !users
+        .verify_blocking(username.clone(), form.password.to_string())
+        .await
+    {
+        log::log_warning(&format!(
+            "Sign in failed for user '{}' from {}",
+            username, addr_str
+        ));
+        log_request(
+            remote_addr,
+            "POST",
+            &request_path.0,
+            Status::Unauthorized,
+            None,
+        );
+        return Err((
+            Status::Unauthorized,
+            login_page(
+                &config,
+                form.csrf,
+                next,
+                Some("Invalid user name or password."),
+            ),
+        ));
+    }
+
+    let ttl = config.auth.session.ttl;
+    let session = Session {
+        user: username.clone(),
+        expires: Utc::now().timestamp() + ttl as i64,
+        csrf: random_token(),
+    };
+    let mut session_cookie = cookie(
+        https.0,
+        config.auth.session.cookie_name.clone(),
+        session.sign(session_key()),
+    );
+    session_cookie.set_max_age(Duration::seconds(ttl as i64));
+    cookies.add(session_cookie);
+
+    log::log_barleywine(&format!("User '{}' signed in from {}", username, addr_str));
+    log_request(
+        remote_addr,
+        "POST",
+        &request_path.0,
+        Status::SeeOther,
+        Some(&username),
+    );
+    Ok(Redirect::to(next.to_string()))
+}
+
+#[get("/logout")]
+fn logout_form(
+    request_path: LoginPath,
+    remote_addr: Option<SocketAddr>,
+    session: CurrentSession,
+) -> Either<FileResponse, Redirect> {
+    let config = config::get_config();
+    let Some(session) = session.0 else {
+        return Either::Right(Redirect::to("/"));
+    };
+
+    log_request(
+        remote_addr,
+        "GET",
+        &request_path.0,
+        Status::Ok,
+        Some(&session.user),
+    );
+    let body = format!(
+        r#"<h1>Sign out</h1>
+<p>Signed in as {user}.</p>
+<form method="post" action="{prefix}/logout">
+    <input type="hidden" name="csrf" value="{csrf}">
+    <p><button type="submit">Sign out</button></p>
+</form>"#,
+        user = escape_html(&session.user),
+        prefix = config.internal.prefix,
+        csrf = escape_html(&session.csrf),
+    );
+    Either::Left(page(&config, "Sign out", body))
+}
+
+#[post("/logout", data = "<form>")]
+fn logout(
+    form: Form<LogoutForm<'_>>,
+    cookies: &CookieJar<'_>,
+    request_path: LoginPath,
+    remote_addr: Option<SocketAddr>,
+    session: CurrentSession,
+) -> Result<Redirect, Status> {
+    let config = config::get_config();
+    let Some(session) = session.0 else {
+        return Ok(Redirect::to("/"));
+    };
+    if !tokens_match(&session.csrf, form.csrf) {
+        log::log_warning(&format!(
+            "Sign out refused for user '{}': mismatched CSRF token",
+            session.user
+        ));
+        log_request(
+            remote_addr,
+            "POST",
+            &request_path.0,
+            Status::Forbidden,
+            Some(&session.user),
+        );
+        return Err(Status::Forbidden);
+    }
+
+    revoke(&session, Utc::now().timestamp());
+    cookies.remove(Cookie::build(config.auth.session.cookie_name.clone()).path("/"));
+    log::log_barleywine(&format!("User '{}' signed out", session.user));
+    log_request(
+        remote_addr,
+        "POST",
+        &request_path.0,
+        Status::SeeOther,
+        Some(&session.user),
+    );
+    Ok(Redirect::to("/"))
+}
+
+/// The path of a sign in or sign out request, for the access log
+struct LoginPath(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LoginPath {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(LoginPath(request.uri().path().to_string()))
+    }
+}
+
+/// The request's valid session, if any
+struct CurrentSession(Option<Session>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CurrentSession {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(CurrentSession(current(request, &config::get_config())))
+    }
+}
+
+/// Routes to mount under the internal endpoint prefix
+pub fn routes() -> Vec<Route> {
+    routes![login_form, login, logout_form, logout]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, b"test key")
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let session = Session {
+            user: "alice.smith".to_string(),
+            expires: 2_000,
+            csrf: "token".to_string(),
+        };
+        let value = session.sign(&key());
+
+        assert_eq!(Session::verify(&value, &key(), 1_000), Some(session));
+        // Expired
+        assert_eq!(Session::verify(&value, &key(), 2_000), None);
+        // Signed with another key
+        let other = hmac::Key::new(hmac::HMAC_SHA256, b"other key");
+        assert_eq!(Session::verify(&value, &other, 1_000), None);
+    }
+
+    #[test]
+    fn test_session_rejects_tampering() {
+        let session = Session {
+            user: "alice".to_string(),
+            expires: 2_000,
+            csrf: "token".to_string(),
+        };
+        let value = session.sign(&key());
+        let extended = value.replacen("2000", "9000", 1);
+        assert_eq!(Session::verify(&extended, &key(), 1_000), None);
+        assert_eq!(Session::verify("garbage", &key(), 1_000), None);
+    }
+
+    #[test]
+    fn test_revoke() {
+        let session = |csrf: &str| Session {
+            user: "alice".to_string(),
+            expires: 2_000,
+            csrf: csrf.to_string(),
+        };
+        revoke(&session("revoked token"), 1_000);
+        assert!(is_revoked(&session("revoked token")));
+        // Other sessions of the same user stay signed in
+        assert!(!is_revoked(&session("other token")));
+
+        // Revocations are forgotten once the session has expired anyway
+        revoke(&session("later token"), 2_000);
+        assert!(!is_revoked(&session("revoked token")));
+        assert!(is_revoked(&session("later token")));
+    }
+
+    #[test]
+    fn test_has_access() {
+        let open = AuthRule {
+            prefix: "/members".to_string(),
+            roles: vec![],
+        };
+        let editors = AuthRule {
+            prefix: "/drafts".to_string(),
+            roles: vec!["editor".to_string(), "admin".to_string()],
+        };
+        assert!(has_access(&open, &[]));
+        assert!(has_access(&editors, &["admin".to_string()]));
+        assert!(!has_access(&editors, &["reader".to_string()]));
+        assert!(!has_access(&editors, &[]));
+    }
+
+    #[test]
+    fn test_find_rule_normalizes_path() {
+        let rules = vec![AuthRule {
+            prefix: "/drafts".to_string(),
+            roles: vec![],
+        }];
+        assert!(find_rule(&rules, "/drafts/post.md").is_some());
+        assert!(find_rule(&rules, "/%64rafts/post.md").is_some());
+        assert!(find_rule(&rules, "//drafts/post.md").is_some());
+        assert!(find_rule(&rules, "/drafts-old/post.md").is_none());
+    }
+
+    #[test]
+    fn test_safe_next() {
+        assert_eq!(
+            safe_next(Some("/drafts/post.md?x=1")),
+            "/drafts/post.md?x=1"
+        );
+        assert_eq!(safe_next(Some("//evil.example.com/")), "/");
+        assert_eq!(safe_next(Some("/\\evil.example.com")), "/");
+        assert_eq!(safe_next(Some("https://evil.example.com/")), "/");
+        assert_eq!(safe_next(None), "/");
+    }
+}
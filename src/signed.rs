@@ -0,0 +1,207 @@
+use crate::config::{self, Config};
+use crate::{log, units, web};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use ring::hmac;
+use rocket::Request;
+use rocket::http::{RawStr, Status};
+use rocket::request::{FromRequest, Outcome};
+
+/// Shortest key accepted for signing URLs
+pub const MIN_KEY_LENGTH: usize = 32;
+
+/// The signature for a path that stays valid until `expires` (Unix time)
+pub fn sign(key: &str, path: &str, expires: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    let tag = hmac::sign(&key, message(path, expires).as_bytes());
+    URL_SAFE_NO_PAD.encode(tag.as_ref())
+}
+
+/// Check a request's `expires` and `sig` parameters for a path, returning
+/// why they don't grant access
+pub fn verify(
+    key: &str,
+    path: &str,
+    expires: Option<i64>,
+    sig: Option<&str>,
+    now: i64,
+) -> Result<(), String> {
+    let (expires, sig) = match (expires, sig) {
+        (None, None) => return Err("no signature".to_string()),
+        (None, Some(_)) => return Err("missing or malformed expiry".to_string()),
+        (Some(_), None) => return Err("missing signature".to_string()),
+        (Some(expires), Some(sig)) => (expires, sig),
+    };
+    let tag = URL_SAFE_NO_PAD
+        .decode(sig)
+        .map_err(|_| "malformed signature".to_string())?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    hmac::verify(&key, message(path, expires).as_bytes(), &tag)
+        .map_err(|_| "bad signature".to_string())?;
+    if expires <= now {
+        let expired = DateTime::from_timestamp(expires, 0)
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| expires.to_string());
+        return Err(format!("expired at {}", expired));
+    }
+    Ok(())
+}
+
+/// What the signature covers: the expiry and the decoded, normalized URL path
+fn message(path: &str, expires: i64) -> String {
+    format!("{}\n{}", expires, path)
+}
+
+/// Whether a URL path lies under a prefix that requires a signature, once
+/// normalized so encoded spellings of a prefix are covered too
+pub fn is_protected(config: &Config, path: &str) -> bool {
+    let path = config::normalize_path(path);
+    config
+        .security
+        .signed_urls
+        .prefixes
+        .iter()
+        .any(|prefix| config::path_has_prefix(&path, prefix))
+}
+
+/// A signed URL (path and query) for a file under a protected prefix,
+/// valid for `ttl` from `now`
+pub fn link(config: &Config, path: &str, ttl: &str, now: i64) -> Result<String, String> {
+    let Some(ref key) = config.security.signed_urls.key else {
+        return Err("No signing key is configured in [security.signed_urls]".to_string());
+    };
+    let path = format!("/{}", path.trim_start_matches('/'));
+    if !is_protected(config, &path) {
+        return Err(format!(
+            "'{}' is not under a prefix in [security.signed_urls]",
+            path
+        ));
+    }
+    let ttl = units::parse_duration(ttl)?;
+    if ttl.is_zero() {
+        return Err("The link needs a --ttl longer than zero".to_string());
+    }
+    let expires = now + ttl.as_secs() as i64;
+
+    let encoded: Vec<String> = path
+        .split('/')
+        .map(|segment| RawStr::new(segment).percent_encode().to_string())
+        .collect();
+    Ok(format!(
+        "{}?expires={}&sig={}",
+        encoded.join("/"),
+        expires,
+        sign(key, &path, expires)
+    ))
+}
+
+/// Request guard requiring a valid signature under the prefixes in
+/// `[security.signed_urls]`. The reason for a refusal is only logged.
+pub struct SignedUrl;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignedUrl {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let config = config::get_config();
+        let path = request.uri().path();
+        if !is_protected(&config, path.as_str()) {
+            return Outcome::Success(SignedUrl);
+        }
+        let Some(ref key) = config.security.signed_urls.key else {
+            return Outcome::Success(SignedUrl);
+        };
+
+        let expires = request
+            .query_value::<i64>("expires")
+            .and_then(|value| value.ok());
+        let sig = request
+            .query_value::<&str>("sig")
+            .and_then(|value| value.ok());
+        let decoded = config::normalize_path(path.as_str());
+        match verify(key, &decoded, expires, sig, Utc::now().timestamp()) {
+            Ok(()) => Outcome::Success(SignedUrl),
+            Err(reason) => {
+                let addr_str = request
+                    .remote()
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                log::log_warning(&format!(
+                    "Signed URL for {} refused from {}: {}",
+                    decoded, addr_str, reason
+                ));
+                web::log_denied(request, Status::Forbidden, None);
+                Outcome::Error((Status::Forbidden, reason))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_sign_and_verify() {
+        let path = "/private/report 2024.pdf";
+        let sig = sign(KEY, path, 2000);
+        assert!(verify(KEY, path, Some(2000), Some(&sig), 1000).is_ok());
+
+        assert_eq!(
+            verify(KEY, path, Some(2000), Some(&sig), 2000).unwrap_err(),
+            "expired at 1970-01-01T00:33:20+00:00"
+        );
+        assert_eq!(
+            verify(KEY, path, Some(3000), Some(&sig), 1000).unwrap_err(),
+            "bad signature"
+        );
+        assert_eq!(
+            verify(KEY, "/private/other.pdf", Some(2000), Some(&sig), 1000).unwrap_err(),
+            "bad signature"
+        );
+        assert_eq!(
+            verify("another key", path, Some(2000), Some(&sig), 1000).unwrap_err(),
+            "bad signature"
+        );
+        assert_eq!(
+            verify(KEY, path, None, None, 1000).unwrap_err(),
+            "no signature"
+        );
+        assert_eq!(
+            verify(KEY, path, Some(2000), Some("not base64!"), 1000).unwrap_err(),
+            "malformed signature"
+        );
+    }
+
+    #[test]
+    fn test_link() {
+        let mut config = Config::default();
+        config.security.signed_urls.key = Some(KEY.to_string());
+        config.security.signed_urls.prefixes = vec!["/private".to_string()];
+
+        let url = link(&config, "private/report 2024.pdf", "1h", 1000).unwrap();
+        let expected = format!(
+            "/private/report%202024.pdf?expires=4600&sig={}",
+            sign(KEY, "/private/report 2024.pdf", 4600)
+        );
+        assert_eq!(url, expected);
+        assert!(link(&config, "/public/file.zip", "1h", 1000).is_err());
+        assert!(link(&config, "/private/file.zip", "0h", 1000).is_err());
+    }
+
+    #[test]
+    fn test_is_protected_normalizes_path() {
+        let mut config = Config::default();
+        config.security.signed_urls.prefixes = vec!["/private".to_string()];
+
+        assert!(is_protected(&config, "/private/report.pdf"));
+        assert!(is_protected(&config, "/%70rivate/report.pdf"));
+        assert!(is_protected(&config, "//private/report.pdf"));
+        assert!(is_protected(&config, "/public/../private/report.pdf"));
+        assert!(!is_protected(&config, "/private-notes/report.pdf"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::config::{self, Config};
+use crate::feed::absolute;
+use crate::render::escape_html;
+use crate::{frontmatter, signed, symlinks, web};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Most URLs in one sitemap file; larger sites get a sitemap index
+pub const MAX_URLS: usize = 50_000;
+
+/// The pages found by the last walk, with the fingerprint of the files they
+/// were read from
+static CACHE: Mutex<Option<(u64, Arc<Vec<Page>>)>> = Mutex::new(None);
+
+/// A generated file at the root of the site
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    Robots,
+    /// The sitemap, or the numbered part of a split one
+    Sitemap(Option<usize>),
+}
+
+impl Document {
+    /// How a resolved request for the document is handled
+    pub fn handling(self) -> &'static str {
+        match self {
+            Document::Robots => "robots",
+            Document::Sitemap(_) => "sitemap",
+        }
+    }
+}
+
+/// A page the site serves to anyone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    /// URL path of the page
+    pub url: String,
+    /// Its date, else its file's modification time
+    pub lastmod: Option<DateTime<Utc>>,
+    /// Whether its front matter asks search engines to stay away
+    pub noindex: bool,
+}
+
+/// A public page file and the URL it is served at
+#[derive(Debug, Clone, Hash)]
+pub struct PageFile {
+    pub path: PathBuf,
+    pub url: String,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// The document for a request such as `sitemap.xml` or `sitemap-2.xml`
+pub fn request(file: &Path) -> Option<Document> {
+    if file.components().count() != 1 {
+        return None;
+    }
+    let name = file.to_str()?;
+    if name == "robots.txt" {
+        return Some(Document::Robots);
+    }
+    if name == "sitemap.xml" {
+        return Some(Document::Sitemap(None));
+    }
+    let part = name
+        .strip_prefix("sitemap-")?
+        .strip_suffix(".xml")?
+        .parse::<usize>()
+        .ok()?;
+    (part > 0).then_some(Document::Sitemap(Some(part)))
+}
+
+/// Whether a URL path needs a login, a client certificate or a signature
+pub fn is_protected(config: &Config, url: &str) -> bool {
+    let url = url.trim_end_matches('/');
+    let url = if url.is_empty() { "/" } else { url };
+    let prefixes = config
+        .auth
+        .basic
+        .iter()
+        .map(|rule| &rule.prefix)
+        .chain(config.auth.rules.iter().map(|rule| &rule.prefix))
+        .chain(config.security.client_certs.iter().map(|rule| &rule.prefix));
+    signed::is_protected(config, url)
+        || prefixes
+            .into_iter()
+            .any(|prefix| config::path_has_prefix(url, prefix))
+}
+
+/// Whether a file is a page: HTML, or markdown when it is rendered
+fn is_page(config: &Config, name: &str) -> bool {
+    match Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("html" | "htm") => true,
+        Some("md") => config.content.markdown_enabled,
+        _ => false,
+    }
+}
+
+/// Collect the page files under a directory, skipping anything the
+/// extension, symlink or authentication rules keep from the public
+fn walk(config: &Config, dir: &Path, url: &str, files: &mut Vec<PageFile>) {
+    let Ok(children) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut children: Vec<(String, PathBuf)> = children
+        .flatten()
+        .map(|child| {
+            (
+                child.file_name().to_string_lossy().to_string(),
+                child.path(),
+            )
+        })
+        .filter(|(name, _)| !name.starts_with('.'))
+        .collect();
+    children.sort();
+
+    // Only the first index file found is served for the directory
+    let index = config
+        .content
+        .index_files
+        .iter()
+        .find(|index| is_page(config, index) && dir.join(index).is_file());
+
+    for (name, path) in children {
+        if web::blocked_component(config, &path).is_some()
+            || symlinks::check(&config.content.webroot, &path, config.security.symlinks).is_err()
+        {
+            continue;
+        }
+        if path.is_dir() {
+            let url = format!("{}{}/", url, name);
+            if !is_protected(config, &url) {
+                walk(config, &path, &url, files);
+            }
+            continue;
+        }
+        if !is_page(config, &name) {
+            continue;
+        }
+        let url = if config.content.index_files.contains(&name) {
+            if index != Some(&name) {
+                continue;
+            }
+            url.to_string()
+        } else {
+            format!("{}{}", url, name)
+        };
+        if is_protected(config, &url) {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        files.push(PageFile {
+            path,
+            url,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+}
+
+/// The HTML and markdown files under the webroot that anyone may read,
+/// sorted by URL. Front matter isn't read, so drafts are included.
+pub fn page_files(config: &Config) -> Vec<PageFile> {
+    let mut files = Vec::new();
+    walk(config, &config.content.webroot, "/", &mut files);
+    files.sort_by(|a, b| a.url.cmp(&b.url));
+    files
+}
+
+/// Every public page under the webroot, sorted by URL. Drafts are left out.
+/// The pages are kept in memory until a page file changes.
+pub fn pages(config: &Config) -> Arc<Vec<Page>> {
+    let files = page_files(config);
+    let mut hasher = DefaultHasher::new();
+    files.hash(&mut hasher);
+    let fingerprint = hasher.finish();
+    if let Ok(cache) = CACHE.lock()
+        && let Some((cached, pages)) = cache.as_ref()
+        && *cached == fingerprint
+    {
+        return Arc::clone(pages);
+    }
+
+    let pages: Arc<Vec<Page>> = Arc::new(
+        files
+            .into_iter()
+            .filter_map(|file| {
+                let modified = file.modified.map(DateTime::<Utc>::from);
+                if file
+                    .path
+                    .extension()
+                    .is_none_or(|extension| extension != "md")
+                {
+                    return Some(Page {
+                        url: file.url,
+                        lastmod: modified,
+                        noindex: false,
+                    });
+                }
+                let source = web::read_to_string(config, &file.path).ok()?;
+                let (front_matter, _) = frontmatter::split(&source);
+                if front_matter.draft {
+                    return None;
+                }
+                Some(Page {
+                    lastmod: front_matter.date_time().or(modified),
+                    url: file.url,
+                    noindex: front_matter.noindex,
+                })
+            })
+            .collect(),
+    );
+    if let Ok(mut cache) = CACHE.lock() {
+        *cache = Some((fingerprint, Arc::clone(&pages)));
+    }
+    pages
+}
+
+fn lastmod(time: DateTime<Utc>) -> String {
+    format!(
+        "<lastmod>{}</lastmod>",
+        time.to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+/// The sitemap, or a part of it. Up to `limit` pages it is a single
+/// `urlset`; above that `/sitemap.xml` is an index of `/sitemap-N.xml`
+/// parts. `None` for a part that doesn't exist.
+fn sitemap_document(
+    site_url: &str,
+    pages: &[&Page],
+    part: Option<usize>,
+    limit: usize,
+) -> Option<String> {
+    let parts = pages.len().div_ceil(limit).max(1);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let urls = match part {
+        None if parts > 1 => {
+            xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+            for (number, chunk) in pages.chunks(limit).enumerate() {
+                xml.push_str(&format!(
+                    "<sitemap><loc>{}</loc>",
+                    escape_html(&absolute(site_url, &format!("/sitemap-{}.xml", number + 1)))
+                ));
+                if let Some(time) = chunk.iter().filter_map(|page| page.lastmod).max() {
+                    xml.push_str(&lastmod(time));
+                }
+                xml.push_str("</sitemap>\n");
+            }
+            xml.push_str("</sitemapindex>\n");
+            return Some(xml);
+        }
+        None => pages,
+        Some(part) if parts > 1 && part <= parts => {
+            let start = (part - 1) * limit;
+            &pages[start..(start + limit).min(pages.len())]
+        }
+        Some(_) => return None,
+    };
+
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for page in urls {
+        xml.push_str(&format!(
+            "<url><loc>{}</loc>",
+            escape_html(&absolute(site_url, &page.url))
+        ));
+        if let Some(time) = page.lastmod {
+            xml.push_str(&lastmod(time));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    Some(xml)
+}
+
+/// The sitemap of the site's public pages, or a numbered part of it when
+/// it is split. `None` when `template.site_url` isn't set.
+pub fn sitemap(config: &Config, part: Option<usize>) -> Option<String> {
+    let site_url = config.template.site_url.as_deref()?;
+    let pages = pages(config);
+    let listed: Vec<&Page> = pages.iter().filter(|page| !page.noindex).collect();
+    sitemap_document(site_url, &listed, part, MAX_URLS)
+}
+
+/// A `robots.txt` for sites without one: everything may be crawled except
+/// pages marked `noindex`. The sitemap is named when `template.site_url` is
+/// set, since it is only served then.
+pub fn robots(config: &Config) -> String {
+    let mut text = String::from("User-agent: *\n");
+    let hidden: Vec<String> = pages(config)
+        .iter()
+        .filter(|page| page.noindex)
+        .map(|page| format!("Disallow: {}\n", absolute("", &page.url)))
+        .collect();
+    if hidden.is_empty() {
+        text.push_str("Disallow:\n");
+    } else {
+        text.extend(hidden);
+    }
+    if let Some(ref site_url) = config.template.site_url {
+        text.push_str(&format!(
+            "\nSitemap: {}\n",
+            absolute(site_url, "/sitemap.xml")
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BasicAuthRule;
+
+    fn site(name: &str) -> Config {
+        let root = std::env::temp_dir().join(format!(
+            "barleywine_sitemap_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("blog")).unwrap();
+        fs::create_dir_all(root.join("private")).unwrap();
+        fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(root.join("about page.md"), "# About").unwrap();
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        fs::create_dir_all(root.join("old.bak")).unwrap();
+        fs::write(root.join("old.bak/notes.md"), "# Old").unwrap();
+        fs::write(
+            root.join("blog/index.md"),
+            "---\ncollection: true\n---\n# Blog",
+        )
+        .unwrap();
+        fs::write(
+            root.join("blog/post.md"),
+            "---\ndate: 2024-01-15\n---\n# Post",
+        )
+        .unwrap();
+        fs::write(root.join("blog/draft.md"), "---\ndraft: true\n---\n# Draft").unwrap();
+        fs::write(
+            root.join("blog/thanks.md"),
+            "---\nnoindex: true\n---\n# Thanks",
+        )
+        .unwrap();
+        fs::write(root.join("private/secret.html"), "secret").unwrap();
+
+        let mut config = Config::default();
+        config.content.webroot = root;
+        config.template.site_url = Some("https://example.com".to_string());
+        config.security.blocked_extensions.push(".bak".to_string());
+        config.auth.basic.push(BasicAuthRule {
+            prefix: "/private".to_string(),
+            realm: "Private".to_string(),
+            htpasswd: PathBuf::from("users.htpasswd"),
+        });
+        config
+    }
+
+    #[test]
+    fn test_request() {
+        assert_eq!(request(Path::new("robots.txt")), Some(Document::Robots));
+        assert_eq!(
+            request(Path::new("sitemap.xml")),
+            Some(Document::Sitemap(None))
+        );
+        assert_eq!(
+            request(Path::new("sitemap-2.xml")),
+            Some(Document::Sitemap(Some(2)))
+        );
+        assert_eq!(request(Path::new("sitemap-0.xml")), None);
+        assert_eq!(request(Path::new("blog/sitemap.xml")), None);
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let config = site("pages");
+        let urls: Vec<String> = pages(&config).iter().map(|page| page.url.clone()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "/",
+                "/about page.md",
+                "/blog/",
+                "/blog/post.md",
+                "/blog/thanks.md"
+            ]
+        );
+
+        let xml = sitemap(&config, None).unwrap();
+        assert!(xml.contains("<urlset"));
+        assert!(xml.contains("<loc>https://example.com/about%20page.md</loc>"));
+        assert!(xml.contains(
+            "<url><loc>https://example.com/blog/post.md</loc><lastmod>2024-01-15T00:00:00Z</lastmod></url>"
+        ));
+        assert!(!xml.contains("thanks"));
+        assert!(!xml.contains("draft"));
+        assert!(sitemap(&config, Some(1)).is_none());
+
+        assert_eq!(
+            robots(&config),
+            "User-agent: *\nDisallow: /blog/thanks.md\n\nSitemap: https://example.com/sitemap.xml\n"
+        );
+
+        // Without an absolute address the rules are still served
+        let mut config = config;
+        config.template.site_url = None;
+        assert_eq!(
+            robots(&config),
+            "User-agent: *\nDisallow: /blog/thanks.md\n"
+        );
+
+        fs::remove_dir_all(&config.content.webroot).ok();
+    }
+
+    #[test]
+    fn test_sitemap_index() {
+        let pages: Vec<Page> = (1..=5)
+            .map(|number| Page {
+                url: format!("/{}.html", number),
+                lastmod: None,
+                noindex: false,
+            })
+            .collect();
+        let pages: Vec<&Page> = pages.iter().collect();
+        let site_url = "https://example.com";
+
+        let index = sitemap_document(site_url, &pages, None, 2).unwrap();
+        assert!(index.contains("<sitemapindex"));
+        assert_eq!(index.matches("<sitemap>").count(), 3);
+        assert!(index.contains("<loc>https://example.com/sitemap-3.xml</loc>"));
+
+        let last = sitemap_document(site_url, &pages, Some(3), 2).unwrap();
+        assert_eq!(last.matches("<url>").count(), 1);
+        assert!(last.contains("https://example.com/5.html"));
+        assert!(sitemap_document(site_url, &pages, Some(4), 2).is_none());
+    }
+}
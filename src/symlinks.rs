@@ -0,0 +1,205 @@
+use crate::config::SymlinkPolicy;
+use crate::log;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Check `path`, a file under `webroot`, against the symlink policy,
+/// returning why it is refused
+pub fn check(webroot: &Path, path: &Path, policy: SymlinkPolicy) -> Result<(), String> {
+    match policy {
+        SymlinkPolicy::Follow => Ok(()),
+        SymlinkPolicy::WithinWebroot => contained(webroot, path).map(|_| ()),
+        SymlinkPolicy::Deny => {
+            let relative = path
+                .strip_prefix(webroot)
+                .map_err(|_| format!("{} is outside the webroot", path.display()))?;
+            let mut current = webroot.to_path_buf();
+            for component in relative.components() {
+                current.push(component);
+                let metadata = fs::symlink_metadata(&current)
+                    .map_err(|e| format!("{}: {}", current.display(), e))?;
+                if metadata.file_type().is_symlink() {
+                    return Err(format!("{} is a symbolic link", current.display()));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The canonical form of `path`, when it lies under the canonical webroot
+fn contained(webroot: &Path, path: &Path) -> Result<PathBuf, String> {
+    let root = webroot
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", webroot.display(), e))?;
+    let target = path
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if target.starts_with(&root) {
+        Ok(target)
+    } else {
+        Err(format!(
+            "{} resolves to {}, outside the webroot",
+            path.display(),
+            target.display()
+        ))
+    }
+}
+
+/// Open a file that passed [`check`], enforcing the policy on the open
+/// itself so a link swapped in after the check can't escape the webroot
+pub fn open(webroot: &Path, path: &Path, policy: SymlinkPolicy) -> io::Result<File> {
+    if policy == SymlinkPolicy::Follow {
+        return File::open(path);
+    }
+
+    // The kernel resolves the path beneath the webroot in a single step
+    #[cfg(target_os = "linux")]
+    match openat2(webroot, path, policy) {
+        // Kernels before 5.6, and some sandboxes, don't offer openat2
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) => {}
+        // Absolute links are never resolved beneath a directory, even when
+        // they point inside it, so those take the slower route below
+        Err(e)
+            if e.raw_os_error() == Some(libc::EXDEV) && policy == SymlinkPolicy::WithinWebroot => {}
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EXDEV | libc::ELOOP)) => {
+            return Err(refuse(
+                path,
+                "a symbolic link is not allowed here or leads outside the webroot".to_string(),
+            ));
+        }
+        result => return result,
+    }
+
+    // Otherwise open first and then confirm that the file we hold is the one
+    // a permitted path names, which a swapped link can't fake
+    let file = File::open(path)?;
+    check(webroot, path, policy).map_err(|reason| refuse(path, reason))?;
+    if !same_file(&file, path)? {
+        return Err(refuse(
+            path,
+            "the file changed while it was being opened".to_string(),
+        ));
+    }
+    Ok(file)
+}
+
+/// Log a refused path as a security warning
+fn refuse(path: &Path, reason: String) -> io::Error {
+    log::log_warning(&format!(
+        "Symlink policy refused {}: {}",
+        path.display(),
+        reason
+    ));
+    io::Error::new(io::ErrorKind::PermissionDenied, reason)
+}
+
+#[cfg(target_os = "linux")]
+fn openat2(webroot: &Path, path: &Path, policy: SymlinkPolicy) -> io::Result<File> {
+    use std::ffi::CString;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    let relative = path
+        .strip_prefix(webroot)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let name = CString::new(relative.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let root = File::open(webroot)?;
+
+    // SAFETY: open_how is plain data, and zero is valid for every field
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (libc::O_RDONLY | libc::O_CLOEXEC) as u64;
+    how.resolve = libc::RESOLVE_BENEATH;
+    if policy == SymlinkPolicy::Deny {
+        how.resolve |= libc::RESOLVE_NO_SYMLINKS;
+    }
+
+    // SAFETY: every pointer outlives the call
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            root.as_raw_fd(),
+            name.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the kernel returned a new descriptor that nothing else owns
+    Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
+
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let held = file.metadata()?;
+    let named = fs::metadata(path)?;
+    Ok(held.dev() == named.dev() && held.ino() == named.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(file: &File, path: &Path) -> io::Result<bool> {
+    // Without inode numbers, the size and modification time will have to do
+    let held = file.metadata()?;
+    let named = fs::metadata(path)?;
+    Ok(held.len() == named.len() && held.modified()? == named.modified()?)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+    use assert_fs::prelude::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_symlink_policies() {
+        let temp = TempDir::new().unwrap();
+        temp.child("secret.txt").write_str("secret").unwrap();
+        let webroot = temp.child("webroot");
+        webroot.child("docs/page.txt").write_str("page").unwrap();
+        symlink(
+            webroot.child("docs/page.txt").path(),
+            webroot.child("inside.txt").path(),
+        )
+        .unwrap();
+        symlink(
+            temp.child("secret.txt").path(),
+            webroot.child("outside.txt").path(),
+        )
+        .unwrap();
+        symlink(temp.path(), webroot.child("escape").path()).unwrap();
+
+        let root = webroot.path();
+        let plain = root.join("docs/page.txt");
+        let inside = root.join("inside.txt");
+        let outside = root.join("outside.txt");
+        let through_dir = root.join("escape/secret.txt");
+
+        for policy in [
+            SymlinkPolicy::Deny,
+            SymlinkPolicy::WithinWebroot,
+            SymlinkPolicy::Follow,
+        ] {
+            assert!(check(root, &plain, policy).is_ok());
+            assert!(open(root, &plain, policy).is_ok());
+        }
+
+        assert!(check(root, &inside, SymlinkPolicy::Deny).is_err());
+        assert!(open(root, &inside, SymlinkPolicy::Deny).is_err());
+        assert!(check(root, &inside, SymlinkPolicy::WithinWebroot).is_ok());
+        assert!(open(root, &inside, SymlinkPolicy::WithinWebroot).is_ok());
+
+        for path in [&outside, &through_dir] {
+            assert!(check(root, path, SymlinkPolicy::WithinWebroot).is_err());
+            assert!(open(root, path, SymlinkPolicy::WithinWebroot).is_err());
+            assert!(check(root, path, SymlinkPolicy::Follow).is_ok());
+            assert!(open(root, path, SymlinkPolicy::Follow).is_ok());
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use crate::config::{self, TlsConfig};
+use crate::{log, watch};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use notify::RecommendedWatcher;
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{self, KeyPair as _};
+use rocket::http::Method;
+use rocket::response::Redirect;
+use rocket::route::{Handler, Outcome};
+use rocket::tokio::sync::Notify;
+use rocket::{Build, Data, Request, Rocket, Route};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use x509_parser::pem::Pem;
+
+/// TLS 1.3 cipher suites supported by Rocket, strongest first
+pub const TLS13_CIPHERS: &[&str] = &[
+    "TLS_CHACHA20_POLY1305_SHA256",
+    "TLS_AES_256_GCM_SHA384",
+    "TLS_AES_128_GCM_SHA256",
+];
+
+/// TLS 1.2 cipher suites supported by Rocket, strongest first
+pub const TLS12_CIPHERS: &[&str] = &[
+    "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+    "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+    "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+    "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+    "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+    "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+];
+
+/// Days a generated development CA stays valid
+const DEV_CA_DAYS: i64 = 3650;
+
+/// Days a generated development leaf certificate stays valid (browsers reject
+/// leaf certificates valid for much longer than a year)
+const DEV_LEAF_DAYS: i64 = 365;
+
+/// Set when the certificate watcher asks for a relaunch, until it is done
+static RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Wakes the server loop when the certificate changed
+static RESTART: Notify = Notify::const_new();
+
+/// Facts about the configured certificate and key
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub not_after: DateTime<Utc>,
+    /// `None` when the key type cannot be checked against the certificate
+    pub key_matches: Option<bool>,
+}
+
+impl CertificateInfo {
+    /// Whole days until the certificate expires (negative once expired)
+    pub fn days_remaining(&self) -> i64 {
+        (self.not_after - Utc::now()).num_days()
+    }
+}
+
+/// The cipher suites to offer, honouring `min_version`. An empty `ciphers`
+/// list means every suite Rocket supports for the allowed versions.
+pub fn effective_ciphers(tls: &TlsConfig) -> Result<Vec<&'static str>, String> {
+    let allowed: Vec<&'static str> = match tls.min_version.as_str() {
+        "1.2" => TLS13_CIPHERS.iter().chain(TLS12_CIPHERS).copied().collect(),
+        "1.3" => TLS13_CIPHERS.to_vec(),
+        other => {
+            return Err(format!(
+                "unsupported min_version '{}' (expected \"1.2\" or \"1.3\")",
+                other
+            ));
+        }
+    };
+
+    if tls.ciphers.is_empty() {
+        return Ok(allowed);
+    }
+
+    let mut ciphers = Vec::new();
+    for name in &tls.ciphers {
+        let known = TLS13_CIPHERS
+            .iter()
+            .chain(TLS12_CIPHERS)
+            .find(|cipher| cipher.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown cipher suite '{}'", name))?;
+        if allowed.contains(known) {
+            ciphers.push(*known);
+        }
+    }
+
+    if ciphers.is_empty() {
+        return Err(format!(
+            "none of the configured ciphers are available with TLS {}",
+            tls.min_version
+        ));
+    }
+    Ok(ciphers)
+}
+
+/// Read the leaf certificate and check that the private key belongs to it
+pub fn inspect(tls: &TlsConfig) -> Result<CertificateInfo, String> {
+    let cert_pem =
+        fs::read(&tls.certs).map_err(|e| format!("cannot read {}: {}", tls.certs.display(), e))?;
+    let key_pem =
+        fs::read(&tls.key).map_err(|e| format!("cannot read {}: {}", tls.key.display(), e))?;
+
+    let leaf = Pem::iter_from_buffer(&cert_pem)
+        .filter_map(Result::ok)
+        .find(|pem| pem.label == "CERTIFICATE")
+        .ok_or_else(|| format!("no certificate found in {}", tls.certs.display()))?;
+    let cert = leaf
+        .parse_x509()
+        .map_err(|e| format!("invalid certificate in {}: {}", tls.certs.display(), e))?;
+
+    let key = Pem::iter_from_buffer(&key_pem)
+        .filter_map(Result::ok)
+        .find(|pem| pem.label.ends_with("PRIVATE KEY"))
+        .ok_or_else(|| format!("no private key found in {}", tls.key.display()))?;
+
+    let not_after = DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| "certificate expiry date out of range".to_string())?;
+
+    Ok(CertificateInfo {
+        subject: cert.subject().to_string(),
+        not_after,
+        key_matches: public_key(&key.label, &key.contents)
+            .map(|public| public == cert.public_key().subject_public_key.data.as_ref()),
+    })
+}
+
+/// Derive the public key from a PEM private key, for the key types ring reads
+fn public_key(label: &str, der: &[u8]) -> Option<Vec<u8>> {
+    let rng = SystemRandom::new();
+    match label {
+        "RSA PRIVATE KEY" => signature::RsaKeyPair::from_der(der)
+            .ok()
+            .map(|pair| pair.public_key().as_ref().to_vec()),
+        "PRIVATE KEY" => {
+            if let Ok(pair) = signature::RsaKeyPair::from_pkcs8(der) {
+                return Some(pair.public_key().as_ref().to_vec());
+            }
+            if let Ok(pair) = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+                return Some(pair.public_key().as_ref().to_vec());
+            }
+            [
+                &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+                &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+            ]
+            .into_iter()
+            .find_map(|algorithm| signature::EcdsaKeyPair::from_pkcs8(algorithm, der, &rng).ok())
+            .map(|pair| pair.public_key().as_ref().to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Watch the certificate and key files, asking for the server to be
+/// relaunched with the new pair once both files are valid again
+pub fn watch_certificates(tls: &TlsConfig) -> Vec<RecommendedWatcher> {
+    if !tls.reload {
+        return Vec::new();
+    }
+
+    let mut watchers = Vec::new();
+    for path in [&tls.certs, &tls.key] {
+        let tls = tls.clone();
+        let on_change = move || {
+            if RESTART_REQUESTED.load(Ordering::SeqCst) {
+                return;
+            }
+            // A renewal rewrites both files; wait until they agree again
+            match inspect(&tls) {
+                Ok(info) if info.key_matches != Some(false) => {
+                    log::log_barleywine(&format!(
+                        "TLS certificate changed (expires {}), relaunching the server",
+                        info.not_after.format("%Y-%m-%d")
+                    ));
+                    RESTART_REQUESTED.store(true, Ordering::SeqCst);
+                    RESTART.notify_one();
+                }
+                Ok(_) => log::log_warning(
+                    "TLS certificate changed but does not match the key yet, waiting",
+                ),
+                Err(e) => log::log_warning(&format!(
+                    "TLS certificate changed but cannot be loaded yet: {}",
+                    e
+                )),
+            }
+        };
+
+        match watch::watch_file(path, on_change) {
+            Ok(watcher) => watchers.push(watcher),
+            Err(e) => log::log_warning(&format!(
+                "TLS certificate reload disabled, could not watch {}: {}",
+                path.display(),
+                e
+            )),
+        }
+    }
+    watchers
+}
+
+/// Wait until the certificate watcher asks for a relaunch
+pub async fn restart_requested() {
+    RESTART.notified().await;
+}
+
+/// Note that the requested relaunch is done, so further changes are acted on
+pub fn finish_restart() {
+    RESTART_REQUESTED.store(false, Ordering::SeqCst);
+}
+
+/// Answers every plain HTTP request with a permanent redirect to HTTPS
+#[derive(Clone)]
+struct HttpsRedirect {
+    https_port: u16,
+}
+
+#[rocket::async_trait]
+impl Handler for HttpsRedirect {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+        let config = config::get_config();
+        let host = request
+            .host()
+            .map(|host| host.domain().to_string())
+            .unwrap_or_else(|| config.server.host.clone());
+        let location = https_location(&host, self.https_port, &request.uri().to_string());
+        Outcome::from(request, Redirect::permanent(location))
+    }
+}
+
+/// The HTTPS URL for a host, port and request URI
+fn https_location(host: &str, port: u16, uri: &str) -> String {
+    let host = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    };
+    match port {
+        443 => format!("https://{}{}", host, uri),
+        port => format!("https://{}:{}{}", host, port, uri),
+    }
+}
+
+/// Build the plain HTTP listener that redirects to the HTTPS server, if configured
+pub fn build_redirect_rocket() -> Option<Rocket<Build>> {
+    let config = config::get_config();
+    let tls = config.server.tls.as_ref()?;
+    let redirect_port = tls.redirect_port?;
+
+    let handler = HttpsRedirect {
+        https_port: config.server.port,
+    };
+    let routes: Vec<Route> = [
+        Method::Get,
+        Method::Head,
+        Method::Post,
+        Method::Put,
+        Method::Delete,
+        Method::Patch,
+        Method::Options,
+    ]
+    .into_iter()
+    .map(|method| Route::new(method, "/<path..>", handler.clone()))
+    .collect();
+
+    let figment = rocket::Config::figment()
+        .merge(("address", config.server.host.as_str()))
+        .merge(("port", redirect_port));
+
+    log::log_barleywine(&format!(
+        "Redirecting HTTP on {}:{} to HTTPS on port {}",
+        config.server.host, redirect_port, config.server.port
+    ));
+
+    Some(rocket::custom(figment).mount("/", routes))
+}
+
+/// Files written by `--dev-tls`
+#[derive(Debug, Clone)]
+pub struct DevCertificates {
+    /// The local CA certificate developers add to their trust store
+    pub ca: PathBuf,
+    pub certs: PathBuf,
+    pub key: PathBuf,
+}
+
+impl DevCertificates {
+    /// TLS settings serving the generated leaf certificate
+    pub fn tls_config(&self) -> TlsConfig {
+        TlsConfig {
+            certs: self.certs.clone(),
+            key: self.key.clone(),
+            min_version: "1.2".to_string(),
+            ciphers: Vec::new(),
+            prefer_server_cipher_order: false,
+            client_ca: None,
+            reload: false,
+            redirect_port: None,
+        }
+    }
+}
+
+/// Generate a leaf certificate for localhost and `host`, signed by a local CA
+/// under `state_dir/tls`. The CA is created once and reused so it only needs
+/// to be trusted once; the leaf is issued afresh on every start.
+pub fn generate_dev_certificates(state_dir: &Path, host: &str) -> Result<DevCertificates, String> {
+    let directory = state_dir.join("tls");
+    fs::create_dir_all(&directory)
+        .map_err(|e| format!("cannot create {}: {}", directory.display(), e))?;
+    let files = DevCertificates {
+        ca: directory.join("ca.pem"),
+        certs: directory.join("cert.pem"),
+        key: directory.join("key.pem"),
+    };
+    let ca_key_path = directory.join("ca-key.pem");
+
+    // The CA certificate served in the chain is always the one on disk, which
+    // developers have trusted. rcgen only signs with a `Certificate` issuer, so
+    // an existing CA is rebuilt from its stored parameters for signing alone;
+    // that keeps its name and key identifier, so the leaf chains to the stored
+    // certificate.
+    let (ca_cert, ca_key, ca_pem) = if files.ca.exists() && ca_key_path.exists() {
+        let ca_pem = read_to_string(&files.ca)?;
+        let ca_key = KeyPair::from_pem(&read_to_string(&ca_key_path)?)
+            .map_err(|e| format!("invalid CA key {}: {}", ca_key_path.display(), e))?;
+        let ca_cert = CertificateParams::from_ca_cert_pem(&ca_pem)
+            .and_then(|params| params.self_signed(&ca_key))
+            .map_err(|e| format!("invalid CA certificate {}: {}", files.ca.display(), e))?;
+        (ca_cert, ca_key, ca_pem)
+    } else {
+        let ca_key = KeyPair::generate().map_err(|e| e.to_string())?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name = distinguished_name("Barleywine Development CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        set_validity(&mut params, DEV_CA_DAYS);
+        let ca_cert = params.self_signed(&ca_key).map_err(|e| e.to_string())?;
+
+        let ca_pem = ca_cert.pem();
+        write_file(&files.ca, &ca_pem, false)?;
+        write_file(&ca_key_path, &ca_key.serialize_pem(), true)?;
+        log::log_barleywine(&format!("Generated development CA: {}", files.ca.display()));
+        (ca_cert, ca_key, ca_pem)
+    };
+
+    let leaf_key = KeyPair::generate().map_err(|e| e.to_string())?;
+    let mut params = CertificateParams::new(dev_hostnames(host)).map_err(|e| e.to_string())?;
+    params.distinguished_name = distinguished_name("localhost");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    set_validity(&mut params, DEV_LEAF_DAYS);
+    let leaf = params
+        .signed_by(&leaf_key, &ca_cert, &ca_key)
+        .map_err(|e| e.to_string())?;
+
+    write_file(&files.certs, &format!("{}{}", leaf.pem(), ca_pem), false)?;
+    write_file(&files.key, &leaf_key.serialize_pem(), true)?;
+    Ok(files)
+}
+
+/// Names the development certificate is valid for
+fn dev_hostnames(host: &str) -> Vec<String> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let unspecified = host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified());
+    if !host.is_empty() && !unspecified && !names.iter().any(|name| name == host) {
+        names.push(host.to_string());
+    }
+    names
+}
+
+fn distinguished_name(common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::OrganizationName, "Barleywine");
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+/// Make a certificate valid from yesterday, to allow for clock skew, for `days`
+fn set_validity(params: &mut CertificateParams, days: i64) {
+    let date = |when: DateTime<Utc>| {
+        rcgen::date_time_ymd(when.year(), when.month() as u8, when.day() as u8)
+    };
+    let now = Utc::now();
+    params.not_before = date(now - Duration::days(1));
+    params.not_after = date(now + Duration::days(days));
+}
+
+fn read_to_string(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))
+}
+
+/// Write a PEM file, readable only by the owner when it holds a private key
+fn write_file(path: &Path, contents: &str, private: bool) -> Result<(), String> {
+    let write = || -> io::Result<()> {
+        fs::write(path, contents)?;
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    };
+    write().map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
+
+/// Print the TLS section of `--verify` output
+pub fn print_verification(tls: &TlsConfig) {
+    match inspect(tls) {
+        Ok(info) => {
+            println!("  TLS certificate: ✅ {}", info.subject);
+            match info.key_matches {
+                Some(true) => println!("  TLS key: ✅ Matches certificate"),
+                Some(false) => println!("  TLS key: ❌ Does not match certificate"),
+                None => println!("  TLS key: ⚠️  Key type not checked against certificate"),
+            }
+            let days = info.days_remaining();
+            let expiry = info.not_after.format("%Y-%m-%d");
+            if days < 0 {
+                println!("  TLS expiry: ❌ Expired on {}", expiry);
+            } else if days < 30 {
+                println!("  TLS expiry: ⚠️  Expires {} ({} days)", expiry, days);
+            } else {
+                println!("  TLS expiry: ✅ Expires {} ({} days)", expiry, days);
+            }
+        }
+        Err(e) => println!("  TLS certificate: ❌ {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tls_config(min_version: &str, ciphers: &[&str]) -> TlsConfig {
+        TlsConfig {
+            certs: "cert.pem".into(),
+            key: "key.pem".into(),
+            min_version: min_version.to_string(),
+            ciphers: ciphers.iter().map(|cipher| cipher.to_string()).collect(),
+            prefer_server_cipher_order: false,
+            client_ca: None,
+            reload: true,
+            redirect_port: None,
+        }
+    }
+
+    #[test]
+    fn test_effective_ciphers() {
+        assert_eq!(effective_ciphers(&tls_config("1.2", &[])).unwrap().len(), 9);
+        assert_eq!(
+            effective_ciphers(&tls_config("1.3", &[])).unwrap(),
+            TLS13_CIPHERS
+        );
+
+        // TLS 1.2 suites are dropped when 1.3 is the minimum
+        let ciphers = effective_ciphers(&tls_config(
+            "1.3",
+            &[
+                "tls_aes_128_gcm_sha256",
+                "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+            ],
+        ))
+        .unwrap();
+        assert_eq!(ciphers, vec!["TLS_AES_128_GCM_SHA256"]);
+
+        assert!(effective_ciphers(&tls_config("1.1", &[])).is_err());
+        assert!(effective_ciphers(&tls_config("1.2", &["TLS_RSA_WITH_RC4"])).is_err());
+        assert!(
+            effective_ciphers(&tls_config(
+                "1.3",
+                &["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"]
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_generate_dev_certificates() {
+        let state_dir = std::env::temp_dir().join("barleywine_test_dev_tls");
+        let _ = fs::remove_dir_all(&state_dir);
+
+        let files = generate_dev_certificates(&state_dir, "dev.example.test").unwrap();
+        let info = inspect(&files.tls_config()).unwrap();
+        assert_eq!(info.key_matches, Some(true));
+        assert!(info.subject.contains("localhost"));
+        assert!(info.days_remaining() >= DEV_LEAF_DAYS - 1);
+
+        // The CA survives a second run so it only has to be trusted once
+        let ca = fs::read_to_string(&files.ca).unwrap();
+        generate_dev_certificates(&state_dir, "dev.example.test").unwrap();
+        assert_eq!(fs::read_to_string(&files.ca).unwrap(), ca);
+
+        // and the new leaf is served with, and signed by, that same certificate
+        let chain = fs::read_to_string(&files.certs).unwrap();
+        assert!(chain.ends_with(&ca));
+        let pems: Vec<Pem> = Pem::iter_from_buffer(chain.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let leaf = pems[0].parse_x509().unwrap();
+        let issuer = pems[1].parse_x509().unwrap();
+        let issuer_key = signature::UnparsedPublicKey::new(
+            &signature::ECDSA_P256_SHA256_ASN1,
+            issuer.public_key().subject_public_key.data.as_ref(),
+        );
+        assert!(
+            issuer_key
+                .verify(
+                    leaf.tbs_certificate.as_ref(),
+                    leaf.signature_value.data.as_ref()
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_dev_hostnames() {
+        assert_eq!(dev_hostnames("0.0.0.0").len(), 3);
+        assert_eq!(dev_hostnames("127.0.0.1").len(), 3);
+        assert_eq!(dev_hostnames("dev.local")[3], "dev.local");
+    }
+
+    #[test]
+    fn test_https_location() {
+        assert_eq!(
+            https_location("example.com", 443, "/blog/?page=2"),
+            "https://example.com/blog/?page=2"
+        );
+        assert_eq!(
+            https_location("localhost", 8443, "/"),
+            "https://localhost:8443/"
+        );
+        assert_eq!(https_location("::1", 8443, "/"), "https://[::1]:8443/");
+    }
+}
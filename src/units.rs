@@ -0,0 +1,152 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Seconds in each duration unit, largest first
+const UNITS: [(&str, u64); 5] = [
+    ("w", 7 * 24 * 60 * 60),
+    ("d", 24 * 60 * 60),
+    ("h", 60 * 60),
+    ("m", 60),
+    ("s", 1),
+];
+
+/// Parse a duration such as `3600`, `90s`, `30m`, `24h`, `7d` or `2w`.
+/// A bare number is seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let scale = match unit.trim() {
+        "" => 1,
+        unit => UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, scale)| *scale)
+            .ok_or_else(|| format!("unknown unit in duration '{}'", text))?,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}'", text))
+}
+
+/// A configured length of time, written as whole seconds or as a string
+/// with a unit such as `"90s"`, `"5m"` or `"1h"`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeSpan(pub Duration);
+
+impl TimeSpan {
+    pub fn from_secs(secs: u64) -> Self {
+        TimeSpan(Duration::from_secs(secs))
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0.as_secs()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+}
+
+impl FromStr for TimeSpan {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        parse_duration(text).map(TimeSpan)
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.as_secs();
+        let (name, scale) = UNITS
+            .iter()
+            .find(|(_, scale)| secs >= *scale && secs.is_multiple_of(*scale))
+            .unwrap_or(&("s", 1));
+        write!(f, "{}{}", secs / scale, name)
+    }
+}
+
+impl Serialize for TimeSpan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeSpan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimeSpanVisitor;
+
+        impl Visitor<'_> for TimeSpanVisitor {
+            type Value = TimeSpan;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("seconds, or a duration such as \"90s\", \"5m\" or \"1h\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, secs: u64) -> Result<TimeSpan, E> {
+                Ok(TimeSpan::from_secs(secs))
+            }
+
+            fn visit_i64<E: de::Error>(self, secs: i64) -> Result<TimeSpan, E> {
+                u64::try_from(secs)
+                    .map(TimeSpan::from_secs)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(secs), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<TimeSpan, E> {
+                text.parse()
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(text), &self))
+            }
+        }
+
+        deserializer.deserialize_any(TimeSpanVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        let secs = |text| parse_duration(text).map(|duration| duration.as_secs());
+        assert_eq!(secs("3600"), Ok(3600));
+        assert_eq!(secs("90s"), Ok(90));
+        assert_eq!(secs("30m"), Ok(1800));
+        assert_eq!(secs("24h"), Ok(86400));
+        assert_eq!(secs("7d"), Ok(604800));
+        assert_eq!(secs("0"), Ok(0));
+        assert!(secs("h").is_err());
+        assert!(secs("ten days").is_err());
+        assert!(secs("5 fortnights").is_err());
+    }
+
+    #[test]
+    fn test_time_span_round_trip() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Settings {
+            timeout: TimeSpan,
+        }
+
+        let parsed: Settings = toml::from_str("timeout = 30").unwrap();
+        assert_eq!(parsed.timeout, TimeSpan::from_secs(30));
+        let parsed: Settings = toml::from_str("timeout = \"2m\"").unwrap();
+        assert_eq!(parsed.timeout, TimeSpan::from_secs(120));
+        assert_eq!(toml::to_string(&parsed).unwrap().trim(), "timeout = \"2m\"");
+        assert_eq!(TimeSpan::from_secs(90).to_string(), "90s");
+        assert_eq!(TimeSpan::from_secs(0).to_string(), "0s");
+
+        let error = toml::from_str::<Settings>("timeout = \"ten megs\"").unwrap_err();
+        assert!(error.to_string().contains("ten megs"));
+        assert!(toml::from_str::<Settings>("timeout = -5").is_err());
+    }
+}
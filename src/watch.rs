@@ -0,0 +1,158 @@
+use crate::log;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::path::{Component, Path};
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::broadcast;
+
+/// Number of pending changes a slow subscriber may fall behind by
+const CHANNEL_CAPACITY: usize = 256;
+
+/// A change to a file under the webroot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileChange {
+    /// URL path of the changed file, e.g. `/blog/post-1.md`
+    pub path: String,
+}
+
+/// Broadcast channel for webroot changes, created when the watcher starts
+static WEBROOT_CHANGES: OnceLock<broadcast::Sender<FileChange>> = OnceLock::new();
+
+/// The running webroot watcher, kept alive for the lifetime of the process
+static WEBROOT_WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+
+/// Start watching the webroot for file changes (does nothing if already watching)
+pub fn watch_webroot(webroot: &Path) -> Result<(), notify::Error> {
+    let mut running = WEBROOT_WATCHER.lock().unwrap();
+    if running.is_some() {
+        return Ok(());
+    }
+
+    let root = webroot.canonicalize()?;
+    let sender = WEBROOT_CHANGES
+        .get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .clone();
+
+    let watch_root = root.clone();
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<Event>| match result {
+            Ok(event) => {
+                if !is_content_change(&event.kind) {
+                    return;
+                }
+                for path in &event.paths {
+                    if let Some(url_path) = url_path(&watch_root, path) {
+                        // Sending only fails when nobody is subscribed
+                        let _ = sender.send(FileChange { path: url_path });
+                    }
+                }
+            }
+            Err(e) => log::log_warning(&format!("Webroot watcher error: {}", e)),
+        })?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+
+    log::log_barleywine(&format!("Watching webroot for changes: {}", root.display()));
+    *running = Some(watcher);
+    Ok(())
+}
+
+/// Watch a single file, calling `on_change` whenever it is written, created or
+/// replaced. The file's directory is watched so editors that save by renaming
+/// a temporary file over the original are picked up. The returned watcher
+/// stops when dropped.
+pub fn watch_file<F>(path: &Path, on_change: F) -> Result<RecommendedWatcher, notify::Error>
+where
+    F: Fn() + Send + 'static,
+{
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_os_string())
+        .ok_or_else(|| notify::Error::generic("path has no file name"))?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<Event>| match result {
+            Ok(event) => {
+                let touches_file = event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == Some(file_name.as_os_str()));
+                if touches_file && is_content_change(&event.kind) {
+                    on_change();
+                }
+            }
+            Err(e) => log::log_warning(&format!("File watcher error: {}", e)),
+        })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
+
+/// Subscribe to webroot changes, if the watcher is running
+pub fn subscribe() -> Option<broadcast::Receiver<FileChange>> {
+    WEBROOT_CHANGES.get().map(|sender| sender.subscribe())
+}
+
+/// Whether an event changes file contents or names (as opposed to access times)
+fn is_content_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        EventKind::Modify(_) => true,
+        _ => false,
+    }
+}
+
+/// Convert a changed file's path into its URL path under the webroot
+fn url_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let segments: Vec<String> = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(segment) => Some(segment.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    if segments.is_empty() {
+        return None;
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind};
+
+    #[test]
+    fn test_url_path() {
+        let root = Path::new("/srv/webroot");
+        assert_eq!(
+            url_path(root, Path::new("/srv/webroot/blog/post-1.md")),
+            Some("/blog/post-1.md".to_string())
+        );
+        assert_eq!(
+            url_path(root, Path::new("/srv/webroot/styles.css")),
+            Some("/styles.css".to_string())
+        );
+        assert_eq!(url_path(root, Path::new("/srv/webroot")), None);
+        assert_eq!(url_path(root, Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn test_is_content_change() {
+        assert!(is_content_change(&EventKind::Create(CreateKind::File)));
+        assert!(is_content_change(&EventKind::Modify(ModifyKind::Data(
+            DataChange::Content
+        ))));
+        assert!(!is_content_change(&EventKind::Modify(
+            ModifyKind::Metadata(MetadataKind::AccessTime)
+        )));
+        assert!(!is_content_change(&EventKind::Access(AccessKind::Read)));
+    }
+}
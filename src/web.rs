@@ -1,200 +1,482 @@
-use crate::{config, log};
-use rocket::fs::NamedFile;
+use crate::auth::{self, BasicAuth};
+use crate::headers::{self, CspNonce};
+use crate::hotlink::{self, Hotlink};
+use crate::ipfilter::IpAccess;
+use crate::mtls::ClientIdentity;
+use crate::proxy::{self, RequireHttps};
+use crate::ratelimit::RateLimit;
+use crate::session::{self, SessionUser};
+use crate::signed::SignedUrl;
+use crate::{
+    collection, config, cors, feed, frontmatter, health, listener, livereload, log, metrics,
+    ratelimit, render, search, sitemap, symlinks, tls, watch,
+};
+use rocket::figment::Figment;
 use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Status};
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome};
 use rocket::response::{content::RawHtml, status::NotFound};
+use rocket::shield::Shield;
+use rocket::tokio::fs::File;
 use rocket::{get, routes};
-use std::fs;
+use serde::Serialize;
+use std::io::Read;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 // Custom response type to handle both static files and generated HTML
 pub enum FileResponse {
-    Static(NamedFile),
-    Markdown(RawHtml<String>),
+    /// A file opened under the symlink policy, with the type its extension implies
+    Static(std::fs::File, Option<ContentType>),
+    /// Rendered markdown, with the CSP nonce its inline elements carry
+    Markdown(RawHtml<String>, Option<String>),
+    Html(RawHtml<String>, Option<String>),
+    /// A document built on request, such as a feed
+    Generated(ContentType, String),
 }
 
 impl<'r> rocket::response::Responder<'r, 'static> for FileResponse {
     fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
         match self {
-            FileResponse::Static(file) => file.respond_to(request),
-            FileResponse::Markdown(html) => html.respond_to(request),
+            FileResponse::Static(file, content_type) => {
+                let mut response = File::from_std(file).respond_to(request)?;
+                if let Some(content_type) = content_type {
+                    response.set_header(content_type);
+                }
+                Ok(response)
+            }
+            FileResponse::Markdown(html, nonce) | FileResponse::Html(html, nonce) => {
+                // Let the security headers fairing allow this page's inline elements
+                request.local_cache(|| CspNonce(nonce));
+                html.respond_to(request)
+            }
+            FileResponse::Generated(content_type, body) => (content_type, body).respond_to(request),
         }
     }
 }
 
-#[get("/<file..>")]
-async fn files(
-    file: PathBuf,
-    remote_addr: Option<SocketAddr>,
-    origin: &Origin<'_>,
-) -> Result<FileResponse, NotFound<String>> {
-    let config = config::get_config();
-    let mut path = config.content.webroot.join(&file);
+/// Outcome of mapping a request path onto the webroot
+#[derive(Debug, Clone, Serialize)]
+pub struct Resolution {
+    /// File on disk that would be served
+    pub path: Option<PathBuf>,
+    /// How the file is served: "markdown" or "static"
+    pub handling: Option<&'static str>,
+    /// Why the request can't be served
+    pub error: Option<String>,
+    /// Listing page of a collection index, for `<collection>/page/<n>`
+    pub page: Option<usize>,
+    /// Step-by-step explanation of how the path was resolved
+    pub steps: Vec<String>,
+}
+
+impl Resolution {
+    fn fail(mut self, error: String) -> Self {
+        self.steps.push(error.clone());
+        self.error = Some(error);
+        self
+    }
+}
+
+/// Resolve a request path (relative to the webroot) to the file that would be served
+pub fn resolve_path(config: &config::Config, file: &Path) -> Resolution {
+    let mut resolution = Resolution {
+        path: None,
+        handling: None,
+        error: None,
+        page: None,
+        steps: Vec::new(),
+    };
+    let mut path = config.content.webroot.join(file);
+    resolution.steps.push(format!(
+        "Joined webroot with request path: {}",
+        path.display()
+    ));
+
+    // The sitemap and robots.txt are generated unless the site has its own
+    if !path.exists()
+        && let Some(document) = sitemap::request(file)
+    {
+        resolution
+            .steps
+            .push(format!("Generated {} for the site", file.display()));
+        if config.template.site_url.is_none() && document != sitemap::Document::Robots {
+            return resolution.fail(format!(
+                "{} needs template.site_url to be set",
+                file.display()
+            ));
+        }
+        resolution.handling = Some(document.handling());
+        if let sitemap::Document::Sitemap(part) = document {
+            resolution.page = part;
+        }
+        resolution.path = Some(config.content.webroot.clone());
+        return resolution;
+    }
+
+    // Feeds of a collection are generated
+    if !path.exists()
+        && let Some((collection, format)) = feed::request(config, file)
+    {
+        resolution.steps.push(format!(
+            "{} feed of the collection at {}",
+            format.handling(),
+            collection.url
+        ));
+        if config.template.site_url.is_none() {
+            return resolution.fail("Feeds need template.site_url to be set".to_string());
+        }
+        resolution.handling = Some(format.handling());
+        resolution.path = Some(collection.dir);
+        return resolution;
+    }
+
+    // Later listing pages of a collection are served by its index
+    if !path.exists()
+        && let Some((collection, page)) = collection::page_request(config, file)
+    {
+        resolution.steps.push(format!(
+            "Listing page {} of the collection at {}",
+            page, collection.url
+        ));
+        resolution.page = Some(page);
+        path = collection.dir;
+    }
 
     // If the path is a directory, try to serve configured index files
     if path.is_dir() {
-        let mut found_index = false;
-        for index_file in &config.content.index_files {
-            let index_path = path.join(index_file);
-            if index_path.exists() {
-                path = index_path;
-                found_index = true;
-                break;
+        resolution.steps.push(format!(
+            "Path is a directory, looking for index files {:?}",
+            config.content.index_files
+        ));
+        let root = file.components().count() == 0;
+        let index = config.content.index_files.iter().find(|index_file| {
+            // The site root never serves raw markdown as its index
+            let skipped = root && !config.content.markdown_enabled && index_file.ends_with(".md");
+            !skipped && path.join(index_file).exists()
+        });
+
+        match index {
+            Some(index_file) => {
+                path = path.join(index_file);
+                resolution
+                    .steps
+                    .push(format!("Using index file: {}", path.display()));
+            }
+            None if root => {
+                return resolution.fail(format!(
+                    "No index file found. Looking for: {:?}",
+                    config.content.index_files
+                ));
+            }
+            None => {
+                return resolution.fail(format!(
+                    "No index file found in directory: {}. Looking for: {:?}",
+                    path.display(),
+                    config.content.index_files
+                ));
             }
         }
+    }
 
-        if !found_index {
-            return Err(NotFound(format!(
-                "No index file found in directory: {}. Looking for: {:?}",
-                path.display(),
-                config.content.index_files
-            )));
+    // Check if the file exists
+    if !path.exists() {
+        return resolution.fail(format!("File not found: {}", path.display()));
+    }
+
+    // Keep symbolic links within what the policy allows
+    let policy = config.security.symlinks;
+    if let Err(reason) = symlinks::check(&config.content.webroot, &path, policy) {
+        log::log_warning(&format!(
+            "Symlink policy refused {}: {}",
+            path.display(),
+            reason
+        ));
+        return resolution.fail(format!("Refused by symlink policy: {}", reason));
+    }
+    if policy != config::SymlinkPolicy::Follow {
+        resolution
+            .steps
+            .push("Path is permitted by the symlink policy".to_string());
+    }
+
+    // Check extension rules
+    if let Some(blocked) = blocked_component(config, &path) {
+        return resolution.fail(format!(
+            "Blocked by security extension rules: {} ({})",
+            path.display(),
+            blocked
+        ));
+    }
+    resolution
+        .steps
+        .push("File extension is allowed by security rules".to_string());
+
+    // Handle markdown files (if enabled)
+    let is_markdown = path.extension().is_some_and(|extension| extension == "md");
+    if is_markdown && config.content.markdown_enabled {
+        resolution
+            .steps
+            .push("Markdown file will be rendered to HTML".to_string());
+        resolution.handling = Some("markdown");
+    } else {
+        if is_markdown {
+            resolution
+                .steps
+                .push("Markdown is disabled, serving the raw file".to_string());
         }
+        resolution.handling = Some("static");
     }
 
-    // If no file segments provided, try to serve configured index files from webroot
-    if file.components().count() == 0 {
-        let mut found_index = false;
-        for index_file in &config.content.index_files {
-            let index_path = config.content.webroot.join(index_file);
-            if index_path.exists() {
-                path = index_path;
-                found_index = true;
-                break;
+    resolution.path = Some(path);
+    resolution
+}
+
+/// Find the first path component rejected by the configured extension rules
+pub fn blocked_component(config: &config::Config, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(&config.content.webroot).unwrap_or(path);
+    relative.components().find_map(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        // Dotfiles such as `.env` are matched by their full name
+        let extension = if name.starts_with('.') {
+            name.to_string()
+        } else {
+            match Path::new(name.as_ref()).extension() {
+                Some(extension) => format!(".{}", extension.to_string_lossy()),
+                None => return None,
             }
-        }
+        };
+        (!config.is_extension_allowed(&extension)).then_some(extension)
+    })
+}
 
-        if !found_index {
-            return Err(NotFound(format!(
-                "No index file found. Looking for: {:?}",
-                config.content.index_files
-            )));
+/// JSON response that honours `development.pretty_json`
+pub struct JsonResponse<T>(pub T);
+
+impl<'r, T: Serialize> rocket::response::Responder<'r, 'static> for JsonResponse<T> {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let body = if config::get_config().development.pretty_json {
+            serde_json::to_string_pretty(&self.0)
+        } else {
+            serde_json::to_string(&self.0)
         }
+        .map_err(|_| Status::InternalServerError)?;
+
+        (ContentType::JSON, body).respond_to(request)
     }
+}
 
-    // Check if the file exists
-    if !path.exists() {
-        // Log access attempt for non-existent file
-        let addr_str = remote_addr
-            .map(|addr| addr.to_string())
-            .unwrap_or_else(|| "unknown".to_string());
-        log::log_access(&addr_str, "GET", origin.path().as_str(), 404, None);
+/// The access checks every served file passes, in order: the HTTPS
+/// redirect for proxied plain HTTP, IP rules, rate limits, hotlink rules,
+/// signed URLs, client certificates, Basic authentication and login sessions.
+/// Holds the authenticated user, if any check produced one.
+pub struct Access {
+    pub user: Option<String>,
+}
 
-        return Err(NotFound(format!("File not found: {}", path.display())));
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Access {
+    type Error = String;
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, String> {
+        try_outcome!(request.guard::<RequireHttps>().await);
+        try_outcome!(request.guard::<IpAccess>().await);
+        try_outcome!(request.guard::<RateLimit>().await);
+        try_outcome!(request.guard::<Hotlink>().await);
+        try_outcome!(request.guard::<SignedUrl>().await);
+        let identity = try_outcome!(request.guard::<ClientIdentity>().await);
+        let basic = try_outcome!(request.guard::<BasicAuth>().await);
+        let session = try_outcome!(request.guard::<SessionUser>().await);
+        Outcome::Success(Access {
+            user: basic.0.or(session.0).or(identity.0),
+        })
     }
+}
 
-    // Log successful access
+#[get("/<file..>")]
+async fn files(
+    file: PathBuf,
+    remote_addr: Option<SocketAddr>,
+    origin: &Origin<'_>,
+    access: Access,
+) -> Result<FileResponse, NotFound<String>> {
+    let config = config::get_config();
     let addr_str = remote_addr
         .map(|addr| addr.to_string())
         .unwrap_or_else(|| "unknown".to_string());
-    log::log_access(&addr_str, "GET", origin.path().as_str(), 200, None);
+    let user = access.user.as_deref();
+
+    let resolution = resolve_path(&config, &file);
+    let path = match (resolution.path, resolution.error) {
+        (Some(path), None) => path,
+        (_, error) => {
+            // Log access attempt for a path that can't be served
+            log::log_access(&addr_str, "GET", origin.path().as_str(), 404, None, user);
+            return Err(NotFound(error.unwrap_or_default()));
+        }
+    };
 
-    // Handle markdown files (if enabled)
-    if config.content.markdown_enabled {
-        if let Some(extension) = path.extension() {
-            if extension == "md" {
-                log::log_file_served(&path.display().to_string(), "markdown");
-                return serve_markdown_file(&path).await;
-            }
+    // Log successful access
+    log::log_access(&addr_str, "GET", origin.path().as_str(), 200, None, user);
+
+    if let Some(format) = resolution.handling.and_then(feed::Format::from_handling) {
+        log::log_file_served(&path.display().to_string(), format.handling());
+        return feed::document(&config, &path, format)
+            .map(|body| FileResponse::Generated(format.content_type(), body))
+            .ok_or_else(|| NotFound(format!("No feed for {}", origin.path())));
+    }
+
+    match resolution.handling {
+        Some("robots") => {
+            log::log_file_served(&path.display().to_string(), "robots");
+            return Ok(FileResponse::Generated(
+                ContentType::Plain,
+                sitemap::robots(&config),
+            ));
         }
+        Some("sitemap") => {
+            log::log_file_served(&path.display().to_string(), "sitemap");
+            return sitemap::sitemap(&config, resolution.page)
+                .map(|body| FileResponse::Generated(ContentType::XML, body))
+                .ok_or_else(|| NotFound(format!("No sitemap at {}", origin.path())));
+        }
+        _ => {}
+    }
+
+    if resolution.handling == Some("markdown") {
+        log::log_file_served(&path.display().to_string(), "markdown");
+        return serve_markdown_file(&config, &path, resolution.page).await;
+    }
+    if resolution.page.is_some() {
+        // Only markdown indexes carry a listing
+        return Err(NotFound(format!("No listing for {}", origin.path())));
+    }
+
+    // HTML pages carry the live reload script in development
+    let is_html = path
+        .extension()
+        .is_some_and(|extension| extension == "html" || extension == "htm");
+    if is_html && livereload::is_enabled(&config) {
+        return match read_to_string(&config, &path) {
+            Ok(html) => {
+                log::log_file_served(&path.display().to_string(), "html");
+                let nonce = headers::page_nonce(&config);
+                let html = livereload::inject_script(&html, nonce.as_deref());
+                Ok(FileResponse::Html(RawHtml(html), nonce))
+            }
+            Err(_) => {
+                log::log_access(&addr_str, "GET", origin.path().as_str(), 500, None, user);
+                Err(NotFound(format!("Could not open file: {}", path.display())))
+            }
+        };
     }
 
     // Serve regular files
-    match NamedFile::open(&path).await {
+    match symlinks::open(&config.content.webroot, &path, config.security.symlinks) {
         Ok(file) => {
             log::log_file_served(&path.display().to_string(), "static");
-            Ok(FileResponse::Static(file))
+            let content_type = path
+                .extension()
+                .and_then(|extension| ContentType::from_extension(&extension.to_string_lossy()));
+            Ok(FileResponse::Static(file, content_type))
         }
         Err(_) => {
             // Log access attempt for file that couldn't be opened
-            let addr_str = remote_addr
-                .map(|addr| addr.to_string())
-                .unwrap_or_else(|| "unknown".to_string());
-            log::log_access(&addr_str, "GET", origin.path().as_str(), 500, None);
+            log::log_access(&addr_str, "GET", origin.path().as_str(), 500, None, user);
             Err(NotFound(format!("Could not open file: {}", path.display())))
         }
     }
 }
 
 #[get("/")]
-async fn index(remote_addr: Option<SocketAddr>) -> Result<FileResponse, NotFound<String>> {
-    let config = config::get_config();
-
-    // Log access to root
-    let addr_str = remote_addr
-        .map(|addr| addr.to_string())
-        .unwrap_or_else(|| "unknown".to_string());
-
-    // Try each configured index file in order
-    for index_file in &config.content.index_files {
-        let index_path = config.content.webroot.join(index_file);
-
-        if index_path.exists() {
-            log::log_access(&addr_str, "GET", "/", 200, None);
-
-            // Check if it's a markdown file and markdown is enabled
-            if config.content.markdown_enabled && index_file.ends_with(".md") {
-                log::log_file_served(index_file, "markdown");
-                return serve_markdown_file(&index_path).await;
-            } else if !index_file.ends_with(".md") {
-                // Serve as static file
-                match NamedFile::open(&index_path).await {
-                    Ok(file) => {
-                        log::log_file_served(index_file, "static");
-                        return Ok(FileResponse::Static(file));
-                    }
-                    Err(_) => {
-                        log::log_access(&addr_str, "GET", "/", 500, None);
-                        return Err(NotFound(format!("Could not open {}", index_file)));
-                    }
-                }
-            }
-        }
-    }
+async fn index(
+    remote_addr: Option<SocketAddr>,
+    origin: &Origin<'_>,
+    access: Access,
+) -> Result<FileResponse, NotFound<String>> {
+    // The site root resolves through the configured index files
+    files(PathBuf::new(), remote_addr, origin, access).await
+}
 
-    // No index file found
-    log::log_access(&addr_str, "GET", "/", 404, None);
-    Err(NotFound(format!(
-        "No index file found. Looking for: {:?}",
-        config.content.index_files
-    )))
+/// Read a file opened under the symlink policy
+pub fn read_to_string(config: &config::Config, path: &Path) -> std::io::Result<String> {
+    let mut file = symlinks::open(&config.content.webroot, path, config.security.symlinks)?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    Ok(content)
 }
 
-async fn serve_markdown_file(path: &Path) -> Result<FileResponse, NotFound<String>> {
+async fn serve_markdown_file(
+    config: &config::Config,
+    path: &Path,
+    page: Option<usize>,
+) -> Result<FileResponse, NotFound<String>> {
     // Read the markdown file
-    let markdown_content = match fs::read_to_string(path) {
+    let markdown_content = match read_to_string(config, path) {
         Ok(content) => content,
         Err(_) => {
             return Err(NotFound(format!(
                 "Could not read markdown file: {}",
                 path.display()
-            )))
+            )));
         }
     };
+    let (front_matter, body) = frontmatter::split(&markdown_content);
 
     // Convert markdown to HTML
-    let html_content = markdown::to_html(&markdown_content);
+    let mut html_content = render::markdown_to_html(config, body, &path.display().to_string());
+
+    // A collection's index lists its pages below its own content
+    let is_index = path.file_name().is_some_and(|name| {
+        config
+            .content
+            .index_files
+            .iter()
+            .any(|index| name == index.as_str())
+    });
+    let collection = path.parent().and_then(|dir| collection::find(config, dir));
+    match (collection.as_ref().filter(|_| is_index), page) {
+        (Some(collection), page) => {
+            let entries = collection::entries(config, collection);
+            let page = page.unwrap_or(1);
+            match collection::listing(collection, &entries, page) {
+                Some(listing) => html_content.push_str(&listing),
+                None => {
+                    return Err(NotFound(format!("No page {} in {}", page, collection.url)));
+                }
+            }
+        }
+        (None, Some(page)) => {
+            return Err(NotFound(format!("No listing page {}", page)));
+        }
+        (None, None) => {}
+    }
 
-    // Extract title from the first # heading if present
-    let title = extract_title(&markdown_content);
+    // The front matter title wins over the first # heading
+    let title = front_matter
+        .title
+        .or_else(|| render::title(body))
+        .unwrap_or_else(|| "Untitled".to_string());
 
     // Wrap in HTML template
-    let full_html = create_html_template(&html_content, &title);
-
-    Ok(FileResponse::Markdown(RawHtml(full_html)))
-}
-
-fn extract_title(markdown: &str) -> String {
-    for line in markdown.lines() {
-        let trimmed = line.trim();
-        if trimmed.starts_with("# ") {
-            return trimmed[2..].trim().to_string();
-        }
+    let nonce = headers::page_nonce(config);
+    let mut full_html = create_html_template(&html_content, &title, nonce.as_deref());
+    // Pages of a collection advertise its feeds
+    if let Some(ref collection) = collection
+        && config.template.site_url.is_some()
+    {
+        full_html = feed::inject_links(&full_html, collection);
+    }
+    if livereload::is_enabled(config) {
+        full_html = livereload::inject_script(&full_html, nonce.as_deref());
     }
-    "Untitled".to_string()
+
+    Ok(FileResponse::Markdown(RawHtml(full_html), nonce))
 }
 
-fn create_html_template(content: &str, title: &str) -> String {
+pub fn create_html_template(content: &str, title: &str, nonce: Option<&str>) -> String {
+    let nonce = headers::nonce_attribute(nonce);
     format!(
         r#"<!DOCTYPE html>
 <html lang="en">
@@ -202,7 +484,7 @@ fn create_html_template(content: &str, title: &str) -> String {
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
     <title>{}</title>
-    <style>
+    <style{nonce}>
         body {{
             font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', 'Roboto', 'Oxygen', 'Ubuntu', 'Cantarell', sans-serif;
             line-height: 1.6;
@@ -326,6 +608,71 @@ fn create_html_template(content: &str, title: &str) -> String {
             color: #3498db;
         }}
 
+        .collection-entry h2 {{
+            border-bottom: none;
+            margin-bottom: 5px;
+        }}
+
+        .collection-meta {{
+            color: #7f8c8d;
+            font-size: 0.9em;
+            margin-top: 0;
+        }}
+
+        .pagination {{
+            display: flex;
+            justify-content: space-between;
+            border-top: 1px solid #ecf0f1;
+            padding-top: 15px;
+            margin-top: 30px;
+        }}
+
+        .search-form {{
+            display: flex;
+            gap: 8px;
+            margin-bottom: 20px;
+        }}
+
+        .search-form input {{
+            flex: 1;
+            padding: 8px;
+            font-size: 1em;
+        }}
+
+        .search-summary {{
+            color: #7f8c8d;
+        }}
+
+        .search-results li {{
+            margin-bottom: 15px;
+        }}
+
+        .search-results p {{
+            margin: 4px 0 0;
+        }}
+
+        math[display="block"] {{
+            margin: 1em 0;
+            overflow-x: auto;
+        }}
+
+        .math-error {{
+            color: #c0392b;
+            background: #fdedec;
+            border: 1px solid #e6b0aa;
+        }}
+
+        .math-error-marker {{
+            font-weight: bold;
+        }}
+
+        .callout {{
+            background-color: #f0f8ff;
+            padding: 15px;
+            border-radius: 5px;
+            border-left: 5px solid #0066cc;
+        }}
+
         @media (max-width: 768px) {{
             body {{
                 padding: 15px;
@@ -351,7 +698,7 @@ fn create_html_template(content: &str, title: &str) -> String {
         {}
     </div>
 
-    <script>
+    <script{nonce}>
         // Add some interactivity for better UX
         document.addEventListener('DOMContentLoaded', function() {{
             // Add anchor links to headings
@@ -403,10 +750,182 @@ fn create_html_template(content: &str, title: &str) -> String {
     </script>
 </body>
 </html>"#,
-        title, content
+        render::escape_html(title),
+        content
     )
 }
 
+/// Write the access log entry for a request a guard turned away
+pub fn log_denied(request: &rocket::Request<'_>, status: Status, user: Option<&str>) {
+    let addr_str = request
+        .remote()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    log::log_access(
+        &addr_str,
+        request.method().as_str(),
+        request.uri().path().as_str(),
+        status.code,
+        None,
+        user,
+    );
+}
+
+/// Rocket configuration: Rocket.toml overlaid with settings from barleywine.toml
+pub fn figment(config: &config::Config) -> Figment {
+    let mut figment = rocket::Config::figment()
+        .merge(("address", config.server.host.as_str()))
+        .merge(("port", config.server.port))
+        .merge(("shutdown.ctrlc", config.shutdown.ctrlc))
+        .merge(("shutdown.signals", &config.shutdown.signals))
+        .merge(("shutdown.grace", config.shutdown.grace))
+        .merge(("shutdown.mercy", config.shutdown.mercy))
+        .merge(("keep_alive", config.server.keep_alive.as_secs() as u32))
+        .merge(("limits.form", config.limits.forms))
+        .merge(("limits.json", config.limits.json))
+        .merge(("limits.file", config.limits.file))
+        .merge(("limits.data-form", config.limits.data))
+        .merge(("limits.string", config.limits.data))
+        .merge(("limits.bytes", config.limits.data))
+        // Client addresses come from [server.proxy], never from a header
+        // anyone can send
+        .merge(("ip_header", false));
+
+    if listener::is_guarding() {
+        // The connection guard owns the public port and relays to whichever
+        // loopback port Rocket binds, as reported by listener::InternalPort
+        figment = figment.merge(("address", "127.0.0.1")).merge(("port", 0));
+    }
+
+    if let Some(ref tls_config) = config.server.tls {
+        // Validation has already rejected unusable cipher settings
+        let ciphers = tls::effective_ciphers(tls_config).unwrap_or_default();
+        figment = figment
+            .merge(("tls.certs", &tls_config.certs))
+            .merge(("tls.key", &tls_config.key))
+            .merge(("tls.ciphers", ciphers))
+            .merge((
+                "tls.prefer_server_cipher_order",
+                tls_config.prefer_server_cipher_order,
+            ));
+        if let Some(ref client_ca) = tls_config.client_ca {
+            // Client certificates are optional at the TLS layer; protected
+            // paths require them through the ClientIdentity guard
+            figment = figment
+                .merge(("tls.mutual.ca_certs", client_ca))
+                .merge(("tls.mutual.mandatory", false));
+        }
+    }
+    figment
+}
+
 pub fn build_rocket() -> rocket::Rocket<rocket::Build> {
-    rocket::build().mount("/", routes![index, files])
+    let config = config::get_config();
+    let mut rocket = rocket::custom(figment(&config))
+        .attach(listener::InternalPort)
+        .attach(proxy::ClientAddress)
+        .attach(metrics::Metrics)
+        .attach(ratelimit::RateLimiter)
+        // An empty shield replaces Rocket's defaults so [security.headers] decides
+        .attach(Shield::new())
+        .attach(headers::SecurityHeaders)
+        .attach(cors::Cors)
+        .attach(auth::AuthChallenge)
+        .attach(hotlink::HotlinkReplacement)
+        .mount("/", routes![index, files])
+        .mount("/", cors::routes())
+        .mount(config.internal.prefix.as_str(), health::routes())
+        .mount(config.internal.prefix.as_str(), metrics::routes())
+        .mount(config.internal.prefix.as_str(), session::routes());
+
+    if config.search.enabled {
+        rocket = rocket.mount(config.search.path.as_str(), search::routes());
+    }
+
+    if livereload::is_enabled(&config) {
+        match watch::watch_webroot(&config.content.webroot) {
+            Ok(()) => {
+                rocket = rocket.mount(config.internal.prefix.as_str(), livereload::routes());
+            }
+            Err(e) => log::log_warning(&format!(
+                "Live reload disabled, could not watch webroot: {}",
+                e
+            )),
+        }
+    }
+
+    rocket
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_config(name: &str) -> config::Config {
+        let webroot = std::env::temp_dir().join(name);
+        fs::create_dir_all(webroot.join("docs")).unwrap();
+        fs::write(webroot.join("index.md"), "# Home").unwrap();
+        fs::write(webroot.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+        fs::write(webroot.join(".env"), "SECRET=1").unwrap();
+
+        let mut config = config::Config::default();
+        config.content.webroot = webroot;
+        config
+    }
+
+    #[test]
+    fn test_resolve_index_files() {
+        let config = test_config("barleywine_test_resolve_index");
+
+        let root = resolve_path(&config, Path::new(""));
+        assert_eq!(root.path, Some(config.content.webroot.join("index.md")));
+        assert_eq!(root.handling, Some("markdown"));
+
+        let docs = resolve_path(&config, Path::new("docs"));
+        assert_eq!(
+            docs.path,
+            Some(config.content.webroot.join("docs/index.html"))
+        );
+        assert_eq!(docs.handling, Some("static"));
+
+        fs::remove_dir_all(&config.content.webroot).ok();
+    }
+
+    #[test]
+    fn test_resolve_rejects_missing_and_blocked_files() {
+        let config = test_config("barleywine_test_resolve_blocked");
+
+        let missing = resolve_path(&config, Path::new("missing.html"));
+        assert!(missing.path.is_none());
+        assert!(missing.error.unwrap().contains("File not found"));
+
+        let blocked = resolve_path(&config, Path::new(".env"));
+        assert!(blocked.path.is_none());
+        assert!(blocked.error.unwrap().contains("Blocked"));
+
+        fs::remove_dir_all(&config.content.webroot).ok();
+    }
+
+    #[test]
+    fn test_resolve_generated_documents_without_site_url() {
+        let config = test_config("barleywine_test_resolve_generated");
+        assert!(config.template.site_url.is_none());
+
+        let robots = resolve_path(&config, Path::new("robots.txt"));
+        assert_eq!(robots.handling, Some("robots"));
+        assert!(robots.error.is_none());
+
+        let sitemap = resolve_path(&config, Path::new("sitemap.xml"));
+        assert!(sitemap.error.unwrap().contains("template.site_url"));
+
+        fs::remove_dir_all(&config.content.webroot).ok();
+    }
+
+    #[test]
+    fn test_template_escapes_title() {
+        let html = create_html_template("<p>Body</p>", "Tips & <script>alert(1)</script>", None);
+        assert!(html.contains("<title>Tips &amp; &lt;script&gt;alert(1)&lt;/script&gt;</title>"));
+        assert!(html.contains("<p>Body</p>"));
+    }
 }
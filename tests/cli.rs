@@ -1,6 +1,6 @@
 use assert_cmd::Command;
-use assert_fs::prelude::*;
 use assert_fs::TempDir;
+use assert_fs::prelude::*;
 use predicates::prelude::*;
 
 static VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -81,7 +81,7 @@ fn test_valid_log_level_error() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "error"])
+        .args(["--verify", "--loglevel", "error"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -95,7 +95,7 @@ fn test_valid_log_level_warn() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "warn"])
+        .args(["--verify", "--loglevel", "warn"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -109,7 +109,7 @@ fn test_valid_log_level_info() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "info"])
+        .args(["--verify", "--loglevel", "info"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -123,7 +123,7 @@ fn test_valid_log_level_debug() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "debug"])
+        .args(["--verify", "--loglevel", "debug"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -137,7 +137,7 @@ fn test_valid_log_level_trace() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "trace"])
+        .args(["--verify", "--loglevel", "trace"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -147,7 +147,7 @@ fn test_valid_log_level_trace() {
 #[test]
 fn test_invalid_log_level() {
     barleywine_cmd()
-        .args(&["--loglevel", "invalid"])
+        .args(["--loglevel", "invalid"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Invalid log level"));
@@ -160,7 +160,7 @@ fn test_log_level_case_insensitive_uppercase() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "INFO"])
+        .args(["--verify", "--loglevel", "INFO"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -174,7 +174,7 @@ fn test_log_level_case_insensitive_mixed() {
     webroot.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--verify", "--loglevel", "Debug"])
+        .args(["--verify", "--loglevel", "Debug"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -191,7 +191,7 @@ fn test_valid_config_file() {
     config_file.write_str("[server]\nport = 8000\n").unwrap();
 
     barleywine_cmd()
-        .args(&["--config", "config.toml", "--verify"])
+        .args(["--config", "config.toml", "--verify"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -208,7 +208,7 @@ fn test_config_file_short_form() {
     config_file.write_str("[server]\nport = 8000\n").unwrap();
 
     barleywine_cmd()
-        .args(&["-c", "config.toml", "--verify"])
+        .args(["-c", "config.toml", "--verify"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -218,7 +218,7 @@ fn test_config_file_short_form() {
 #[test]
 fn test_nonexistent_config_file() {
     barleywine_cmd()
-        .args(&["--config", "nonexistent.toml", "--verify"])
+        .args(["--config", "nonexistent.toml", "--verify"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("does not exist"));
@@ -234,7 +234,7 @@ fn test_valid_log_directory() {
     logs_dir.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&["--log", "logs", "--verify"])
+        .args(["--log", "logs", "--verify"])
         .current_dir(temp_dir.path())
         .assert()
         .success()
@@ -244,7 +244,7 @@ fn test_valid_log_directory() {
 #[test]
 fn test_log_directory_nonexistent() {
     barleywine_cmd()
-        .args(&["--log", "baddir"])
+        .args(["--log", "baddir"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("does not exist"));
@@ -263,7 +263,7 @@ fn test_multiple_valid_options() {
     logs_dir.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&[
+        .args([
             "--config",
             "config.toml",
             "--loglevel",
@@ -328,7 +328,7 @@ fn test_rocket_framework_detection() {
 #[test]
 fn test_empty_log_level_fails() {
     barleywine_cmd()
-        .args(&["--loglevel", "", "--verify"])
+        .args(["--loglevel", "", "--verify"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Invalid log level"));
@@ -377,7 +377,7 @@ fn test_absolute_config_path() {
     let absolute_path = config_file.path().to_str().unwrap();
 
     barleywine_cmd()
-        .args(&["--config", absolute_path, "--verify"])
+        .args(["--config", absolute_path, "--verify"])
         .assert()
         .success()
         .stdout(predicate::str::contains("Configuration is valid!"));
@@ -395,7 +395,7 @@ fn test_absolute_log_directory() {
     let absolute_path = logs_dir.path().to_str().unwrap();
 
     barleywine_cmd()
-        .args(&["--log", absolute_path, "--verify"])
+        .args(["--log", absolute_path, "--verify"])
         .assert()
         .success()
         .stdout(predicate::str::contains("Configuration is valid!"));
@@ -428,7 +428,7 @@ fn test_log_level_in_verify_output() {
 
     for level in &["error", "warn", "info", "debug", "trace"] {
         barleywine_cmd()
-            .args(&["--verify", "--loglevel", level])
+            .args(["--verify", "--loglevel", level])
             .current_dir(temp_dir.path())
             .assert()
             .success()
@@ -452,7 +452,7 @@ fn test_configuration_with_complex_paths() {
     nested_log_dir.create_dir_all().unwrap();
 
     barleywine_cmd()
-        .args(&[
+        .args([
             "--config",
             "config/environments/test.toml",
             "--log",
@@ -464,3 +464,25 @@ fn test_configuration_with_complex_paths() {
         .success()
         .stdout(predicate::str::contains("Configuration is valid!"));
 }
+
+#[test]
+fn test_sign_subcommand_without_key() {
+    let temp_dir = TempDir::new().unwrap();
+    temp_dir.child("webroot").create_dir_all().unwrap();
+
+    barleywine_cmd()
+        .args(["sign", "/private/report.pdf", "--ttl", "2h"])
+        .current_dir(temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No signing key is configured"));
+}
+
+#[test]
+fn test_sign_subcommand_help() {
+    barleywine_cmd()
+        .args(["sign", "--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("--ttl"));
+}
@@ -1,7 +1,7 @@
 use chrono::Utc;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
@@ -89,24 +89,19 @@ fn test_logging_system() {
     println!("🎉 Logging system test finished successfully!");
 }
 
-fn write_test_barleywine_log(log_dir: &PathBuf, message: &str) {
+fn write_test_barleywine_log(log_dir: &Path, message: &str) {
     let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
     let log_entry = format!("[{}] {}\n", timestamp, message);
 
     let log_file = log_dir.join("barleywine.log");
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(&log_file)
-    {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log_file) {
         let _ = file.write_all(log_entry.as_bytes());
         let _ = file.flush();
     }
 }
 
 fn write_test_access_log(
-    log_dir: &PathBuf,
+    log_dir: &Path,
     remote_addr: &str,
     method: &str,
     uri: &str,
@@ -121,12 +116,7 @@ fn write_test_access_log(
     );
 
     let log_file = log_dir.join("access.log");
-    if let Ok(mut file) = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(&log_file)
-    {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log_file) {
         let _ = file.write_all(log_entry.as_bytes());
         let _ = file.flush();
     }
@@ -143,7 +133,6 @@ fn test_log_file_creation() {
     let log_file = test_dir.join("test.log");
     let mut file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(&log_file)
         .expect("Failed to create log file");
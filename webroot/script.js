@@ -146,8 +146,11 @@ function createFileTypeDemo() {
         item.innerHTML = `
             <strong>${type.ext}</strong><br>
             <em>${type.mime}</em><br>
-            <a href="${type.example}" style="color: #66ccff; font-size: 10px;">${type.example}</a>
+            <a href="${type.example}">${type.example}</a>
         `;
+        const link = item.querySelector('a');
+        link.style.color = '#66ccff';
+        link.style.fontSize = '10px';
         demoSection.appendChild(item);
     });
 
@@ -182,18 +185,6 @@ function createFileTypeDemo() {
     }
 }
 
-// Add CSS animations for ripple effect
-const style = document.createElement('style');
-style.textContent = `
-    @keyframes ripple {
-        to {
-            transform: scale(4);
-            opacity: 0;
-        }
-    }
-`;
-document.head.appendChild(style);
-
 // Export functions for potential use in other scripts
 window.BarleywineUtils = {
     initializeInteractivity,